@@ -36,6 +36,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .into_inner();
 
     while let Some(disk) = stream.message().await? {
+        if disk.going_away {
+            println!("Server is shutting down");
+            break;
+        }
         dbg!(&disk);
     }
 
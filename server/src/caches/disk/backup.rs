@@ -0,0 +1,480 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use super::registry::now_secs;
+use super::{DiskCacheData, FileSandbox};
+use crate::config::BackupConfig;
+use crate::public::job::{JobId, JobToken};
+use crate::public::time::utc_datetime;
+use crate::public::{BackupRecord, BackupStatus};
+
+const STATE_FILE: &str = "backups.json";
+const PARTIAL_SUFFIX: &str = ".partial";
+/// A failed backup is retried after this many seconds, or after its
+/// interval if that is shorter.
+const RETRY_DELAY: u64 = 15 * 60;
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Stored {
+    backups: Vec<BackupConfig>,
+    records: BTreeMap<String, BackupRecord>,
+}
+
+/// Backups from the command line and those added at runtime, with the
+/// outcome of their last runs.
+#[derive(Debug, Default)]
+pub(super) struct Backups {
+    path: Option<PathBuf>,
+    configured: BTreeMap<String, BackupConfig>,
+    added: BTreeMap<String, BackupConfig>,
+    records: BTreeMap<String, BackupRecord>,
+    running: HashSet<String>,
+}
+
+impl Backups {
+    /// Load backups added at runtime and past results from `state_dir`.
+    /// Backups of `configured` take precedence over stored ones.
+    pub(super) fn load(state_dir: &str, configured: &[BackupConfig]) -> Self {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let stored = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<Stored>(&content).unwrap_or_else(|e| {
+                log::error!("Cannot parse {:?}, starting empty: {}", path, e);
+                Stored::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => {
+                log::error!("Cannot read {:?}, starting empty: {}", path, e);
+                Stored::default()
+            }
+        };
+
+        let configured = configured
+            .iter()
+            .map(|b| (b.name.clone(), b.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let added = stored
+            .backups
+            .into_iter()
+            .filter(|b| !configured.contains_key(&b.name))
+            .map(|b| (b.name.clone(), b))
+            .collect();
+
+        Self {
+            path: Some(path),
+            configured,
+            added,
+            records: stored.records,
+            running: HashSet::new(),
+        }
+    }
+
+    pub(super) fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let stored = Stored {
+            backups: self.added.values().cloned().collect(),
+            records: self.records.clone(),
+        };
+        let content = serde_json::to_string_pretty(&stored)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(super) fn get(&self, name: &str) -> Option<&BackupConfig> {
+        self.configured.get(name).or_else(|| self.added.get(name))
+    }
+
+    /// Add or replace a backup. Those from the command line cannot be changed.
+    pub(super) fn set(&mut self, backup: BackupConfig) -> Result<(), String> {
+        let backup = backup.complete()?;
+        if self.configured.contains_key(&backup.name) {
+            return Err(format!("{} is defined on the command line", backup.name));
+        }
+        self.added.insert(backup.name.clone(), backup);
+        Ok(())
+    }
+
+    pub(super) fn remove(&mut self, name: &str) -> Result<(), String> {
+        if self.configured.contains_key(name) {
+            return Err(format!("{} is defined on the command line", name));
+        }
+        self.added
+            .remove(name)
+            .ok_or_else(|| format!("Unknown backup {}", name))?;
+        self.records.remove(name);
+        Ok(())
+    }
+
+    pub(super) fn list(&self) -> Vec<BackupStatus> {
+        let configured = self.configured.values().map(|b| (b, true));
+        let added = self.added.values().map(|b| (b, false));
+        configured
+            .chain(added)
+            .map(|(backup, from_config)| BackupStatus {
+                backup: backup.clone(),
+                from_config,
+                running: self.running.contains(&backup.name),
+                record: self.records.get(&backup.name).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Scheduled backups whose destination is mounted and whose last
+    /// successful run is older than their interval. A backup missed while
+    /// its disk was away runs as soon as the disk is back.
+    pub(super) fn due(&self, now: u64, mounted: impl Fn(&str) -> bool) -> Vec<String> {
+        self.list()
+            .into_iter()
+            .filter(|status| {
+                let backup = &status.backup;
+                let record = &status.record;
+                let retry = RETRY_DELAY.min(backup.interval);
+                backup.interval > 0
+                    && !status.running
+                    && (record.last_success == 0 || now >= record.last_success + backup.interval)
+                    && (record.last_run == 0 || now >= record.last_run + retry)
+                    && mounted(&backup.target)
+            })
+            .map(|status| status.backup.name)
+            .collect()
+    }
+}
+
+pub(super) fn snapshot_name(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = utc_datetime(secs);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn is_snapshot_name(name: &str) -> bool {
+    name.len() == 19 && name.as_bytes()[10] == b'T' && !name.ends_with(PARTIAL_SUFFIX)
+}
+
+/// Finished snapshots in `base`, oldest first.
+fn snapshots(base: &Path) -> io::Result<Vec<String>> {
+    let mut names = fs::read_dir(base)?
+        .filter_map(|x| x.ok())
+        .filter_map(|x| x.file_name().into_string().ok())
+        .filter(|name| is_snapshot_name(name))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+enum Stop {
+    Cancelled,
+    Failed(io::Error),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Failed(e)
+    }
+}
+
+/// Copies a directory tree, hard linking files unchanged since the
+/// previous snapshot instead of copying them, like `rsync --link-dest`.
+struct Snapshot<'a> {
+    token: &'a JobToken,
+    device: u64,
+    exclude: &'a Path,
+    record: BackupRecord,
+}
+
+impl<'a> Snapshot<'a> {
+    fn copy_tree(&mut self, src: &Path, dst: &Path, prev: Option<&Path>) -> Result<(), Stop> {
+        let mut entries = fs::read_dir(src)?
+            .filter_map(|x| x.ok())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|x| x.file_name());
+
+        for entry in entries {
+            self.token.checkpoint().map_err(|_| Stop::Cancelled)?;
+            let name = entry.file_name();
+            let from = entry.path();
+            let to = dst.join(&name);
+            let prev = prev.map(|p| p.join(&name));
+            if let Err(e) = self.copy_entry(&from, &to, prev.as_deref()) {
+                match e {
+                    Stop::Cancelled => return Err(Stop::Cancelled),
+                    Stop::Failed(e) => {
+                        log::warn!("DiskCache - Backup of {:?} failed: {}", from, e);
+                        self.record.files_failed += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_entry(&mut self, from: &Path, to: &Path, prev: Option<&Path>) -> Result<(), Stop> {
+        let meta = fs::symlink_metadata(from)?;
+        let file_type = meta.file_type();
+
+        if file_type.is_symlink() {
+            symlink(fs::read_link(from)?, to)?;
+        } else if file_type.is_dir() {
+            // Stay on one filesystem, and never back up the snapshots themselves.
+            if meta.dev() != self.device || from == self.exclude {
+                return Ok(());
+            }
+            fs::create_dir(to)?;
+            self.copy_tree(from, to, prev)?;
+            fs::set_permissions(to, meta.permissions())?;
+        } else if file_type.is_file() {
+            let unchanged = prev
+                .and_then(|p| fs::symlink_metadata(p).ok())
+                .filter(|p| p.is_file() && p.len() == meta.len() && p.mtime() == meta.mtime())
+                .is_some();
+            if unchanged && fs::hard_link(prev.unwrap(), to).is_ok() {
+                self.record.files_linked += 1;
+                return Ok(());
+            }
+
+            fs::copy(from, to)?;
+            fs::OpenOptions::new()
+                .write(true)
+                .open(to)?
+                .set_modified(meta.modified()?)?;
+            self.record.files_copied += 1;
+            self.record.bytes_copied += meta.len();
+        }
+        // Sockets, fifos and device nodes are skipped.
+        Ok(())
+    }
+}
+
+/// Take a snapshot of `source` in `base`, then drop old snapshots so at
+/// most `keep` remain. The new snapshot is built under a `.partial` name
+/// and only renamed once complete.
+fn run(
+    token: &JobToken,
+    source: &Path,
+    base: &Path,
+    keep: u32,
+    now: u64,
+) -> Result<BackupRecord, Stop> {
+    // Left over by an interrupted run.
+    for entry in fs::read_dir(base)?.filter_map(|x| x.ok()) {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SUFFIX)
+        {
+            log::info!(
+                "DiskCache - Removing incomplete snapshot {:?}",
+                entry.path()
+            );
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+
+    let previous = snapshots(base)?.pop().map(|name| base.join(name));
+    let name = snapshot_name(now);
+    let partial = base.join(format!("{}{}", name, PARTIAL_SUFFIX));
+    let source_meta = fs::metadata(source)?;
+    fs::create_dir(&partial)?;
+
+    let mut snapshot = Snapshot {
+        token,
+        device: source_meta.dev(),
+        exclude: base,
+        record: BackupRecord::default(),
+    };
+    snapshot.copy_tree(source, &partial, previous.as_deref())?;
+    fs::set_permissions(&partial, source_meta.permissions())?;
+    fs::rename(&partial, base.join(&name))?;
+
+    let names = snapshots(base)?;
+    if keep > 0 && names.len() > keep as usize {
+        for old in &names[..names.len() - keep as usize] {
+            log::info!("DiskCache - Removing old snapshot {}", old);
+            fs::remove_dir_all(base.join(old))?;
+        }
+    }
+
+    snapshot.record.last_snapshot = name;
+    Ok(snapshot.record)
+}
+
+/// Create `dir` and its parents below the mount point of `sandbox`.
+fn create_dirs(sandbox: &FileSandbox, dir: &str) -> Result<PathBuf, String> {
+    let mut path = String::new();
+    for part in dir.split('/').filter(|x| !x.is_empty()) {
+        path = format!("{}/{}", path, part);
+        sandbox.create_dir(&path).map_err(|e| e.to_string())?;
+    }
+    sandbox.resolve(dir).map_err(|e| e.to_string())
+}
+
+/// Start backup `name` as a job. The backups are never locked together
+/// with another lock of `data`, nor across I/O.
+pub(super) fn start(data: &DiskCacheData, name: &str) -> Result<JobId, String> {
+    let (backup, from_config) = {
+        let backups = data.backups.lock().unwrap();
+        if backups.running.contains(name) {
+            return Err(format!("{} is already running", name));
+        }
+        let backup = backups
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown backup {}", name))?;
+        (backup, backups.configured.contains_key(name))
+    };
+    // Checked again when it runs, the disk may have changed since it was added.
+    let source = match from_config {
+        true => PathBuf::from(&backup.source),
+        false => managed_source(&mount_points(data), &backup.source)?,
+    };
+
+    let mount_point = {
+        let disks = data.data.lock().unwrap();
+        let (_, part) = disks
+            .find_partition(&backup.target)
+            .ok_or_else(|| format!("Disk {} is not attached", backup.target))?;
+        part.mount_point()
+            .map(String::from)
+            .ok_or_else(|| format!("{} is not mounted", part.kernel))?
+    };
+    let read_only = data.known.lock().unwrap().is_read_only(&backup.target);
+    let sandbox = FileSandbox::new(&mount_point, read_only).map_err(|e| e.to_string())?;
+    let base = create_dirs(&sandbox, &backup.target_dir)?;
+
+    // Checked again, it may have been started meanwhile.
+    if !data
+        .backups
+        .lock()
+        .unwrap()
+        .running
+        .insert(backup.name.clone())
+    {
+        return Err(format!("{} is already running", name));
+    }
+    let token = data.jobs.register("backup");
+    let job_id = token.id();
+
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!("DiskCache - Backup {} to {:?} starts", backup.name, base);
+        let now = now_secs();
+        let result = run(&token, &source, &base, backup.keep, now);
+        data.jobs.unregister(&token);
+
+        let mut backups = data.backups.lock().unwrap();
+        backups.running.remove(&backup.name);
+        let record = backups.records.entry(backup.name.clone()).or_default();
+        record.last_run = now;
+        match result {
+            Ok(new) => {
+                log::info!(
+                    "DiskCache - Backup {} done, {} copied, {} linked, {} failed",
+                    backup.name,
+                    new.files_copied,
+                    new.files_linked,
+                    new.files_failed
+                );
+                *record = BackupRecord {
+                    last_run: now,
+                    last_success: now,
+                    last_error: None,
+                    ..new
+                };
+            }
+            Err(Stop::Cancelled) => {
+                record.last_error = Some(String::from("Cancelled"));
+            }
+            Err(Stop::Failed(e)) => {
+                log::error!("DiskCache - Backup {} failed: {}", backup.name, e);
+                record.last_error = Some(e.to_string());
+            }
+        }
+        if let Err(e) = backups.save() {
+            log::error!("DiskCache - Cannot save backups: {}", e);
+        }
+    });
+
+    Ok(job_id)
+}
+
+/// Mount points of the mounted managed partitions.
+fn mount_points(data: &DiskCacheData) -> Vec<String> {
+    data.data
+        .lock()
+        .unwrap()
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .filter_map(|p| p.mount_point().map(String::from))
+        .collect()
+}
+
+/// Resolve the source of a backup added at runtime. Unlike those from the
+/// command line, it must be on one of the `mount_points` of managed
+/// partitions, and symlinks must not lead out of it.
+fn managed_source(mount_points: &[String], source: &str) -> Result<PathBuf, String> {
+    for mount_point in mount_points {
+        let sandbox = match FileSandbox::new(mount_point, true) {
+            Ok(sandbox) => sandbox,
+            Err(_) => continue,
+        };
+        if let Some(relative) = sandbox.relative(Path::new(source)) {
+            return sandbox.resolve(&relative).map_err(|e| e.to_string());
+        }
+    }
+    Err(format!("Backup source {} is not on a managed disk", source))
+}
+
+/// Check the source of a backup to be added at runtime, see `managed_source`.
+pub(super) fn check_source(data: &DiskCacheData, source: &str) -> Result<(), String> {
+    managed_source(&mount_points(data), source).map(|_| ())
+}
+
+/// Start every scheduled backup which is due.
+pub(super) fn check(data: &DiskCacheData) {
+    // Released before locking the backups, see `start`.
+    let mounted = data
+        .data
+        .lock()
+        .unwrap()
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .filter(|p| p.mount_point().is_some())
+        .map(|p| p.uuid.to_string())
+        .collect::<HashSet<_>>();
+    let due = data
+        .backups
+        .lock()
+        .unwrap()
+        .due(now_secs(), |uuid| mounted.contains(uuid));
+
+    for name in due {
+        if let Err(e) = start(data, &name) {
+            log::warn!("DiskCache - Cannot start backup {}: {}", name, e);
+            let mut backups = data.backups.lock().unwrap();
+            let record = backups.records.entry(name).or_default();
+            record.last_run = now_secs();
+            record.last_error = Some(e);
+            if let Err(e) = backups.save() {
+                log::error!("DiskCache - Cannot save backups: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./backup_test.rs"]
+mod backup_test;
@@ -0,0 +1,149 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+
+fn backup(name: &str, interval: u64) -> BackupConfig {
+    BackupConfig {
+        name: name.into(),
+        source: "/home".into(),
+        target: "uuid-a".into(),
+        target_dir: "backups".into(),
+        interval,
+        keep: 0,
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    let parsed = "name=home,source=/home,target=uuid-a,interval=3600,keep=7"
+        .parse::<BackupConfig>()
+        .unwrap();
+    assert_eq!(parsed.target_dir, "picontrolx-backups/home");
+    assert_eq!((parsed.interval, parsed.keep), (3600, 7));
+
+    assert!("name=home,source=home,target=uuid-a"
+        .parse::<BackupConfig>()
+        .is_err());
+    assert!("name=home,when=now".parse::<BackupConfig>().is_err());
+}
+
+#[test]
+fn test_due() {
+    let mut backups = Backups::default();
+    backups.set(backup("daily", 86400)).unwrap();
+    backups.set(backup("manual", 0)).unwrap();
+
+    // Never ran, runs once the disk is mounted.
+    assert!(backups.due(1000, |_| false).is_empty());
+    assert_eq!(backups.due(1000, |_| true), vec!["daily"]);
+
+    backups.records.insert(
+        "daily".into(),
+        BackupRecord {
+            last_run: 1000,
+            last_success: 1000,
+            ..Default::default()
+        },
+    );
+    assert!(backups.due(1000 + 3600, |_| true).is_empty());
+    // Missed while the disk was away, runs when it is back.
+    assert_eq!(backups.due(1000 + 3 * 86400, |_| true), vec!["daily"]);
+
+    // A failure is retried later, not on every check.
+    backups.records.get_mut("daily").unwrap().last_run = 1000 + 3 * 86400;
+    assert!(backups.due(1000 + 3 * 86400 + 60, |_| true).is_empty());
+    assert_eq!(
+        backups.due(1000 + 3 * 86400 + RETRY_DELAY, |_| true),
+        vec!["daily"]
+    );
+}
+
+#[test]
+fn test_configured_cannot_change() {
+    let mut backups = Backups::load("/nonexistent", &[backup("home", 60)]);
+    assert!(backups.set(backup("home", 120)).is_err());
+    assert!(backups.remove("home").is_err());
+    assert_eq!(backups.list().len(), 1);
+    assert!(backups.list()[0].from_config);
+}
+
+#[test]
+fn test_set_default_target_dir() {
+    let mut backups = Backups::default();
+    backups
+        .set(BackupConfig {
+            target_dir: String::new(),
+            ..backup("photos", 0)
+        })
+        .unwrap();
+    assert_eq!(
+        backups.get("photos").unwrap().target_dir,
+        "picontrolx-backups/photos"
+    );
+    assert!(backups
+        .set(BackupConfig {
+            source: "photos".into(),
+            ..backup("photos", 0)
+        })
+        .is_err());
+}
+
+#[test]
+fn test_managed_source() {
+    let base = TempDir::new("backup-source");
+    let mount = base.join("mnt");
+    fs::create_dir_all(mount.join("photos")).unwrap();
+    symlink("/etc", mount.join("etc")).unwrap();
+    let mounts = vec![mount.to_string_lossy().into_owned()];
+    let source = |path: &str| format!("{}/{}", mount.display(), path);
+
+    assert_eq!(
+        managed_source(&mounts, &source("photos")).unwrap(),
+        fs::canonicalize(mount.join("photos")).unwrap()
+    );
+    assert!(managed_source(&mounts, &source("etc")).is_err());
+    assert!(managed_source(&mounts, &source("../..")).is_err());
+    assert!(managed_source(&mounts, "/etc").is_err());
+    assert!(managed_source(&[], &source("photos")).is_err());
+}
+
+#[test]
+fn test_snapshots() {
+    let base = TempDir::new("backup");
+    let source = base.join("source");
+    let target = base.join("target");
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::create_dir_all(&target).unwrap();
+    fs::write(source.join("docs/a.txt"), b"aaaa").unwrap();
+    fs::write(source.join("b.txt"), b"bb").unwrap();
+    symlink("docs/a.txt", source.join("link")).unwrap();
+    fs::create_dir(target.join(format!("stale{}", PARTIAL_SUFFIX))).unwrap();
+
+    let token = Jobs::default().register("test");
+    let first = run(&token, &source, &target, 2, 0).ok().unwrap();
+    assert_eq!((first.files_copied, first.files_linked), (2, 0));
+    assert_eq!(first.last_snapshot, "1970-01-01T00-00-00");
+    assert!(
+        fs::symlink_metadata(target.join("1970-01-01T00-00-00/link"))
+            .unwrap()
+            .file_type()
+            .is_symlink()
+    );
+    assert!(!target.join(format!("stale{}", PARTIAL_SUFFIX)).exists());
+
+    fs::write(source.join("b.txt"), b"changed").unwrap();
+    let second = run(&token, &source, &target, 2, 60).ok().unwrap();
+    assert_eq!((second.files_copied, second.files_linked), (1, 1));
+    let ino = |snapshot: &str| {
+        fs::metadata(target.join(snapshot).join("docs/a.txt"))
+            .unwrap()
+            .ino()
+    };
+    assert_eq!(ino("1970-01-01T00-00-00"), ino("1970-01-01T00-01-00"));
+
+    run(&token, &source, &target, 2, 120).ok().unwrap();
+    assert_eq!(
+        snapshots(&target).unwrap(),
+        vec!["1970-01-01T00-01-00", "1970-01-01T00-02-00"]
+    );
+}
@@ -0,0 +1,307 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use super::direct::{read_exact_at, write_all_at, AlignedBuf};
+use super::DiskCacheData;
+use crate::public::job::{JobState, JobToken};
+use crate::public::{BenchPhase, BenchProgress, BenchResult};
+
+const DEFAULT_SIZE: u64 = 256 << 20; // 256 MiB
+const SEQ_BLOCK: usize = 1 << 20; // 1 MiB
+const RAND_BLOCK: usize = 4096;
+const RAND_MAX_OPS: u64 = 16384;
+const RAND_MAX_DURATION: Duration = Duration::from_secs(15);
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Removes the benchmark file however the benchmark ends.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::error!("DiskCache - Cannot remove {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+/// xorshift64, good enough to pick offsets and fill buffers.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let v = self.next().to_le_bytes();
+            chunk.copy_from_slice(&v[..chunk.len()]);
+        }
+    }
+}
+
+fn available_bytes(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+enum Stop {
+    Cancelled,
+    Failed(io::Error),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Failed(e)
+    }
+}
+
+struct Bench {
+    token: JobToken,
+    progress: watch::Sender<BenchProgress>,
+    current: BenchProgress,
+    last_publish: Instant,
+    rng: Rng,
+    /// Since when the job is paused, if it is.
+    paused_since: Option<Instant>,
+    /// Time the current phase was paused, it is not measured.
+    paused_for: Duration,
+}
+
+impl Bench {
+    fn publish(&mut self, force: bool) {
+        if !force && self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    fn start_phase(&mut self, phase: BenchPhase, total: u64) {
+        self.current.phase = phase;
+        self.current.done = 0;
+        self.current.total = total;
+        self.paused_for = Duration::ZERO;
+        self.publish(true);
+    }
+
+    /// Publish a pause or resume of the job.
+    fn paused(&mut self, paused: bool) {
+        if paused {
+            self.current.state = JobState::Paused;
+            self.paused_since = Some(Instant::now());
+        } else {
+            self.current.state = JobState::Running;
+            if let Some(since) = self.paused_since.take() {
+                self.paused_for += since.elapsed();
+            }
+        }
+        self.publish(true);
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Stop> {
+        let token = self.token.clone();
+        token
+            .checkpoint_with(|paused| self.paused(paused))
+            .map_err(|_| Stop::Cancelled)
+    }
+
+    /// Time spent in the phase started at `start`, without pauses.
+    fn elapsed(&self, start: Instant) -> Duration {
+        start.elapsed().saturating_sub(self.paused_for)
+    }
+
+    fn finish_phase(&mut self, bytes: u64, ops: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let result = BenchResult {
+            phase: self.current.phase,
+            mb_per_sec: bytes as f64 / secs / (1 << 20) as f64,
+            iops: ops as f64 / secs,
+        };
+        log::info!("DiskCache - Benchmark {:?}", result);
+        self.current.results.push(result);
+    }
+
+    fn sequential(&mut self, file: &fs::File, size: u64, write: bool) -> Result<(), Stop> {
+        let phase = if write {
+            BenchPhase::SeqWrite
+        } else {
+            BenchPhase::SeqRead
+        };
+        self.start_phase(phase, size);
+
+        let mut buf = AlignedBuf::new(SEQ_BLOCK);
+        let start = Instant::now();
+        let mut offset = 0;
+        while offset < size {
+            self.checkpoint()?;
+            if write {
+                // Random content, so compressing controllers do not cheat.
+                self.rng.fill(buf.get(SEQ_BLOCK));
+                write_all_at(file, buf.get(SEQ_BLOCK), offset)?;
+            } else {
+                read_exact_at(file, buf.get(SEQ_BLOCK), offset)?;
+            }
+            offset += SEQ_BLOCK as u64;
+            self.current.done = offset;
+            self.publish(false);
+        }
+        if write {
+            file.sync_all()?;
+        }
+
+        self.finish_phase(size, size / SEQ_BLOCK as u64, self.elapsed(start));
+        Ok(())
+    }
+
+    fn random(&mut self, file: &fs::File, size: u64, write: bool) -> Result<(), Stop> {
+        let phase = if write {
+            BenchPhase::RandWrite
+        } else {
+            BenchPhase::RandRead
+        };
+        let blocks = size / RAND_BLOCK as u64;
+        let ops = blocks.min(RAND_MAX_OPS);
+        self.start_phase(phase, ops * RAND_BLOCK as u64);
+
+        let mut buf = AlignedBuf::new(RAND_BLOCK);
+        self.rng.fill(buf.get(RAND_BLOCK));
+        let start = Instant::now();
+        let mut done = 0;
+        while done < ops && self.elapsed(start) < RAND_MAX_DURATION {
+            self.checkpoint()?;
+            let offset = (self.rng.next() % blocks) * RAND_BLOCK as u64;
+            if write {
+                write_all_at(file, buf.get(RAND_BLOCK), offset)?;
+            } else {
+                read_exact_at(file, buf.get(RAND_BLOCK), offset)?;
+            }
+            done += 1;
+            self.current.done = done * RAND_BLOCK as u64;
+            self.publish(false);
+        }
+        if write {
+            file.sync_all()?;
+        }
+
+        self.finish_phase(done * RAND_BLOCK as u64, done, self.elapsed(start));
+        Ok(())
+    }
+
+    fn run(&mut self, mount_point: &Path, size: u64) -> Result<(), Stop> {
+        let available = available_bytes(mount_point)?;
+        if available < size {
+            return Err(Stop::Failed(io::Error::other(format!(
+                "Only {} bytes available, need {}",
+                available, size
+            ))));
+        }
+
+        let tmp = TempFile {
+            path: mount_point.join(format!(".picontrolx-bench-{}.tmp", self.token.id())),
+        };
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(&tmp.path)?;
+
+        self.sequential(&file, size, true)?;
+        self.sequential(&file, size, false)?;
+        self.random(&file, size, true)?;
+        self.random(&file, size, false)?;
+
+        drop(file);
+        drop(tmp);
+        Ok(())
+    }
+}
+
+/// Size of the benchmark file, see `start`, None if it does not fit a u64.
+fn file_size(size: u64) -> Option<u64> {
+    match size {
+        0 => Some(DEFAULT_SIZE),
+        size => size
+            .div_ceil(SEQ_BLOCK as u64)
+            .checked_mul(SEQ_BLOCK as u64),
+    }
+}
+
+/// Benchmark the mounted partition `uuid` with a temporary file of
+/// `size` bytes rounded up to whole MiB, or 256 MiB if `size` is 0.
+pub(super) fn start(
+    data: &DiskCacheData,
+    uuid: &str,
+    size: u64,
+) -> Result<watch::Receiver<BenchProgress>, String> {
+    let mount_point = {
+        let disks = data.data.lock().unwrap();
+        let (_, part) = disks
+            .find_partition(uuid)
+            .ok_or_else(|| format!("Unknown disk {}", uuid))?;
+        part.mount_point()
+            .map(PathBuf::from)
+            .ok_or_else(|| format!("{} is not mounted", part.kernel))?
+    };
+    let size = file_size(size).ok_or_else(|| format!("Benchmark size {} too large", size))?;
+
+    let token = data.jobs.register("benchmark");
+    let current = BenchProgress {
+        job_id: token.id(),
+        uuid: uuid.to_owned(),
+        mount_point: mount_point.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(current.clone());
+
+    let mut bench = Bench {
+        rng: Rng(0x9e37_79b9_7f4a_7c15 ^ token.id()),
+        token,
+        progress: tx,
+        current,
+        last_publish: Instant::now(),
+        paused_since: None,
+        paused_for: Duration::ZERO,
+    };
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!("DiskCache - Benchmark on {:?} starts", mount_point);
+        bench.current.state = match bench.run(&mount_point, size) {
+            Ok(()) => JobState::Finished,
+            Err(Stop::Cancelled) => JobState::Cancelled,
+            Err(Stop::Failed(e)) => {
+                log::error!("DiskCache - Benchmark on {:?} failed: {}", mount_point, e);
+                bench.current.error = Some(e.to_string());
+                JobState::Failed
+            }
+        };
+        bench.current.phase = BenchPhase::Done;
+
+        data.jobs.unregister(&bench.token);
+        bench.publish(true);
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+#[path = "./bench_test.rs"]
+mod bench_test;
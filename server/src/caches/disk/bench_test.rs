@@ -0,0 +1,97 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+
+fn bench(jobs: &Jobs) -> Bench {
+    let (tx, _rx) = watch::channel(BenchProgress::default());
+    Bench {
+        token: jobs.register("test"),
+        progress: tx,
+        current: BenchProgress::default(),
+        last_publish: Instant::now(),
+        rng: Rng(1),
+        paused_since: None,
+        paused_for: Duration::ZERO,
+    }
+}
+
+fn bench_files(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| {
+            let name = e.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(".picontrolx-bench-")
+        })
+        .count()
+}
+
+#[test]
+fn test_file_size() {
+    assert_eq!(file_size(0), Some(DEFAULT_SIZE));
+    assert_eq!(file_size(1), Some(1 << 20));
+    assert_eq!(file_size(1 << 20), Some(1 << 20));
+    assert_eq!(file_size((1 << 20) + 1), Some(2 << 20));
+    let largest = u64::MAX - (1 << 20) + 1;
+    assert_eq!(file_size(largest), Some(largest));
+    assert_eq!(file_size(largest + 1), None);
+    assert_eq!(file_size(u64::MAX), None);
+}
+
+#[test]
+fn test_finish_phase() {
+    let mut bench = bench(&Jobs::default());
+    bench.current.phase = BenchPhase::SeqWrite;
+    bench.finish_phase(64 << 20, 64, Duration::from_secs(2));
+    bench.current.phase = BenchPhase::RandRead;
+    bench.finish_phase(0, 0, Duration::ZERO);
+
+    let seq = &bench.current.results[0];
+    assert_eq!(seq.phase, BenchPhase::SeqWrite);
+    assert_eq!(seq.mb_per_sec, 32.0);
+    assert_eq!(seq.iops, 32.0);
+    // Nothing done in no time is no throughput, not a division by zero.
+    assert_eq!(bench.current.results[1].mb_per_sec, 0.0);
+    assert_eq!(bench.current.results[1].iops, 0.0);
+
+    // Pauses are not measured.
+    let start = Instant::now() - Duration::from_secs(10);
+    bench.paused_for = Duration::from_secs(4);
+    let elapsed = bench.elapsed(start);
+    assert!(elapsed >= Duration::from_secs(6) && elapsed < Duration::from_secs(7));
+}
+
+#[test]
+fn test_run() {
+    let dir = TempDir::new("bench");
+    let jobs = Jobs::default();
+
+    let mut done = bench(&jobs);
+    assert!(done.run(&dir, 1 << 20).is_ok());
+    let phases = done.current.results.iter().map(|r| r.phase);
+    assert_eq!(
+        phases.collect::<Vec<_>>(),
+        vec![
+            BenchPhase::SeqWrite,
+            BenchPhase::SeqRead,
+            BenchPhase::RandWrite,
+            BenchPhase::RandRead
+        ]
+    );
+    // The random phases cover the file in 4 KiB blocks.
+    assert_eq!(done.current.total, 1 << 20);
+    assert_eq!(bench_files(&dir), 0);
+
+    // The file is removed when the benchmark is cancelled.
+    let mut cancelled = bench(&jobs);
+    jobs.cancel(cancelled.token.id()).unwrap();
+    assert!(matches!(cancelled.run(&dir, 1 << 20), Err(Stop::Cancelled)));
+    assert_eq!(bench_files(&dir), 0);
+
+    // A file larger than the free space is refused before it is created.
+    let mut too_large = bench(&jobs);
+    match too_large.run(&dir, u64::MAX) {
+        Err(Stop::Failed(e)) => assert!(e.to_string().starts_with("Only"), "{}", e),
+        _ => panic!("expected a failure"),
+    }
+    assert_eq!(bench_files(&dir), 0);
+}
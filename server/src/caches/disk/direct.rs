@@ -0,0 +1,47 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// O_DIRECT requires buffers, offsets and lengths aligned to the logical
+/// block size, 4096 covers every disk we care about.
+pub(super) const ALIGNMENT: usize = 4096;
+
+/// A buffer aligned for O_DIRECT I/O.
+pub(super) struct AlignedBuf {
+    inner: Vec<u8>,
+    offset: usize,
+}
+
+impl AlignedBuf {
+    pub(super) fn new(size: usize) -> Self {
+        let inner = vec![0u8; size + ALIGNMENT];
+        let offset = inner.as_ptr().align_offset(ALIGNMENT);
+        Self { inner, offset }
+    }
+
+    pub(super) fn get(&mut self, len: usize) -> &mut [u8] {
+        &mut self.inner[self.offset..self.offset + len]
+    }
+}
+
+pub(super) fn read_exact_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match file.read_at(&mut buf[done..], offset + done as u64)? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            n => done += n,
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn write_all_at(file: &fs::File, buf: &[u8], offset: u64) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match file.write_at(&buf[done..], offset + done as u64)? {
+            0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            n => done += n,
+        }
+    }
+    Ok(())
+}
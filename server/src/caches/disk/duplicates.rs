@@ -0,0 +1,297 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use super::checksum;
+use super::files::{FileSandbox, WalkEntry};
+use super::DiskCacheData;
+use crate::public::job::{JobId, JobState, JobToken};
+use crate::public::{DuplicateFile, DuplicateGroup, DuplicateProgress, FileError};
+
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+/// Searches whose groups are kept for deleting duplicates.
+const KEEP_RESULTS: usize = 5;
+
+/// A file to delete and the bytes freed, or why it was kept.
+pub(super) type Deletion = (DuplicateFile, Result<u64, String>);
+
+/// Groups found by the latest searches, so a delete only touches files
+/// known to have a copy.
+#[derive(Debug, Default)]
+pub(super) struct Results {
+    searches: Vec<(JobId, Vec<DuplicateGroup>)>,
+}
+
+impl Results {
+    fn insert(&mut self, job_id: JobId, groups: Vec<DuplicateGroup>) {
+        self.searches.retain(|(id, _)| *id != job_id);
+        self.searches.push((job_id, groups));
+        if self.searches.len() > KEEP_RESULTS {
+            self.searches.remove(0);
+        }
+    }
+
+    /// Groups found by search `job_id`, if among the latest ones.
+    pub(super) fn search(&self, job_id: JobId) -> Option<Vec<DuplicateGroup>> {
+        self.searches
+            .iter()
+            .find(|(id, _)| *id == job_id)
+            .map(|(_, groups)| groups.clone())
+    }
+}
+
+/// Files which may have duplicates, grouped by size from the largest,
+/// without empty or smaller than `min_size` files. Hard links of the same
+/// file are counted once, removing one would not free anything.
+pub(super) fn by_size(
+    entries: Vec<(String, WalkEntry)>,
+    min_size: u64,
+) -> Vec<(u64, Vec<DuplicateFile>)> {
+    let mut seen = HashSet::new();
+    let mut sizes = HashMap::<u64, Vec<DuplicateFile>>::new();
+    for (uuid, entry) in entries {
+        if entry.is_dir || entry.is_symlink || entry.size == 0 || entry.size < min_size {
+            continue;
+        }
+        if !seen.insert(entry.file_id) {
+            continue;
+        }
+        sizes.entry(entry.size).or_default().push(DuplicateFile {
+            uuid,
+            path: entry.path,
+        });
+    }
+
+    let mut groups = sizes
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    groups
+}
+
+enum Stop {
+    Cancelled,
+    Failed(String),
+}
+
+struct Finder {
+    token: JobToken,
+    progress: watch::Sender<DuplicateProgress>,
+    current: DuplicateProgress,
+    last_publish: Instant,
+    roots: Vec<DuplicateFile>,
+    min_size: u64,
+    sandboxes: HashMap<String, FileSandbox>,
+}
+
+impl Finder {
+    fn publish(&mut self, force: bool) {
+        if !force && self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    /// Publish a pause or resume of the job.
+    fn paused(&mut self, paused: bool) {
+        self.current.state = match paused {
+            true => JobState::Paused,
+            false => JobState::Running,
+        };
+        self.publish(true);
+    }
+
+    fn hash(&mut self, file: &DuplicateFile) -> Result<String, Stop> {
+        let input = self.sandboxes[&file.uuid]
+            .open_read(&file.path)
+            .map_err(|e| Stop::Failed(e.to_string()))?;
+        let token = self.token.clone();
+        let mut cancelled = false;
+        let hash = checksum::sha256(input, |chunk| {
+            if token.checkpoint_with(|paused| self.paused(paused)).is_err() {
+                cancelled = true;
+                return Err(io::Error::other("cancelled"));
+            }
+            self.current.bytes_hashed += chunk.len() as u64;
+            self.publish(false);
+            Ok(())
+        });
+        match cancelled {
+            true => Err(Stop::Cancelled),
+            false => hash.map_err(|e| Stop::Failed(e.to_string())),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Stop> {
+        let mut entries = Vec::new();
+        for root in &self.roots {
+            let found = self.sandboxes[&root.uuid]
+                .walk(&root.path)
+                .map_err(|e| Stop::Failed(format!("Cannot walk {}: {}", root.path, e)))?;
+            entries.extend(found.into_iter().map(|e| (root.uuid.clone(), e)));
+        }
+        self.current.files_scanned = entries.iter().filter(|(_, e)| !e.is_dir).count() as u64;
+
+        let sizes = by_size(entries, self.min_size);
+        for (size, files) in &sizes {
+            self.current.candidates += files.len() as u64;
+            self.current.bytes_total += size * files.len() as u64;
+        }
+        self.publish(true);
+
+        for (size, files) in sizes {
+            let mut hashes = HashMap::<String, Vec<DuplicateFile>>::new();
+            for file in files {
+                match self.hash(&file) {
+                    Ok(hash) => hashes.entry(hash).or_default().push(file),
+                    Err(Stop::Cancelled) => return Err(Stop::Cancelled),
+                    Err(Stop::Failed(e)) => {
+                        log::warn!("DiskCache - Cannot hash {}: {}", file.path, e);
+                        self.current.errors += 1;
+                    }
+                }
+                self.current.hashed += 1;
+            }
+
+            let mut found = hashes
+                .into_iter()
+                .filter(|(_, files)| files.len() > 1)
+                .map(|(hash, files)| DuplicateGroup { hash, size, files })
+                .collect::<Vec<_>>();
+            found.sort_by(|a, b| a.hash.cmp(&b.hash));
+            for group in found {
+                self.current.reclaimable += group.reclaimable();
+                self.current.groups.push(group);
+            }
+            self.publish(false);
+        }
+        Ok(())
+    }
+}
+
+/// Search the directories `roots` for duplicate files, `sandboxes` holds
+/// the sandbox of every partition of `roots`.
+pub(super) fn start(
+    data: &DiskCacheData,
+    roots: Vec<DuplicateFile>,
+    min_size: u64,
+    sandboxes: HashMap<String, FileSandbox>,
+) -> Result<watch::Receiver<DuplicateProgress>, String> {
+    if roots.is_empty() {
+        return Err(String::from("No path given"));
+    }
+
+    let token = data.jobs.register("duplicates");
+    let current = DuplicateProgress {
+        job_id: token.id(),
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(current.clone());
+    let mut finder = Finder {
+        token,
+        progress: tx,
+        current,
+        last_publish: Instant::now(),
+        roots,
+        min_size,
+        sandboxes,
+    };
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!("DiskCache - Searching duplicates in {:?}", finder.roots);
+        finder.current.state = match finder.run() {
+            Ok(()) => JobState::Finished,
+            Err(Stop::Cancelled) => JobState::Cancelled,
+            Err(Stop::Failed(e)) => {
+                log::error!("DiskCache - Duplicate search failed: {}", e);
+                finder.current.error = Some(e);
+                JobState::Failed
+            }
+        };
+        if finder.current.state == JobState::Finished {
+            data.duplicates
+                .lock()
+                .unwrap()
+                .insert(finder.current.job_id, finder.current.groups.clone());
+        }
+
+        data.jobs.unregister(&finder.token);
+        finder.publish(true);
+    });
+    Ok(rx)
+}
+
+/// Delete `file`, which must still be the file found in one of `groups`,
+/// if another file of its group is kept with the same content.
+fn delete_one(
+    groups: &[DuplicateGroup],
+    file: &DuplicateFile,
+    deleting: &[DuplicateFile],
+    sandboxes: &HashMap<String, Result<FileSandbox, FileError>>,
+) -> Result<u64, String> {
+    let group = groups
+        .iter()
+        .find(|g| g.files.contains(file))
+        .ok_or_else(|| String::from("Not a duplicate found by this search"))?;
+    let sandbox = |uuid: &str| match sandboxes.get(uuid) {
+        Some(Ok(sandbox)) => Ok(sandbox),
+        Some(Err(e)) => Err(e.to_string()),
+        None => Err(format!("Unknown disk {}", uuid)),
+    };
+    let unchanged = |f: &DuplicateFile| -> Result<bool, String> {
+        let sandbox = sandbox(&f.uuid)?;
+        if sandbox.stat(&f.path).map_err(|e| e.to_string())?.size != group.size {
+            return Ok(false);
+        }
+        let hash = sandbox
+            .open_read(&f.path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| checksum::sha256(f, |_| Ok(())).map_err(|e| e.to_string()))?;
+        Ok(hash == group.hash)
+    };
+
+    // Stops at the first copy still holding the content.
+    let kept = group
+        .files
+        .iter()
+        .filter(|f| !deleting.contains(f))
+        .any(|f| unchanged(f) == Ok(true));
+    if !kept {
+        return Err(String::from("No other copy would be kept"));
+    }
+
+    if !unchanged(file)? {
+        return Err(String::from("File changed since the search"));
+    }
+    sandbox(&file.uuid)?
+        .remove(&file.path)
+        .map_err(|e| e.to_string())?;
+    Ok(group.size)
+}
+
+/// Delete the chosen duplicates among the `groups` of a search, returns the
+/// bytes freed or the error for each file.
+pub(super) fn delete(
+    groups: &[DuplicateGroup],
+    files: &[DuplicateFile],
+    sandboxes: &HashMap<String, Result<FileSandbox, FileError>>,
+) -> Vec<Deletion> {
+    files
+        .iter()
+        .map(|file| {
+            // None of `files` counts as kept, even if deleting it fails.
+            let result = delete_one(groups, file, files, sandboxes);
+            if let Err(e) = &result {
+                log::warn!("DiskCache - Cannot delete duplicate {}: {}", file.path, e);
+            }
+            (file.clone(), result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[path = "./duplicates_test.rs"]
+mod duplicates_test;
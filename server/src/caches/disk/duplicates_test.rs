@@ -0,0 +1,154 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+use std::fs;
+use std::path::Path;
+
+fn entry(path: &str, size: u64, ino: u64) -> (String, WalkEntry) {
+    let entry = WalkEntry {
+        path: path.to_owned(),
+        is_dir: false,
+        is_symlink: false,
+        size,
+        file_id: (1, ino),
+    };
+    (String::from("uuid"), entry)
+}
+
+fn file(uuid: &str, path: &str) -> DuplicateFile {
+    DuplicateFile {
+        uuid: uuid.to_owned(),
+        path: path.to_owned(),
+    }
+}
+
+#[test]
+fn test_by_size() {
+    let entries = vec![
+        entry("a", 10, 1),
+        entry("b", 10, 2),
+        // Hard link of a.
+        entry("c", 10, 1),
+        entry("d", 20, 3),
+        entry("e", 20, 4),
+        entry("f", 30, 5),
+        entry("g", 0, 6),
+        entry("h", 0, 7),
+    ];
+    let groups = by_size(entries.clone(), 0);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].0, 20);
+    assert_eq!(groups[1].0, 10);
+    assert_eq!(groups[1].1, vec![file("uuid", "a"), file("uuid", "b")]);
+
+    assert_eq!(by_size(entries, 15).len(), 1);
+}
+
+fn setup(name: &str) -> TempDir {
+    let base = TempDir::new(&format!("duplicates-{}", name));
+    for dir in ["one/photos", "two/backup"].iter() {
+        fs::create_dir_all(base.join(dir)).unwrap();
+    }
+    fs::write(base.join("one/photos/a.jpg"), b"same").unwrap();
+    fs::write(base.join("one/photos/b.jpg"), b"diff").unwrap();
+    fs::write(base.join("two/backup/a.jpg"), b"same").unwrap();
+    fs::write(base.join("two/backup/c.jpg"), b"same").unwrap();
+    base
+}
+
+fn find(base: &Path) -> (DuplicateProgress, HashMap<String, FileSandbox>) {
+    let sandboxes = ["one", "two"]
+        .iter()
+        .map(|uuid| {
+            let dir = base.join(uuid);
+            let sandbox = FileSandbox::new(dir.to_str().unwrap(), false).unwrap();
+            (uuid.to_string(), sandbox)
+        })
+        .collect::<HashMap<_, _>>();
+    let (tx, _rx) = watch::channel(DuplicateProgress::default());
+    let mut finder = Finder {
+        token: Jobs::default().register("test"),
+        progress: tx,
+        current: DuplicateProgress::default(),
+        last_publish: Instant::now(),
+        roots: vec![file("one", "/"), file("two", "/backup")],
+        min_size: 0,
+        sandboxes,
+    };
+    assert!(finder.run().is_ok());
+    (finder.current, finder.sandboxes)
+}
+
+#[test]
+fn test_find_and_delete() {
+    let base = setup("find");
+    let (found, sandboxes) = find(&base);
+    assert_eq!(found.files_scanned, 4);
+    assert_eq!(found.candidates, 4);
+    assert_eq!(found.groups.len(), 1);
+    assert_eq!(found.reclaimable, 8);
+    let group = &found.groups[0];
+    assert_eq!(group.files.len(), 3);
+    assert!(group.files.contains(&file("two", "backup/c.jpg")));
+
+    let sandboxes = sandboxes
+        .into_iter()
+        .map(|(uuid, s)| (uuid, Ok(s)))
+        .collect::<HashMap<_, _>>();
+    let results = delete(
+        &found.groups,
+        &[file("two", "backup/a.jpg"), file("one", "photos/b.jpg")],
+        &sandboxes,
+    );
+    assert_eq!(results[0].1, Ok(4));
+    assert!(results[1].1.is_err());
+    assert!(!base.join("two/backup/a.jpg").exists());
+    assert!(base.join("one/photos/b.jpg").exists());
+
+    // The last two copies cannot both go.
+    let results = delete(
+        &found.groups,
+        &[file("one", "photos/a.jpg"), file("two", "backup/c.jpg")],
+        &sandboxes,
+    );
+    assert!(results.iter().all(|(_, r)| r.is_err()));
+    assert!(base.join("one/photos/a.jpg").exists());
+}
+
+#[test]
+fn test_delete_changed_file() {
+    let base = setup("changed");
+    let (found, sandboxes) = find(&base);
+    fs::write(base.join("two/backup/c.jpg"), b"edit").unwrap();
+
+    let sandboxes = sandboxes
+        .into_iter()
+        .map(|(uuid, s)| (uuid, Ok(s)))
+        .collect::<HashMap<_, _>>();
+    let results = delete(&found.groups, &[file("two", "backup/c.jpg")], &sandboxes);
+    assert_eq!(
+        results[0].1,
+        Err(String::from("File changed since the search"))
+    );
+    assert!(base.join("two/backup/c.jpg").exists());
+}
+
+#[test]
+fn test_delete_changed_copy() {
+    let base = setup("changed-copy");
+    let (found, sandboxes) = find(&base);
+    // Same size, other content: no longer a copy.
+    fs::write(base.join("one/photos/a.jpg"), b"edit").unwrap();
+    fs::remove_file(base.join("two/backup/a.jpg")).unwrap();
+
+    let sandboxes = sandboxes
+        .into_iter()
+        .map(|(uuid, s)| (uuid, Ok(s)))
+        .collect::<HashMap<_, _>>();
+    let results = delete(&found.groups, &[file("two", "backup/c.jpg")], &sandboxes);
+    assert_eq!(
+        results[0].1,
+        Err(String::from("No other copy would be kept"))
+    );
+    assert!(base.join("two/backup/c.jpg").exists());
+}
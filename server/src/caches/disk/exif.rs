@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// EXIF is near the start of the file, in the first APP1 segment of a JPEG
+/// or right in the TIFF header of most raw formats.
+const HEAD_SIZE: u64 = 256 << 10;
+
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let b = self.data.get(pos..pos + 2)?;
+        Some(match self.little_endian {
+            true => u16::from_le_bytes([b[0], b[1]]),
+            false => u16::from_be_bytes([b[0], b[1]]),
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let b = self.data.get(pos..pos + 4)?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            false => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    }
+
+    /// Value offsets of the entries of the IFD at `pos`, keyed by tag.
+    fn entries(&self, pos: usize) -> Vec<(u16, usize)> {
+        let count = self.u16(pos).unwrap_or(0) as usize;
+        (0..count)
+            .filter_map(|i| {
+                let entry = pos + 2 + i * 12;
+                Some((self.u16(entry)?, entry + 8))
+            })
+            .collect()
+    }
+
+    fn ascii(&self, value: usize) -> Option<&'a str> {
+        // Dates are 20 bytes long, so stored at an offset.
+        let offset = self.u32(value)? as usize;
+        let bytes = self.data.get(offset..offset + 19)?;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    fn date(&self) -> Option<(i64, u32, u32)> {
+        let ifd0 = self.u32(4)? as usize;
+        let entries = self.entries(ifd0);
+        let find = |entries: &[(u16, usize)], tag| {
+            entries
+                .iter()
+                .find(|(t, _)| *t == tag)
+                .and_then(|(_, value)| self.ascii(*value))
+                .and_then(parse_date)
+        };
+
+        let original = entries
+            .iter()
+            .find(|(tag, _)| *tag == TAG_EXIF_IFD)
+            .and_then(|(_, value)| self.u32(*value))
+            .and_then(|exif| find(&self.entries(exif as usize), TAG_DATE_TIME_ORIGINAL));
+        original.or_else(|| find(&entries, TAG_DATE_TIME))
+    }
+}
+
+/// Parse the date of `YYYY:MM:DD HH:MM:SS`.
+fn parse_date(s: &str) -> Option<(i64, u32, u32)> {
+    let year = s.get(0..4)?.parse().ok()?;
+    let month = s.get(5..7)?.parse().ok()?;
+    let day = s.get(8..10)?.parse().ok()?;
+    if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some((year, month, day))
+}
+
+fn parse_tiff(data: &[u8]) -> Option<(i64, u32, u32)> {
+    let little_endian = match data.get(0..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    Tiff {
+        data,
+        little_endian,
+    }
+    .date()
+}
+
+fn parse_jpeg(data: &[u8]) -> Option<(i64, u32, u32)> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        // Start of scan, no more metadata after it.
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return parse_tiff(&segment[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// Date a picture was taken according to its EXIF data, as (year, month, day).
+pub(super) fn parse(data: &[u8]) -> Option<(i64, u32, u32)> {
+    if data.starts_with(&[0xff, 0xd8]) {
+        parse_jpeg(data)
+    } else {
+        parse_tiff(data)
+    }
+}
+
+pub(super) fn date_taken(path: &Path) -> Option<(i64, u32, u32)> {
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .ok()?
+        .take(HEAD_SIZE)
+        .read_to_end(&mut head)
+        .ok()?;
+    parse(&head)
+}
+
+#[cfg(test)]
+#[path = "./exif_test.rs"]
+mod exif_test;
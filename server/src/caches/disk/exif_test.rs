@@ -0,0 +1,70 @@
+use super::*;
+
+/// A big endian TIFF with DateTime in IFD0 and DateTimeOriginal in the
+/// EXIF IFD.
+fn tiff(original: Option<&str>) -> Vec<u8> {
+    let mut t = b"MM\0*".to_vec();
+    t.extend_from_slice(&8u32.to_be_bytes());
+    // IFD0 at 8, two entries, then the next IFD offset.
+    t.extend_from_slice(&2u16.to_be_bytes());
+    t.extend_from_slice(&TAG_DATE_TIME.to_be_bytes());
+    t.extend_from_slice(&2u16.to_be_bytes());
+    t.extend_from_slice(&20u32.to_be_bytes());
+    t.extend_from_slice(&38u32.to_be_bytes());
+    t.extend_from_slice(&TAG_EXIF_IFD.to_be_bytes());
+    t.extend_from_slice(&4u16.to_be_bytes());
+    t.extend_from_slice(&1u32.to_be_bytes());
+    t.extend_from_slice(&58u32.to_be_bytes());
+    t.extend_from_slice(&0u32.to_be_bytes());
+    // DateTime at 38.
+    t.extend_from_slice(b"2020:01:02 03:04:05\0");
+    // EXIF IFD at 58, its DateTimeOriginal at 76.
+    let count = original.map_or(0u16, |_| 1);
+    t.extend_from_slice(&count.to_be_bytes());
+    if let Some(original) = original {
+        t.extend_from_slice(&TAG_DATE_TIME_ORIGINAL.to_be_bytes());
+        t.extend_from_slice(&2u16.to_be_bytes());
+        t.extend_from_slice(&20u32.to_be_bytes());
+        t.extend_from_slice(&76u32.to_be_bytes());
+        t.extend_from_slice(&0u32.to_be_bytes());
+        t.extend_from_slice(original.as_bytes());
+        t.push(0);
+    }
+    t
+}
+
+fn jpeg(tiff: &[u8]) -> Vec<u8> {
+    let mut j = vec![0xff, 0xd8];
+    // An APP0 segment first, like most cameras write.
+    j.extend_from_slice(&[0xff, 0xe0, 0x00, 0x04, 0x00, 0x00]);
+    j.extend_from_slice(&[0xff, 0xe1]);
+    j.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    j.extend_from_slice(b"Exif\0\0");
+    j.extend_from_slice(tiff);
+    j.extend_from_slice(&[0xff, 0xda, 0x00, 0x02]);
+    j
+}
+
+#[test]
+fn test_date_original() {
+    let t = tiff(Some("2019:12:31 23:59:59"));
+    assert_eq!(parse(&t), Some((2019, 12, 31)));
+    assert_eq!(parse(&jpeg(&t)), Some((2019, 12, 31)));
+}
+
+#[test]
+fn test_date_fallback() {
+    assert_eq!(parse(&jpeg(&tiff(None))), Some((2020, 1, 2)));
+    // Unset dates are written as zeros or spaces by some cameras.
+    assert_eq!(
+        parse(&tiff(Some("0000:00:00 00:00:00"))),
+        Some((2020, 1, 2))
+    );
+}
+
+#[test]
+fn test_no_exif() {
+    assert_eq!(parse(b""), None);
+    assert_eq!(parse(&[0xff, 0xd8, 0xff, 0xda, 0x00, 0x02]), None);
+    assert_eq!(parse(&jpeg(&tiff(None))[..20]), None);
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::{self, Seek, SeekFrom};
+
+use super::direct::read_exact_at;
+
+const SECTOR_SIZE: u64 = 512;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const PARTITION_TYPE_LINUX: u8 = 0x83;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_MAGIC: u16 = 0xef53;
+const INCOMPAT_64BIT: u32 = 0x80;
+const BG_BLOCK_UNINIT: u16 = 0x2;
+/// Largest block size, 64 KiB, as a shift of 1 KiB.
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+/// Largest group descriptor table read. A 2 TiB filesystem of 4 KiB blocks
+/// has 1 MiB of descriptors.
+const MAX_DESCS_SIZE: u64 = 16 << 20;
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+/// Byte offsets of the Linux partitions in the MBR partition table of a
+/// whole disk.
+fn linux_partitions(disk: &fs::File) -> io::Result<Vec<u64>> {
+    let mut mbr = [0u8; 512];
+    read_exact_at(disk, &mut mbr, 0)?;
+    if mbr[510..] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    Ok((0..4)
+        .map(|i| &mbr[446 + i * 16..446 + (i + 1) * 16])
+        .filter(|entry| entry[4] == PARTITION_TYPE_LINUX)
+        .map(|entry| u32_at(entry, 8) as u64 * SECTOR_SIZE)
+        .collect())
+}
+
+fn corrupt(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Corrupt ext superblock: {}", what),
+    )
+}
+
+/// Free blocks of the ext2/3/4 filesystem starting at byte `start` of
+/// `disk`, which is `size` bytes, as (offset, length) byte ranges of the
+/// disk. Groups whose block bitmap is not initialized are taken as used.
+/// The superblock comes from the card, it is checked before use.
+fn free_ranges_of(disk: &fs::File, start: u64, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    let mut sb = [0u8; 1024];
+    read_exact_at(disk, &mut sb, start + SUPERBLOCK_OFFSET)?;
+    if u16_at(&sb, 0x38) != EXT4_MAGIC {
+        return Ok(Vec::new());
+    }
+
+    let is_64bit = u32_at(&sb, 0x60) & INCOMPAT_64BIT != 0;
+    let mut blocks = u32_at(&sb, 0x04) as u64;
+    if is_64bit {
+        blocks |= (u32_at(&sb, 0x150) as u64) << 32;
+    }
+    let first_data_block = u32_at(&sb, 0x14) as u64;
+    let log_block_size = u32_at(&sb, 0x18);
+    if log_block_size > MAX_LOG_BLOCK_SIZE {
+        return Err(corrupt("block size"));
+    }
+    let block_size = 1024u64 << log_block_size;
+    let per_group = u32_at(&sb, 0x20) as u64;
+    let desc_size = match is_64bit {
+        true => u16_at(&sb, 0xfe) as u64,
+        false => 32,
+    };
+    if first_data_block >= blocks {
+        return Err(corrupt("first data block"));
+    }
+    let fits = blocks
+        .checked_mul(block_size)
+        .and_then(|bytes| bytes.checked_add(start))
+        .is_some_and(|end| end <= size);
+    if !fits {
+        return Err(corrupt("larger than the disk"));
+    }
+    if per_group == 0 || per_group > 8 * block_size {
+        return Err(corrupt("blocks per group"));
+    }
+    if !(32..=1024).contains(&desc_size) {
+        return Err(corrupt("group descriptor size"));
+    }
+
+    let groups = (blocks - first_data_block).div_ceil(per_group);
+    let descs_size = groups
+        .checked_mul(desc_size)
+        .filter(|&n| n <= MAX_DESCS_SIZE)
+        .ok_or_else(|| corrupt("number of groups"))?;
+    let mut descs = vec![0u8; descs_size as usize];
+    read_exact_at(
+        disk,
+        &mut descs,
+        start + (first_data_block + 1) * block_size,
+    )?;
+
+    let desc_size = desc_size as usize;
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    let mut bitmap = vec![0u8; block_size as usize];
+    for group in 0..groups {
+        let desc = &descs[group as usize * desc_size..(group as usize + 1) * desc_size];
+        if u16_at(desc, 0x12) & BG_BLOCK_UNINIT != 0 {
+            continue;
+        }
+        let mut bitmap_block = u32_at(desc, 0) as u64;
+        if desc_size >= 64 {
+            bitmap_block |= (u32_at(desc, 0x20) as u64) << 32;
+        }
+        let bitmap_offset = bitmap_block
+            .checked_mul(block_size)
+            .and_then(|offset| offset.checked_add(start))
+            .ok_or_else(|| corrupt("block bitmap location"))?;
+        read_exact_at(disk, &mut bitmap, bitmap_offset)?;
+
+        let first = first_data_block + group * per_group;
+        let count = per_group.min(blocks - first);
+        for i in 0..count {
+            if bitmap[(i / 8) as usize] & (1 << (i % 8)) != 0 {
+                continue;
+            }
+            let offset = start + (first + i) * block_size;
+            match ranges.last_mut() {
+                Some((s, len)) if *s + *len == offset => *len += block_size,
+                _ => ranges.push((offset, block_size)),
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// Free blocks of every ext filesystem on `disk`, sorted by offset.
+pub(super) fn free_ranges(disk: &fs::File) -> io::Result<Vec<(u64, u64)>> {
+    let size = (&*disk).seek(SeekFrom::End(0))?;
+    let mut ranges = Vec::new();
+    for start in linux_partitions(disk)? {
+        ranges.extend(free_ranges_of(disk, start, size)?);
+    }
+    ranges.sort_unstable();
+    Ok(ranges)
+}
+
+#[cfg(test)]
+#[path = "./ext4_test.rs"]
+mod ext4_test;
@@ -0,0 +1,92 @@
+use super::*;
+use crate::test_util::TempDir;
+
+const START: u64 = 2048 * SECTOR_SIZE;
+
+/// A disk with one Linux partition holding an ext2 filesystem of 1 KiB
+/// blocks in a single group of 16 blocks, where blocks 1 to 4 and 10 are
+/// used.
+fn disk() -> Vec<u8> {
+    let mut disk = vec![0u8; (START + 17 * 1024) as usize];
+    disk[446 + 4] = PARTITION_TYPE_LINUX;
+    disk[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+    disk[446 + 12..446 + 16].copy_from_slice(&34u32.to_le_bytes());
+    disk[510..512].copy_from_slice(&MBR_SIGNATURE);
+
+    let sb = (START + SUPERBLOCK_OFFSET) as usize;
+    disk[sb + 0x04..sb + 0x08].copy_from_slice(&17u32.to_le_bytes());
+    disk[sb + 0x14..sb + 0x18].copy_from_slice(&1u32.to_le_bytes());
+    disk[sb + 0x20..sb + 0x24].copy_from_slice(&16u32.to_le_bytes());
+    disk[sb + 0x38..sb + 0x3a].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+
+    // Group descriptor in block 2, bitmap in block 3.
+    let desc = (START + 2 * 1024) as usize;
+    disk[desc..desc + 4].copy_from_slice(&3u32.to_le_bytes());
+    let bitmap = (START + 3 * 1024) as usize;
+    disk[bitmap] = 0b0000_1111;
+    disk[bitmap + 1] = 0b0000_0010;
+    disk
+}
+
+fn write(name: &str, content: &[u8]) -> fs::File {
+    let dir = TempDir::new(&format!("ext4-{}", name));
+    let path = dir.join("image");
+    fs::write(&path, content).unwrap();
+    fs::File::open(&path).unwrap()
+}
+
+#[test]
+fn test_free_ranges() {
+    let file = write("free", &disk());
+    assert_eq!(
+        free_ranges(&file).unwrap(),
+        vec![(START + 5 * 1024, 5 * 1024), (START + 11 * 1024, 6 * 1024)]
+    );
+}
+
+#[test]
+fn test_uninitialized_group_is_used() {
+    let mut disk = disk();
+    let desc = (START + 2 * 1024) as usize;
+    disk[desc + 0x12..desc + 0x14].copy_from_slice(&BG_BLOCK_UNINIT.to_le_bytes());
+    let file = write("uninit", &disk);
+    assert_eq!(free_ranges(&file).unwrap(), Vec::new());
+}
+
+#[test]
+fn test_no_partition_table() {
+    let mut disk = disk();
+    disk[510] = 0;
+    let file = write("nombr", &disk);
+    assert_eq!(free_ranges(&file).unwrap(), Vec::new());
+}
+
+#[test]
+fn test_corrupt_superblock() {
+    let sb = (START + SUPERBLOCK_OFFSET) as usize;
+    let corruptions: &[(usize, u32)] = &[
+        // Block size shifted out of range.
+        (0x18, 40),
+        // Data starting past the last block.
+        (0x14, 17),
+        // More blocks than the disk holds.
+        (0x04, u32::MAX),
+        // More blocks per group than a bitmap block covers.
+        (0x20, 8 * 1024 + 1),
+        (0x20, 0),
+    ];
+    for (i, (field, value)) in corruptions.iter().enumerate() {
+        let mut disk = disk();
+        disk[sb + field..sb + field + 4].copy_from_slice(&value.to_le_bytes());
+        let file = write(&format!("corrupt-{}", i), &disk);
+        let e = free_ranges(&file).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}: {}", field, e);
+    }
+
+    // 64 bit descriptors of a nonsensical size.
+    let mut disk = disk();
+    disk[sb + 0x60..sb + 0x64].copy_from_slice(&INCOMPAT_64BIT.to_le_bytes());
+    disk[sb + 0xfe..sb + 0x100].copy_from_slice(&8u16.to_le_bytes());
+    let file = write("corrupt-desc", &disk);
+    assert!(free_ranges(&file).is_err());
+}
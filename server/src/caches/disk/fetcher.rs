@@ -7,7 +7,11 @@ use std::path::Path;
 use block_utils;
 use uuid::Uuid;
 
+use super::raid;
+use super::spindown;
+use super::trim;
 use super::{DiskInfo, Disks, Partition};
+use crate::public::SpinState;
 
 fn is_valid_subsystem(entry: &DirEntry) -> bool {
     let v = fs::read_link(entry.path());
@@ -27,15 +31,18 @@ fn is_valid_subsystem(entry: &DirEntry) -> bool {
     // Now, device_path should be something like this:
     // ../devices/pci0000:a2/0000:a2:00.0/0000:a3:00.0/0000:a4:00.0/0000:a5:00.0/virtio0/block/vda
     let valid_subsystem = vec!["pci", "usb"];
-    let nr = valid_subsystem.into_iter()
-        .filter(|&subsystem| device_path.contains(subsystem))
-        .count();
-
-    if nr <= 0 {
-        return false;
+    if valid_subsystem
+        .into_iter()
+        .any(|subsystem| device_path.contains(subsystem))
+    {
+        return true;
     }
 
-    true
+    // Software RAID arrays and device mapper volumes are virtual devices:
+    // ../devices/virtual/block/md0
+    // ../devices/virtual/block/dm-0
+    device_path.contains("virtual/block/")
+        && raid::is_virtual_disk(&entry.file_name().to_string_lossy())
 }
 
 fn scan_disks_in_dev_folder() -> Vec<DiskInfo> {
@@ -54,6 +61,10 @@ fn scan_disks_in_dev_folder() -> Vec<DiskInfo> {
         .map(|x| DiskInfo{
             kernel: x,
             size: 0,
+            serial: String::new(),
+            discard: false,
+            rotational: false,
+            spin_state: SpinState::Unknown,
             partitions: Vec::new(),
         })
         // Convert to vector
@@ -70,6 +81,14 @@ fn get_disks_info(disks: &mut Vec<DiskInfo>) {
             let size: u64 = size.trim().parse().unwrap_or(0);
             disk.size = size << 9; // * 512
         }
+
+        disk.discard = trim::supports_discard(&disk.kernel);
+        disk.rotational = spindown::is_rotational(&disk.kernel);
+
+        // Get disk serial number, USB bridges do not always report it
+        if let Ok(device_info) = block_utils::get_device_info(&disk.kernel) {
+            disk.serial = device_info.serial_number.unwrap_or_default();
+        }
     }
 }
 
@@ -114,11 +133,16 @@ fn get_disks_partitions(disks: &mut Vec<DiskInfo>) {
             }
         }
 
-        // Test if partition is mounted
+        // Test if partition is mounted, device mapper volumes are usually
+        // mounted by their /dev/mapper/ alias
+        let mut devices = vec![format!("/dev/{}", part.kernel)];
+        if let Some(name) = raid::dm_name(&part.kernel) {
+            devices.push(format!("/dev/mapper/{}", name));
+        }
         let f = io::BufReader::new(fs::File::open("/proc/mounts").expect("Cannot read /proc/mounts"));
         let it = f.lines()
                   .filter_map(|line| line.ok())
-                  .filter(|line| line.starts_with(&format!("/dev/{}", part.kernel)))
+                  .filter(|line| devices.iter().any(|dev| line.starts_with(dev.as_str())))
                   .map(|line| line.split(' ').nth(1).unwrap().to_owned())
                   .collect::<Vec<_>>();
         part.mount_path = Some(it);
@@ -127,10 +151,17 @@ fn get_disks_partitions(disks: &mut Vec<DiskInfo>) {
     }
 
     for disk in disks.iter_mut() {
-        let partitions = (1..10)
-            .filter(|x| Path::new(&format!("/dev/{}{}", disk.kernel, x)).exists())
+        // Arrays and logical volumes normally carry a filesystem directly
+        let names = if raid::is_virtual_disk(&disk.kernel) {
+            vec![disk.kernel.clone()]
+        } else {
+            (1..10).map(|x| format!("{}{}", disk.kernel, x)).collect()
+        };
+        let partitions = names
+            .into_iter()
+            .filter(|x| Path::new(&format!("/dev/{}", x)).exists())
             .map(|x| Partition {
-                kernel: format!("{}{}", disk.kernel, x),
+                kernel: x,
                 size: 0,
                 uuid: Uuid::nil(),
                 mount_path: None,
@@ -147,12 +178,16 @@ fn get_disks_partitions(disks: &mut Vec<DiskInfo>) {
 }
 
 pub(super) fn get_disks() -> Disks {
-    println!("-=-=-=-=-=-=-==- get_disks() -=-=-=--=-=-=-=-=");
+    log::debug!("DiskCache - Scanning disks");
     let mut disks = scan_disks_in_dev_folder();
     get_disks_info(&mut disks);
     get_disks_partitions(&mut disks);
 
-    Disks { disks }
+    Disks {
+        disks,
+        raids: raid::get_raid_arrays(),
+        volumes: raid::get_logical_volumes(),
+    }
 }
 
 #[cfg(test)]
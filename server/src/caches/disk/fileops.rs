@@ -0,0 +1,398 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use super::checksum;
+use super::files::{FileSandbox, WalkEntry};
+use super::DiskCacheData;
+use crate::public::job::{JobState, JobToken};
+use crate::public::{ConflictPolicy, FileError, FileOp, FileOpProgress, FileOpRequest};
+
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+enum Stop {
+    Cancelled,
+    Failed(FileError),
+}
+
+impl From<FileError> for Stop {
+    fn from(e: FileError) -> Self {
+        Stop::Failed(e)
+    }
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Failed(e.into())
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    Path::new(dir).join(name).to_string_lossy().into_owned()
+}
+
+/// `path` with ` (n)` appended to its file stem, e.g. `a/b (2).jpg`.
+pub(super) fn numbered(path: &str, n: u32) -> String {
+    let p = Path::new(path);
+    let stem = p.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match p.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    p.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Drop cached pages of `file`, so reading it again hits the disk.
+fn drop_cache(file: &fs::File) {
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+struct FileOpJob {
+    token: JobToken,
+    progress: watch::Sender<FileOpProgress>,
+    current: FileOpProgress,
+    last_publish: Instant,
+    request: FileOpRequest,
+    source: FileSandbox,
+    target: Option<FileSandbox>,
+}
+
+impl FileOpJob {
+    fn publish(&mut self, force: bool) {
+        if !force && self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    /// Publish a pause or resume of the job.
+    fn paused(&mut self, paused: bool) {
+        self.current.state = match paused {
+            true => JobState::Paused,
+            false => JobState::Running,
+        };
+        self.publish(true);
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Stop> {
+        let token = self.token.clone();
+        token
+            .checkpoint_with(|paused| self.paused(paused))
+            .map_err(|_| Stop::Cancelled)
+    }
+
+    fn fail(&mut self, path: &str, e: FileError) {
+        log::warn!(
+            "DiskCache - {:?} of {} failed: {}",
+            self.request.op,
+            path,
+            e
+        );
+        self.current.failed.push((path.to_owned(), e.to_string()));
+    }
+
+    fn target(&self) -> &FileSandbox {
+        self.target.as_ref().expect("copy and move have a target")
+    }
+
+    fn free_name(&self, path: &str) -> String {
+        (1..)
+            .map(|n| numbered(path, n))
+            .find(|candidate| !self.target().exists(candidate))
+            .unwrap()
+    }
+
+    fn run(&mut self) -> Result<(), Stop> {
+        // Walk everything first, so totals are known from the start.
+        let mut items = Vec::new();
+        for path in self.request.paths.clone() {
+            match self.source.walk(&path) {
+                Ok(entries) => items.push(entries),
+                Err(e) => self.fail(&path, e),
+            }
+        }
+        for entry in items.iter().flatten().filter(|x| !x.is_dir) {
+            self.current.files_total += 1;
+            self.current.bytes_total += entry.size;
+        }
+        self.publish(true);
+
+        for entries in items {
+            match self.request.op {
+                FileOp::Delete => self.delete(&entries)?,
+                FileOp::Copy | FileOp::Move => self.transfer(&entries)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `entry` as done, whatever happened to it.
+    fn done(&mut self, entry: &WalkEntry, bytes_before: u64) {
+        if !entry.is_dir {
+            self.current.files_done += 1;
+            self.current.bytes_done = bytes_before + entry.size;
+        }
+        self.publish(false);
+    }
+
+    fn delete(&mut self, entries: &[WalkEntry]) -> Result<(), Stop> {
+        // Children before their parents.
+        for entry in entries.iter().rev() {
+            self.checkpoint()?;
+            self.current.current = entry.path.clone();
+            let bytes_before = self.current.bytes_done;
+            if let Err(e) = self.source.remove(&entry.path) {
+                self.fail(&entry.path, e);
+            }
+            self.done(entry, bytes_before);
+        }
+        Ok(())
+    }
+
+    /// Copy, or move, one top level item and everything below it.
+    fn transfer(&mut self, entries: &[WalkEntry]) -> Result<(), Stop> {
+        let top = &entries[0];
+        let name = Path::new(&top.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let mut dst_top = join(&self.request.target_dir, &name);
+
+        // Directories are merged into an existing one, unless renaming.
+        if self.target().exists(&dst_top) && self.request.conflict == ConflictPolicy::Rename {
+            dst_top = self.free_name(&dst_top);
+        }
+
+        // Within one partition a move is a rename, if nothing is in the way.
+        let same_partition = self.request.source == self.request.target;
+        if self.request.op == FileOp::Move && same_partition && !self.target().exists(&dst_top) {
+            self.checkpoint()?;
+            self.current.current = top.path.clone();
+            match self.source.rename(&top.path, &dst_top) {
+                Ok(()) => {
+                    for entry in entries {
+                        let bytes_before = self.current.bytes_done;
+                        self.done(entry, bytes_before);
+                    }
+                    return Ok(());
+                }
+                Err(e) => log::info!("DiskCache - Rename of {} failed, copying: {}", top.path, e),
+            }
+        }
+
+        let mut transferred = Vec::new();
+        for entry in entries {
+            self.checkpoint()?;
+            self.current.current = entry.path.clone();
+            let rel = entry.path[top.path.len()..].trim_start_matches('/');
+            let dst = if rel.is_empty() {
+                dst_top.clone()
+            } else {
+                join(&dst_top, rel)
+            };
+
+            let bytes_before = self.current.bytes_done;
+            let result = if entry.is_dir {
+                self.target()
+                    .create_dir(&dst)
+                    .map(|_| true)
+                    .map_err(Stop::from)
+            } else if entry.is_symlink {
+                self.copy_link(entry, &dst)
+            } else {
+                self.copy_file(entry, &dst)
+            };
+            match result {
+                Ok(true) => transferred.push(entry),
+                Ok(false) => {}
+                Err(Stop::Failed(e)) => self.fail(&entry.path, e),
+                Err(Stop::Cancelled) => return Err(Stop::Cancelled),
+            }
+            self.done(entry, bytes_before);
+        }
+
+        if self.request.op == FileOp::Move {
+            for entry in transferred.into_iter().rev() {
+                match self.source.remove(&entry.path) {
+                    Ok(()) => {}
+                    // Holds files which were skipped or failed.
+                    Err(_) if entry.is_dir => {}
+                    Err(e) => self.fail(&entry.path, e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Where to write `dst` given the conflict policy and whether to
+    /// overwrite it, None to skip it.
+    fn conflict_free(&mut self, dst: &str) -> Option<(String, bool)> {
+        if !self.target().exists(dst) {
+            return Some((dst.to_owned(), false));
+        }
+        match self.request.conflict {
+            ConflictPolicy::Skip => {
+                self.current.skipped += 1;
+                None
+            }
+            ConflictPolicy::Overwrite => Some((dst.to_owned(), true)),
+            ConflictPolicy::Rename => Some((self.free_name(dst), false)),
+        }
+    }
+
+    /// Copy a symlink as a symlink to the same target, returns false if it
+    /// was skipped.
+    fn copy_link(&mut self, entry: &WalkEntry, dst: &str) -> Result<bool, Stop> {
+        let (dst, overwrite) = match self.conflict_free(dst) {
+            Some(dst) => dst,
+            None => return Ok(false),
+        };
+        let link = self.source.read_link(&entry.path)?;
+        if overwrite {
+            self.target().remove(&dst)?;
+        }
+        self.target().symlink(&link, &dst)?;
+        Ok(true)
+    }
+
+    /// Copy a single file, returns false if it was skipped.
+    fn copy_file(&mut self, entry: &WalkEntry, dst: &str) -> Result<bool, Stop> {
+        let (dst, overwrite) = match self.conflict_free(dst) {
+            Some(dst) => dst,
+            None => return Ok(false),
+        };
+
+        let input = self.source.open_read(&entry.path)?;
+        let mut upload = self.target().create(&dst, overwrite)?;
+        let token = self.token.clone();
+        let mut cancelled = false;
+        let copied = checksum::sha256(input, |chunk| {
+            if token.checkpoint_with(|paused| self.paused(paused)).is_err() {
+                cancelled = true;
+                return Err(io::Error::other("cancelled"));
+            }
+            upload.file().write_all(chunk)?;
+            self.current.bytes_done += chunk.len() as u64;
+            self.publish(false);
+            Ok(())
+        });
+        if cancelled {
+            return Err(Stop::Cancelled);
+        }
+        let copied = copied?;
+        upload.commit()?;
+
+        if self.request.verify {
+            let file = self.target().open_read(&dst)?;
+            drop_cache(&file);
+            let written = checksum::sha256(file, |_| Ok(()))?;
+            if written != copied {
+                let _ = self.target().remove(&dst);
+                return Err(Stop::Failed(FileError::Io(format!(
+                    "Checksum mismatch, {} != {}",
+                    written, copied
+                ))));
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Start `request` as a job, `target` is None for a delete.
+pub(super) fn start(
+    data: &DiskCacheData,
+    request: FileOpRequest,
+    source: FileSandbox,
+    target: Option<FileSandbox>,
+) -> Result<watch::Receiver<FileOpProgress>, FileError> {
+    if request.paths.is_empty() {
+        return Err(FileError::Invalid(String::from("No path given")));
+    }
+    if request.op != FileOp::Copy && source.read_only() {
+        return Err(FileError::Denied(String::from("Source mount is read-only")));
+    }
+    for path in &request.paths {
+        if source.relative(&source.resolve(path)?) == Some(String::new()) {
+            return Err(FileError::Invalid(format!("{} is the mount point", path)));
+        }
+    }
+    if let Some(target) = &target {
+        if target.read_only() {
+            return Err(FileError::Denied(String::from("Target mount is read-only")));
+        }
+        let dir = target.resolve(&request.target_dir)?;
+        if !dir.is_dir() {
+            return Err(FileError::Invalid(format!(
+                "{} is not a directory",
+                request.target_dir
+            )));
+        }
+        if request.source == request.target {
+            for path in &request.paths {
+                if dir.starts_with(source.resolve(path)?) {
+                    return Err(FileError::Invalid(format!("{} would contain itself", path)));
+                }
+            }
+        }
+    }
+
+    let kind = match request.op {
+        FileOp::Copy => "copy",
+        FileOp::Move => "move",
+        FileOp::Delete => "delete",
+    };
+    let token = data.jobs.register(kind);
+    let current = FileOpProgress {
+        job_id: token.id(),
+        op: request.op,
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(current.clone());
+
+    let mut job = FileOpJob {
+        token,
+        progress: tx,
+        current,
+        last_publish: Instant::now(),
+        request,
+        source,
+        target,
+    };
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!(
+            "DiskCache - {:?} of {:?} starts",
+            job.request.op,
+            job.request.paths
+        );
+        job.current.state = match job.run() {
+            Ok(()) if job.current.failed.is_empty() => JobState::Finished,
+            Ok(()) => {
+                job.current.error = Some(format!("{} items failed", job.current.failed.len()));
+                JobState::Failed
+            }
+            Err(Stop::Cancelled) => JobState::Cancelled,
+            Err(Stop::Failed(e)) => {
+                job.current.error = Some(e.to_string());
+                JobState::Failed
+            }
+        };
+        job.current.current.clear();
+
+        data.jobs.unregister(&job.token);
+        job.publish(true);
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+#[path = "./fileops_test.rs"]
+mod fileops_test;
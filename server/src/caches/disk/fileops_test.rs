@@ -0,0 +1,148 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+use std::os::unix::fs::symlink;
+
+fn setup(name: &str) -> TempDir {
+    let base = TempDir::new(&format!("fileops-{}", name));
+    fs::create_dir_all(base.join("src/photos/2021")).unwrap();
+    fs::create_dir_all(base.join("dst")).unwrap();
+    fs::write(base.join("src/photos/a.jpg"), b"aaaa").unwrap();
+    fs::write(base.join("src/photos/2021/b.jpg"), b"bb").unwrap();
+    base
+}
+
+fn run(base: &Path, request: FileOpRequest) -> FileOpProgress {
+    let sandbox = |dir: &str| FileSandbox::new(base.join(dir).to_str().unwrap(), false).unwrap();
+    let source = sandbox(&request.source);
+    let target = match request.op {
+        FileOp::Delete => None,
+        _ => Some(sandbox(&request.target)),
+    };
+    let (tx, _rx) = watch::channel(FileOpProgress::default());
+    let mut job = FileOpJob {
+        token: Jobs::default().register("test"),
+        progress: tx,
+        current: FileOpProgress::default(),
+        last_publish: Instant::now(),
+        request,
+        source,
+        target,
+    };
+    assert!(job.run().is_ok());
+    job.current
+}
+
+fn copy_request(conflict: ConflictPolicy) -> FileOpRequest {
+    FileOpRequest {
+        op: FileOp::Copy,
+        source: String::from("src"),
+        paths: vec![String::from("photos")],
+        target: String::from("dst"),
+        target_dir: String::from("/"),
+        conflict,
+        verify: true,
+    }
+}
+
+#[test]
+fn test_numbered() {
+    assert_eq!(numbered("a/b.jpg", 1), "a/b (1).jpg");
+    assert_eq!(numbered("photos", 2), "photos (2)");
+    assert_eq!(numbered("a/.hidden", 1), "a/.hidden (1)");
+}
+
+#[test]
+fn test_copy_conflicts() {
+    let base = setup("copy");
+
+    let progress = run(&base, copy_request(ConflictPolicy::Skip));
+    assert_eq!((progress.files_done, progress.files_total), (2, 2));
+    assert_eq!((progress.bytes_done, progress.bytes_total), (6, 6));
+    assert_eq!(fs::read(base.join("dst/photos/2021/b.jpg")).unwrap(), b"bb");
+
+    // Directories merge, existing files are skipped.
+    fs::write(base.join("dst/photos/a.jpg"), b"old").unwrap();
+    let progress = run(&base, copy_request(ConflictPolicy::Skip));
+    assert_eq!(progress.skipped, 2);
+    assert_eq!(fs::read(base.join("dst/photos/a.jpg")).unwrap(), b"old");
+
+    let progress = run(&base, copy_request(ConflictPolicy::Overwrite));
+    assert!(progress.failed.is_empty());
+    assert_eq!(fs::read(base.join("dst/photos/a.jpg")).unwrap(), b"aaaa");
+
+    run(&base, copy_request(ConflictPolicy::Rename));
+    assert_eq!(
+        fs::read(base.join("dst/photos (1)/a.jpg")).unwrap(),
+        b"aaaa"
+    );
+}
+
+#[test]
+fn test_move_and_delete() {
+    let base = setup("move");
+
+    let request = FileOpRequest {
+        op: FileOp::Move,
+        ..copy_request(ConflictPolicy::Skip)
+    };
+    let progress = run(&base, request);
+    assert!(progress.failed.is_empty());
+    assert!(!base.join("src/photos").exists());
+    assert_eq!(fs::read(base.join("dst/photos/a.jpg")).unwrap(), b"aaaa");
+
+    // Within one partition.
+    fs::create_dir(base.join("dst/archive")).unwrap();
+    let request = FileOpRequest {
+        op: FileOp::Move,
+        source: String::from("dst"),
+        target_dir: String::from("archive"),
+        ..copy_request(ConflictPolicy::Skip)
+    };
+    run(&base, request);
+    assert!(base.join("dst/archive/photos/2021/b.jpg").exists());
+
+    let request = FileOpRequest {
+        op: FileOp::Delete,
+        source: String::from("dst"),
+        paths: vec![String::from("archive")],
+        ..Default::default()
+    };
+    let progress = run(&base, request);
+    assert_eq!(progress.files_done, 2);
+    assert!(!base.join("dst/archive").exists());
+}
+
+#[test]
+fn test_symlinks() {
+    let base = setup("links");
+    symlink("2021", base.join("src/photos/latest")).unwrap();
+    symlink("photos/2021", base.join("src/shortcut")).unwrap();
+
+    // The link is copied, not what it points to.
+    let progress = run(&base, copy_request(ConflictPolicy::Skip));
+    assert!(progress.failed.is_empty());
+    let link = base.join("dst/photos/latest");
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_link(&link).unwrap(), Path::new("2021"));
+
+    // Deleting a link leaves its target.
+    let delete = |paths: &[&str]| FileOpRequest {
+        op: FileOp::Delete,
+        source: String::from("src"),
+        paths: paths.iter().map(|p| p.to_string()).collect(),
+        ..Default::default()
+    };
+    let progress = run(&base, delete(&["shortcut"]));
+    assert!(progress.failed.is_empty());
+    assert!(fs::symlink_metadata(base.join("src/shortcut")).is_err());
+    assert!(base.join("src/photos/2021/b.jpg").exists());
+
+    // Directories holding links are deleted too.
+    let progress = run(&base, delete(&["photos"]));
+    assert!(progress.failed.is_empty());
+    assert!(!base.join("src/photos").exists());
+}
@@ -0,0 +1,415 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::public::{FileEntry, FileError};
+
+/// Numbers the temporary files of uploads, so that concurrent uploads and
+/// files left by a crash never collide.
+static UPLOAD_NUMBER: AtomicU64 = AtomicU64::new(0);
+
+/// An entry found by `FileSandbox::walk`.
+#[derive(Clone, Debug)]
+pub(crate) struct WalkEntry {
+    pub(crate) path: String,
+    pub(crate) is_dir: bool,
+    /// A symlink, never followed: the link itself is copied, moved or
+    /// removed.
+    pub(crate) is_symlink: bool,
+    pub(crate) size: u64,
+    /// Device and inode, equal for hard links of the same file.
+    pub(crate) file_id: (u64, u64),
+}
+
+/// Access to the files below the mount point of a managed partition. Every
+/// path is relative to the mount point, and is refused if it leads outside
+/// of it, either by `..` or by following a symlink.
+#[derive(Clone, Debug)]
+pub(crate) struct FileSandbox {
+    root: PathBuf,
+    read_only: bool,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::NotFound(v)
+            | FileError::Denied(v)
+            | FileError::Invalid(v)
+            | FileError::Exists(v)
+            | FileError::Io(v) => f.write_str(v),
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound(e.to_string()),
+            io::ErrorKind::PermissionDenied => FileError::Denied(e.to_string()),
+            _ => FileError::Io(e.to_string()),
+        }
+    }
+}
+
+/// Lexically clean `path`, a leading `/` means the mount point itself.
+fn clean_relative(path: &str) -> Result<PathBuf, FileError> {
+    let mut clean = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => clean.push(name),
+            Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir => {
+                return Err(FileError::Denied(format!("{} contains ..", path)));
+            }
+            Component::Prefix(_) => return Err(FileError::Invalid(path.to_owned())),
+        }
+    }
+    Ok(clean)
+}
+
+fn to_entry(name: String, meta: &fs::Metadata) -> FileEntry {
+    FileEntry {
+        name,
+        size: meta.len(),
+        is_dir: meta.is_dir(),
+        is_symlink: meta.file_type().is_symlink(),
+        mode: meta.mode(),
+        modified: meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs()),
+    }
+}
+
+impl FileSandbox {
+    pub(super) fn new(mount_point: &str, read_only: bool) -> Result<Self, FileError> {
+        Ok(Self {
+            root: fs::canonicalize(mount_point)?,
+            read_only,
+        })
+    }
+
+    pub(crate) fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Resolve an existing path, symlinks must stay inside the sandbox.
+    pub(crate) fn resolve(&self, path: &str) -> Result<PathBuf, FileError> {
+        let full = fs::canonicalize(self.root.join(clean_relative(path)?))?;
+        if !full.starts_with(&self.root) {
+            return Err(FileError::Denied(format!("{} leads outside", path)));
+        }
+        Ok(full)
+    }
+
+    /// Resolve `path` through its parent directory, the last component is
+    /// not followed, so symlinks themselves can be inspected or removed.
+    fn resolve_entry(&self, path: &str) -> Result<PathBuf, FileError> {
+        let clean = clean_relative(path)?;
+        let name = clean
+            .file_name()
+            .ok_or_else(|| FileError::Invalid(format!("{} has no file name", path)))?;
+        let parent = self.resolve(&clean.parent().unwrap_or(Path::new("")).to_string_lossy())?;
+        Ok(parent.join(name))
+    }
+
+    /// Resolve a path to be created, its parent directory must exist and the
+    /// path itself must not be a symlink.
+    fn resolve_new(&self, path: &str) -> Result<PathBuf, FileError> {
+        let full = self.resolve_entry(path)?;
+        if let Ok(meta) = fs::symlink_metadata(&full) {
+            if meta.file_type().is_symlink() || meta.is_dir() {
+                return Err(FileError::Denied(format!("{} cannot be overwritten", path)));
+            }
+        }
+        Ok(full)
+    }
+
+    fn check_writable(&self) -> Result<(), FileError> {
+        if self.read_only {
+            return Err(FileError::Denied(String::from("Mount is read-only")));
+        }
+        Ok(())
+    }
+
+    /// Path relative to the mount point, as accepted by the other methods.
+    pub(crate) fn relative(&self, full: &Path) -> Option<String> {
+        full.strip_prefix(&self.root)
+            .ok()
+            .map(|v| v.to_string_lossy().into_owned())
+    }
+
+    pub(crate) fn exists(&self, path: &str) -> bool {
+        self.resolve_entry(path)
+            .map(|full| fs::symlink_metadata(full).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// Create directory `path`, an existing directory is fine.
+    pub(crate) fn create_dir(&self, path: &str) -> Result<(), FileError> {
+        self.check_writable()?;
+        let full = self.resolve_entry(path)?;
+        match fs::symlink_metadata(&full) {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => Err(FileError::Exists(path.to_owned())),
+            Err(_) => Ok(fs::create_dir(&full)?),
+        }
+    }
+
+    /// Remove a file, a symlink or an empty directory.
+    pub(crate) fn remove(&self, path: &str) -> Result<(), FileError> {
+        self.check_writable()?;
+        let full = self.resolve_entry(path)?;
+        if fs::symlink_metadata(&full)?.is_dir() {
+            fs::remove_dir(&full)?;
+        } else {
+            fs::remove_file(&full)?;
+        }
+        Ok(())
+    }
+
+    /// Rename `from` to `to` within the sandbox, `to` must not exist.
+    pub(crate) fn rename(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.check_writable()?;
+        let from = self.resolve_entry(from)?;
+        let full = self.resolve_entry(to)?;
+        if fs::symlink_metadata(&full).is_ok() {
+            return Err(FileError::Exists(to.to_owned()));
+        }
+        fs::rename(from, full)?;
+        Ok(())
+    }
+
+    /// Every entry below `path`, `path` included, parents before children.
+    /// Symlinks, `path` included, are returned but not followed.
+    pub(crate) fn walk(&self, path: &str) -> Result<Vec<WalkEntry>, FileError> {
+        fn visit(dir: &Path, out: &mut Vec<(PathBuf, fs::Metadata)>) {
+            let entries = match fs::read_dir(dir) {
+                Err(e) => {
+                    log::warn!("Cannot read {:?}: {}", dir, e);
+                    return;
+                }
+                Ok(entries) => entries,
+            };
+            let mut entries = entries.filter_map(|x| x.ok()).collect::<Vec<_>>();
+            entries.sort_by_key(|x| x.file_name());
+            for entry in entries {
+                // Not following symlinks.
+                let meta = match entry.metadata() {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                let is_dir = meta.is_dir();
+                out.push((entry.path(), meta));
+                if is_dir {
+                    visit(&entry.path(), out);
+                }
+            }
+        }
+
+        // The mount point itself has no parent to resolve through.
+        let top = if clean_relative(path)?.as_os_str().is_empty() {
+            self.root.clone()
+        } else {
+            self.resolve_entry(path)?
+        };
+        let mut found = vec![(top.clone(), fs::symlink_metadata(&top)?)];
+        if found[0].1.is_dir() {
+            visit(&top, &mut found);
+        }
+
+        Ok(found
+            .into_iter()
+            .filter_map(|(full, meta)| {
+                let is_symlink = meta.file_type().is_symlink();
+                Some(WalkEntry {
+                    path: self.relative(&full)?,
+                    is_dir: meta.is_dir(),
+                    is_symlink,
+                    size: if is_symlink { 0 } else { meta.len() },
+                    file_id: (meta.dev(), meta.ino()),
+                })
+            })
+            .collect())
+    }
+
+    /// List `path` sorted by name, skipping `offset` entries and returning at
+    /// most `limit`. The second value is the offset of the next page.
+    pub(crate) fn list(
+        &self,
+        path: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<FileEntry>, Option<usize>), FileError> {
+        let dir = self.resolve(path)?;
+        let mut names = fs::read_dir(&dir)?
+            .filter_map(|x| x.ok())
+            .filter_map(|x| x.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        let end = offset.saturating_add(limit).min(names.len());
+        let next = if end < names.len() { Some(end) } else { None };
+        let entries = names
+            .into_iter()
+            .skip(offset)
+            .take(end.saturating_sub(offset))
+            .filter_map(|name| {
+                let meta = fs::symlink_metadata(dir.join(&name)).ok()?;
+                Some(to_entry(name, &meta))
+            })
+            .collect();
+
+        Ok((entries, next))
+    }
+
+    /// Where symlink `path` points to.
+    pub(crate) fn read_link(&self, path: &str) -> Result<PathBuf, FileError> {
+        Ok(fs::read_link(self.resolve_entry(path)?)?)
+    }
+
+    /// Create symlink `path` pointing to `target`. It is not followed by the
+    /// sandbox if it leads outside.
+    pub(crate) fn symlink(&self, target: &Path, path: &str) -> Result<(), FileError> {
+        self.check_writable()?;
+        let full = self.resolve_new(path)?;
+        if fs::symlink_metadata(&full).is_ok() {
+            return Err(FileError::Exists(path.to_owned()));
+        }
+        std::os::unix::fs::symlink(target, full)?;
+        Ok(())
+    }
+
+    pub(crate) fn stat(&self, path: &str) -> Result<FileEntry, FileError> {
+        let full = self.resolve(path)?;
+        let name = full
+            .file_name()
+            .map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(to_entry(name, &fs::metadata(&full)?))
+    }
+
+    pub(crate) fn open_read(&self, path: &str) -> Result<fs::File, FileError> {
+        let full = self.resolve(path)?;
+        if full.is_dir() {
+            return Err(FileError::Invalid(format!("{} is a directory", path)));
+        }
+        Ok(fs::File::open(full)?)
+    }
+
+    /// Create a temporary file next to `path`, to be moved over `path` by
+    /// `commit` once the upload is complete. Unless `overwrite`, `path` must
+    /// not exist, neither now nor when committing.
+    pub(crate) fn create(&self, path: &str, overwrite: bool) -> Result<Upload, FileError> {
+        self.check_writable()?;
+
+        let target = self.resolve_new(path)?;
+        if !overwrite && target.exists() {
+            return Err(FileError::Exists(path.to_owned()));
+        }
+
+        let (file, tmp) = loop {
+            let mut tmp_name = target.file_name().unwrap_or_default().to_os_string();
+            let n = UPLOAD_NUMBER.fetch_add(1, Ordering::Relaxed);
+            tmp_name.push(format!(".{}-{}.picontrolx-upload", std::process::id(), n));
+            let tmp = target.with_file_name(tmp_name);
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&tmp);
+            match file {
+                Ok(file) => break (file, tmp),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        Ok(Upload {
+            file,
+            tmp,
+            target,
+            path: path.to_owned(),
+            overwrite,
+            committed: false,
+        })
+    }
+}
+
+/// Rename `from` to `to`, failing with `AlreadyExists` if `to` exists.
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let c_from = CString::new(from.as_os_str().as_bytes())?;
+    let c_to = CString::new(to.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_from.as_ptr(),
+            libc::AT_FDCWD,
+            c_to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // Not supported by the filesystem, a hard link is as exclusive.
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => {
+            fs::hard_link(from, to)?;
+            fs::remove_file(from)
+        }
+        _ => Err(e),
+    }
+}
+
+/// A file being uploaded, removed unless committed.
+pub(crate) struct Upload {
+    file: fs::File,
+    tmp: PathBuf,
+    target: PathBuf,
+    path: String,
+    overwrite: bool,
+    committed: bool,
+}
+
+impl Upload {
+    pub(crate) fn file(&mut self) -> &mut fs::File {
+        &mut self.file
+    }
+
+    pub(crate) fn commit(mut self) -> Result<u64, FileError> {
+        self.file.sync_all()?;
+        let size = self.file.metadata()?.len();
+        match self.overwrite {
+            true => fs::rename(&self.tmp, &self.target)?,
+            // Created since the upload started.
+            false => rename_noreplace(&self.tmp, &self.target).map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => FileError::Exists(self.path.clone()),
+                _ => FileError::from(e),
+            })?,
+        }
+        self.committed = true;
+        Ok(size)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./files_test.rs"]
+mod files_test;
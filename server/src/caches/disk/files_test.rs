@@ -0,0 +1,144 @@
+use super::*;
+use crate::test_util::TempDir;
+use std::os::unix::fs::symlink;
+
+fn setup(name: &str) -> (TempDir, FileSandbox) {
+    let base = TempDir::new(&format!("files-{}", name));
+    let root = base.join("mnt");
+    fs::create_dir_all(root.join("photos")).unwrap();
+    fs::write(root.join("photos/a.jpg"), b"aaaa").unwrap();
+    fs::write(root.join("photos/b.jpg"), b"bb").unwrap();
+    fs::write(root.join("photos/c.jpg"), b"c").unwrap();
+    fs::write(base.join("secret"), b"secret").unwrap();
+    symlink(base.join("secret"), root.join("escape")).unwrap();
+    symlink(root.join("photos/a.jpg"), root.join("inside")).unwrap();
+
+    let sandbox = FileSandbox::new(root.to_str().unwrap(), false).unwrap();
+    (base, sandbox)
+}
+
+#[test]
+fn test_resolve_confined() {
+    let (_base, sandbox) = setup("resolve");
+
+    assert!(sandbox.resolve("/photos/a.jpg").is_ok());
+    assert!(sandbox.resolve("photos/./b.jpg").is_ok());
+    assert!(sandbox.resolve("inside").is_ok());
+    assert!(matches!(
+        sandbox.resolve("../secret"),
+        Err(FileError::Denied(_))
+    ));
+    assert!(matches!(
+        sandbox.resolve("photos/../../secret"),
+        Err(FileError::Denied(_))
+    ));
+    assert!(matches!(
+        sandbox.resolve("escape"),
+        Err(FileError::Denied(_))
+    ));
+    assert!(matches!(
+        sandbox.resolve("missing"),
+        Err(FileError::NotFound(_))
+    ));
+}
+
+#[test]
+fn test_list_paging() {
+    let (_base, sandbox) = setup("list");
+
+    let (entries, next) = sandbox.list("photos", 0, 2).unwrap();
+    let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["a.jpg", "b.jpg"]);
+    assert_eq!(next, Some(2));
+
+    let (entries, next) = sandbox.list("photos", 2, 2).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].size, 1);
+    assert_eq!(next, None);
+}
+
+#[test]
+fn test_upload() {
+    use std::io::Write;
+    let (base, sandbox) = setup("upload");
+
+    let mut upload = sandbox.create("photos/new.jpg", false).unwrap();
+    upload.file().write_all(b"new").unwrap();
+    assert_eq!(upload.commit().unwrap(), 3);
+    assert_eq!(fs::read(base.join("mnt/photos/new.jpg")).unwrap(), b"new");
+
+    // Dropped before commit, nothing is left behind.
+    let upload = sandbox.create("photos/a.jpg", true).unwrap();
+    drop(upload);
+    assert_eq!(fs::read_dir(base.join("mnt/photos")).unwrap().count(), 4);
+
+    // Uploads of the same path do not share their temporary file.
+    let mut first = sandbox.create("photos/b.jpg", true).unwrap();
+    let mut second = sandbox.create("photos/b.jpg", true).unwrap();
+    first.file().write_all(b"first").unwrap();
+    second.file().write_all(b"second").unwrap();
+    assert_eq!(second.commit().unwrap(), 6);
+    assert_eq!(first.commit().unwrap(), 5);
+    assert_eq!(fs::read(base.join("mnt/photos/b.jpg")).unwrap(), b"first");
+
+    assert!(matches!(
+        sandbox.create("photos/a.jpg", false),
+        Err(FileError::Exists(_))
+    ));
+    // Created meanwhile, kept.
+    let mut upload = sandbox.create("photos/d.jpg", false).unwrap();
+    upload.file().write_all(b"late").unwrap();
+    fs::write(base.join("mnt/photos/d.jpg"), b"first").unwrap();
+    assert!(matches!(upload.commit(), Err(FileError::Exists(_))));
+    assert_eq!(fs::read(base.join("mnt/photos/d.jpg")).unwrap(), b"first");
+    assert_eq!(fs::read_dir(base.join("mnt/photos")).unwrap().count(), 5);
+    assert!(matches!(
+        sandbox.create("escape", true),
+        Err(FileError::Denied(_))
+    ));
+
+    let read_only = FileSandbox::new(base.join("mnt").to_str().unwrap(), true).unwrap();
+    assert!(matches!(
+        read_only.create("photos/x.jpg", false),
+        Err(FileError::Denied(_))
+    ));
+}
+
+#[test]
+fn test_walk_and_remove() {
+    let (base, sandbox) = setup("walk");
+
+    let paths = sandbox
+        .walk("/")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.path)
+        .collect::<Vec<_>>();
+    // Symlinks are returned, not followed.
+    assert_eq!(
+        paths,
+        vec![
+            "",
+            "escape",
+            "inside",
+            "photos",
+            "photos/a.jpg",
+            "photos/b.jpg",
+            "photos/c.jpg"
+        ]
+    );
+    let escape = sandbox.walk("escape").unwrap();
+    assert_eq!(escape.len(), 1);
+    assert!(escape[0].is_symlink && !escape[0].is_dir);
+
+    // The link is removed, not what it points to.
+    sandbox.remove("escape").unwrap();
+    assert!(base.join("secret").exists());
+    assert!(sandbox.remove("photos").is_err());
+
+    sandbox.rename("photos/c.jpg", "c.jpg").unwrap();
+    assert!(matches!(
+        sandbox.rename("photos/a.jpg", "c.jpg"),
+        Err(FileError::Exists(_))
+    ));
+}
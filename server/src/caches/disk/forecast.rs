@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::DiskCacheData;
+use crate::public::event_queue::EventNotifier;
+use crate::public::FillForecast;
+
+const STATE_FILE: &str = "usage_history.json";
+/// Seconds between two samples of a partition.
+const SAMPLE_INTERVAL: u64 = 3600;
+/// Seconds of samples kept.
+const HISTORY: u64 = 30 * 86400;
+/// Seconds of samples the trend is fitted on.
+const FIT_WINDOW: u64 = 14 * 86400;
+/// A trend needs samples over at least this many seconds.
+const MIN_SPAN: u64 = 6 * 3600;
+const MIN_SAMPLES: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(super) struct Sample {
+    pub(super) time: u64, // unix timestamp, in seconds
+    pub(super) used: u64, // in bytes
+}
+
+/// Growth of the used bytes in bytes per second, by a least squares fit of
+/// the samples of the last `FIT_WINDOW` seconds.
+pub(super) fn trend(samples: &[Sample], now: u64) -> Option<f64> {
+    let recent = samples
+        .iter()
+        .filter(|s| s.time + FIT_WINDOW >= now)
+        .collect::<Vec<_>>();
+    let (first, last) = (recent.first()?, recent.last()?);
+    if recent.len() < MIN_SAMPLES || last.time - first.time < MIN_SPAN {
+        return None;
+    }
+
+    // Relative to the first sample, to keep precision.
+    let n = recent.len() as f64;
+    let xs = recent.iter().map(|s| (s.time - first.time) as f64);
+    let ys = recent.iter().map(|s| s.used as f64 - first.used as f64);
+    let mean_x = xs.clone().sum::<f64>() / n;
+    let mean_y = ys.clone().sum::<f64>() / n;
+    let (cov, var) = xs.zip(ys).fold((0.0, 0.0), |(cov, var), (x, y)| {
+        (
+            cov + (x - mean_x) * (y - mean_y),
+            var + (x - mean_x).powi(2),
+        )
+    });
+    match var > 0.0 {
+        true => Some(cov / var),
+        false => None,
+    }
+}
+
+/// Days until `available` bytes are used up at the current trend, None if
+/// usage is not growing or there is not enough history.
+pub(super) fn days_until_full(samples: &[Sample], available: u64, now: u64) -> Option<f64> {
+    let rate = trend(samples, now).filter(|r| *r > 0.0)?;
+    Some(available as f64 / rate / 86400.0)
+}
+
+/// Used and available bytes of the filesystem mounted at `path`.
+fn space(path: &str) -> io::Result<(u64, u64)> {
+    let path = CString::new(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let used = (stat.f_blocks - stat.f_bfree) as u64 * stat.f_frsize as u64;
+    Ok((used, stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Used bytes of every partition over time, kept across restarts, and the
+/// forecasts of the mounted ones.
+#[derive(Debug, Default)]
+pub(super) struct History {
+    path: Option<PathBuf>,
+    samples: HashMap<String, Vec<Sample>>,
+    forecasts: HashMap<String, FillForecast>,
+}
+
+impl History {
+    pub(super) fn load(state_dir: &str) -> Self {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let samples = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::error!("Cannot parse {:?}, starting empty: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                log::error!("Cannot read {:?}, starting empty: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            samples,
+            forecasts: HashMap::new(),
+        }
+    }
+
+    pub(super) fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string(&self.samples)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(super) fn forecasts(&self) -> Vec<FillForecast> {
+        let mut forecasts = self.forecasts.values().cloned().collect::<Vec<_>>();
+        forecasts.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        forecasts
+    }
+
+    /// Record the space of the mounted partition `uuid`, warning once it is
+    /// forecast to be full within `warn_days`. Returns whether a sample was
+    /// taken or the warning changed.
+    pub(super) fn record(
+        &mut self,
+        uuid: &str,
+        (used, available): (u64, u64),
+        now: u64,
+        warn_days: f64,
+    ) -> bool {
+        let samples = self.samples.entry(uuid.to_owned()).or_default();
+        let sampled = samples
+            .last()
+            .is_none_or(|s| s.time + SAMPLE_INTERVAL <= now || s.time > now);
+        if sampled {
+            samples.push(Sample { time: now, used });
+            samples.retain(|s| s.time + HISTORY >= now && s.time <= now);
+        }
+
+        let days_until_full = days_until_full(samples, available, now);
+        let warning = warn_days > 0.0 && days_until_full.is_some_and(|d| d < warn_days);
+        let warned = self.forecasts.get(uuid).is_some_and(|f| f.warning);
+        if warning && !warned {
+            log::warn!(
+                "DiskCache - Partition {} is forecast to be full in {:.1} days",
+                uuid,
+                days_until_full.unwrap_or_default()
+            );
+        }
+
+        self.forecasts.insert(
+            uuid.to_owned(),
+            FillForecast {
+                uuid: uuid.to_owned(),
+                used,
+                available,
+                days_until_full,
+                warning,
+            },
+        );
+        sampled || warning != warned
+    }
+
+    /// Forget the forecasts of partitions not in `mounted`, their history
+    /// is kept.
+    fn retain(&mut self, mounted: &[(String, String)]) -> bool {
+        let count = self.forecasts.len();
+        self.forecasts
+            .retain(|uuid, _| mounted.iter().any(|(u, _)| u == uuid));
+        self.forecasts.len() != count
+    }
+}
+
+/// Sample the space of the mounted partitions, watchers are notified when
+/// forecasts change.
+pub(super) fn check(
+    data: &DiskCacheData,
+    event_notifier: &EventNotifier,
+    warn_days: f64,
+    now: u64,
+) {
+    let mounted = data
+        .data
+        .lock()
+        .unwrap()
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .filter_map(|p| Some((p.uuid.to_string(), p.mount_point()?.to_owned())))
+        .collect::<Vec<_>>();
+
+    let mut history = data.history.lock().unwrap();
+    let mut changed = history.retain(&mounted);
+    let mut sampled = false;
+    for (uuid, mount_point) in &mounted {
+        match space(mount_point) {
+            Ok(space) => sampled |= history.record(uuid, space, now, warn_days),
+            Err(e) => log::warn!("DiskCache - Cannot read space of {}: {}", mount_point, e),
+        }
+    }
+    if sampled {
+        if let Err(e) = history.save() {
+            log::error!("DiskCache - Cannot save usage history: {}", e);
+        }
+    }
+    changed |= sampled;
+    drop(history);
+
+    if changed {
+        data.publish(event_notifier);
+    }
+}
+
+#[cfg(test)]
+#[path = "./forecast_test.rs"]
+mod forecast_test;
@@ -0,0 +1,76 @@
+use super::*;
+
+const GB: u64 = 1 << 30;
+const DAY: u64 = 86400;
+
+fn samples(count: u64, step: u64, per_step: u64) -> Vec<Sample> {
+    (0..count)
+        .map(|i| Sample {
+            time: 1_000_000 + i * step,
+            used: 10 * GB + i * per_step,
+        })
+        .collect()
+}
+
+#[test]
+fn test_trend() {
+    // 1 GiB a day, sampled hourly.
+    let history = samples(48, 3600, GB / 24);
+    let now = history.last().unwrap().time;
+    let rate = trend(&history, now).unwrap();
+    assert!((rate * DAY as f64 - GB as f64).abs() < 1e3);
+
+    let days = days_until_full(&history, 5 * GB, now).unwrap();
+    assert!((days - 5.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_not_enough_history() {
+    let history = samples(2, 3600, GB);
+    let now = history.last().unwrap().time;
+    assert_eq!(trend(&history, now), None);
+
+    let history = samples(10, 60, GB);
+    let now = history.last().unwrap().time;
+    assert_eq!(trend(&history, now), None);
+
+    // Old samples are out of the window.
+    let history = samples(48, 3600, GB);
+    assert_eq!(trend(&history, 1_000_000 + 30 * DAY), None);
+}
+
+#[test]
+fn test_shrinking_never_full() {
+    let history = (0..48)
+        .map(|i| Sample {
+            time: i * 3600,
+            used: 100 * GB - i * GB,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(days_until_full(&history, GB, 47 * 3600), None);
+}
+
+#[test]
+fn test_record_warns_once() {
+    let mut history = History::default();
+    let mut now = 1_000_000;
+    let mut used = 10 * GB;
+    let mut changes = Vec::new();
+    for _ in 0..24 {
+        changes.push(history.record("uuid", (used, 20 * GB - used), now, 7.0));
+        // Polled again before the next sample is due.
+        assert!(!history.record("uuid", (used, 20 * GB - used), now + 60, 7.0));
+        now += SAMPLE_INTERVAL;
+        used += GB / 4;
+    }
+    assert!(changes.iter().all(|c| *c));
+    assert_eq!(history.samples["uuid"].len(), 24);
+
+    let forecast = &history.forecasts()[0];
+    assert!(forecast.warning);
+    assert!(forecast.days_until_full.unwrap() < 7.0);
+
+    // Usage drops, the warning clears.
+    assert!(history.record("uuid", (GB, 19 * GB), now + 60, 7.0));
+    assert!(!history.forecasts()[0].warning);
+}
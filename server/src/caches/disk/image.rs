@@ -0,0 +1,362 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+use super::backup::snapshot_name;
+use super::checksum::{self, HashWriter};
+use super::direct::read_exact_at;
+use super::files::FileSandbox;
+use super::scan::read_attr_u64;
+use super::{ext4, DiskCacheData};
+use crate::public::job::{JobState, JobToken};
+use crate::public::{FileError, ImageProgress};
+
+/// The boot SD card of the Pi.
+pub(super) const SD_CARD: &str = "mmcblk0";
+const IMAGE_DIR: &str = "picontrolx-images";
+const SIDECAR_EXTENSION: &str = ".sha256";
+const CHUNK_SIZE: usize = 1 << 20; // 1 MiB per read
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+enum Stop {
+    Cancelled,
+    Failed(String),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Failed(e.to_string())
+    }
+}
+
+impl From<FileError> for Stop {
+    fn from(e: FileError) -> Self {
+        Stop::Failed(e.to_string())
+    }
+}
+
+/// Zero the parts of `buf`, read at `offset`, which fall in the free
+/// `ranges`, skipping ranges which end before it. `next` is the first range
+/// still to consider, ranges are sorted and chunks come in order. Returns
+/// the number of bytes zeroed.
+pub(super) fn zero_free(
+    buf: &mut [u8],
+    offset: u64,
+    ranges: &[(u64, u64)],
+    next: &mut usize,
+) -> u64 {
+    let end = offset + buf.len() as u64;
+    while *next < ranges.len() && ranges[*next].0 + ranges[*next].1 <= offset {
+        *next += 1;
+    }
+
+    let mut zeroed = 0;
+    for &(start, len) in ranges[*next..].iter().take_while(|(s, _)| *s < end) {
+        let from = start.max(offset);
+        let to = (start + len).min(end);
+        buf[(from - offset) as usize..(to - offset) as usize].fill(0);
+        zeroed += to - from;
+    }
+    zeroed
+}
+
+/// Mount points in `mountinfo` (/proc/self/mountinfo) of the `devices`,
+/// as major:minor, which are mounted read-write.
+pub(super) fn read_write_mounts(mountinfo: &str, devices: &[String]) -> Vec<String> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (device, mount_point, options) = (fields.get(2)?, fields.get(4)?, fields.get(5)?);
+            let read_write = options.split(',').any(|o| o == "rw");
+            if read_write && devices.iter().any(|d| d == device) {
+                Some(mount_point.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// major:minor of the SD card and of its partitions.
+fn card_devices() -> Vec<String> {
+    let dir = format!("/sys/block/{}", SD_CARD);
+    let partitions = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with(SD_CARD))
+                .map(|name| format!("{}/{}", dir, name))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    std::iter::once(dir)
+        .chain(partitions)
+        .filter_map(|dir| fs::read_to_string(format!("{}/dev", dir)).ok())
+        .map(|dev| dev.trim().to_owned())
+        .collect()
+}
+
+/// The checksum in a sidecar in `sha256sum` format.
+pub(super) fn parse_sidecar(content: &str) -> Option<&str> {
+    let hash = content.split_whitespace().next()?;
+    match hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Some(hash),
+        false => None,
+    }
+}
+
+struct ImageJob {
+    token: JobToken,
+    progress: watch::Sender<ImageProgress>,
+    current: ImageProgress,
+    last_publish: Instant,
+}
+
+impl ImageJob {
+    fn publish(&mut self, force: bool) {
+        if !force && self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    /// Publish a pause or resume of the job.
+    fn paused(&mut self, paused: bool) {
+        self.current.state = match paused {
+            true => JobState::Paused,
+            false => JobState::Running,
+        };
+        self.publish(true);
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Stop> {
+        let token = self.token.clone();
+        token
+            .checkpoint_with(|paused| self.paused(paused))
+            .map_err(|_| Stop::Cancelled)
+    }
+
+    /// Compress `current.total` bytes of `device` into `out`, with the
+    /// `free` ranges zeroed instead of read. Returns the checksum of the
+    /// compressed image.
+    fn backup<W: Write>(
+        &mut self,
+        device: &fs::File,
+        free: &[(u64, u64)],
+        out: W,
+    ) -> Result<String, Stop> {
+        let mut encoder = GzEncoder::new(HashWriter::new(out), Compression::fast());
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut next = 0;
+        while self.current.done < self.current.total {
+            self.checkpoint()?;
+
+            let offset = self.current.done;
+            let len = (self.current.total - offset).min(CHUNK_SIZE as u64) as usize;
+            let chunk = &mut buf[..len];
+            while free.get(next).is_some_and(|&(s, l)| s + l <= offset) {
+                next += 1;
+            }
+            let unused = free
+                .get(next)
+                .is_some_and(|&(s, l)| s <= offset && offset + len as u64 <= s + l);
+            if unused {
+                chunk.fill(0);
+                self.current.skipped += len as u64;
+            } else {
+                read_exact_at(device, chunk, offset)?;
+                self.current.skipped += zero_free(chunk, offset, free, &mut next);
+            }
+            encoder.write_all(chunk)?;
+
+            self.current.done += len as u64;
+            self.publish(false);
+        }
+        Ok(encoder.finish()?.finish())
+    }
+
+    /// Check `image` against the checksum in `sidecar`.
+    fn verify(&mut self, image: fs::File, sidecar: &str) -> Result<(), Stop> {
+        let expected = parse_sidecar(sidecar)
+            .ok_or_else(|| Stop::Failed(String::from("Invalid checksum file")))?
+            .to_ascii_lowercase();
+        self.current.total = image.metadata()?.len();
+        self.publish(true);
+
+        let token = self.token.clone();
+        let mut cancelled = false;
+        let actual = checksum::sha256(image, |chunk| {
+            if token.checkpoint_with(|paused| self.paused(paused)).is_err() {
+                cancelled = true;
+                return Err(io::Error::other("cancelled"));
+            }
+            self.current.done += chunk.len() as u64;
+            self.publish(false);
+            Ok(())
+        });
+        if cancelled {
+            return Err(Stop::Cancelled);
+        }
+        self.current.checksum = actual?;
+        if self.current.checksum != expected {
+            return Err(Stop::Failed(format!(
+                "Checksum mismatch, {} != {}",
+                self.current.checksum, expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn spawn(
+    data: &DiskCacheData,
+    current: ImageProgress,
+    run: impl FnOnce(&mut ImageJob) -> Result<(), Stop> + Send + 'static,
+) -> watch::Receiver<ImageProgress> {
+    let (tx, rx) = watch::channel(current.clone());
+    let mut job = ImageJob {
+        token: data.jobs.register(match current.verify {
+            true => "image verify",
+            false => "image backup",
+        }),
+        progress: tx,
+        current,
+        last_publish: Instant::now(),
+    };
+    job.current.job_id = job.token.id();
+    let _ = job.progress.send(job.current.clone());
+
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        job.current.state = match run(&mut job) {
+            Ok(()) => JobState::Finished,
+            Err(Stop::Cancelled) => JobState::Cancelled,
+            Err(Stop::Failed(e)) => {
+                log::error!(
+                    "DiskCache - Image job on {} failed: {}",
+                    job.current.path,
+                    e
+                );
+                job.current.error = Some(e);
+                JobState::Failed
+            }
+        };
+        log::info!(
+            "DiskCache - Image job on {} done: {:?}",
+            job.current.path,
+            job.current.state
+        );
+
+        data.jobs.unregister(&job.token);
+        job.publish(true);
+    });
+    rx
+}
+
+/// Image the SD card into a new compressed file on `target`, zeroing blocks
+/// which are free in its ext filesystems if `skip_unused`. Blocks are taken
+/// as free when the job starts reading. A filesystem of the card mounted
+/// read-write, like the root of a running system, is synced first but keeps
+/// changing: blocks it allocates meanwhile are zeroed in the image. Such an
+/// image is best effort, its `live_mounts` tell so.
+pub(super) fn start_backup(
+    data: &DiskCacheData,
+    target: FileSandbox,
+    uuid: &str,
+    skip_unused: bool,
+) -> Result<watch::Receiver<ImageProgress>, String> {
+    let total = read_attr_u64(SD_CARD, "size").unwrap_or(0) << 9; // * 512
+    if total == 0 {
+        return Err(format!("No {} device", SD_CARD));
+    }
+    let device = fs::File::open(format!("/dev/{}", SD_CARD))
+        .map_err(|e| format!("Cannot open {}: {}", SD_CARD, e))?;
+    let live_mounts = match skip_unused {
+        true => {
+            let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+                .map_err(|e| format!("Cannot read mounts: {}", e))?;
+            read_write_mounts(&mountinfo, &card_devices())
+        }
+        false => Vec::new(),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = format!("{}/{}-{}.img.gz", IMAGE_DIR, SD_CARD, snapshot_name(now));
+    target.create_dir(IMAGE_DIR).map_err(|e| e.to_string())?;
+    let upload = target.create(&path, false).map_err(|e| e.to_string())?;
+
+    let current = ImageProgress {
+        device: String::from(SD_CARD),
+        uuid: uuid.to_owned(),
+        path,
+        total,
+        live_mounts,
+        ..Default::default()
+    };
+    Ok(spawn(data, current, move |job| {
+        log::info!("DiskCache - Imaging {} into {}", SD_CARD, job.current.path);
+        if !job.current.live_mounts.is_empty() {
+            log::warn!(
+                "DiskCache - Skipping unused blocks of {} while {} is mounted read-write, the image is best effort",
+                SD_CARD,
+                job.current.live_mounts.join(", ")
+            );
+            unsafe { libc::sync() };
+        }
+        let free = match skip_unused {
+            true => ext4::free_ranges(&device).map_err(|e| {
+                Stop::Failed(format!("Cannot read free blocks of {}: {}", SD_CARD, e))
+            })?,
+            false => Vec::new(),
+        };
+        let mut upload = upload;
+        job.current.checksum = job.backup(&device, &free, upload.file())?;
+        upload.commit()?;
+
+        let name = job.current.path.rsplit('/').next().unwrap_or_default();
+        let sidecar = format!("{}  {}\n", job.current.checksum, name);
+        let mut upload =
+            target.create(&format!("{}{}", job.current.path, SIDECAR_EXTENSION), true)?;
+        upload.file().write_all(sidecar.as_bytes())?;
+        upload.commit()?;
+        Ok(())
+    }))
+}
+
+/// Check the image at `path` on `target` against its checksum sidecar.
+pub(super) fn start_verify(
+    data: &DiskCacheData,
+    target: FileSandbox,
+    uuid: &str,
+    path: &str,
+) -> Result<watch::Receiver<ImageProgress>, String> {
+    let image = target.open_read(path).map_err(|e| e.to_string())?;
+    let mut sidecar = String::new();
+    target
+        .open_read(&format!("{}{}", path, SIDECAR_EXTENSION))
+        .map_err(|e| e.to_string())
+        .and_then(|mut f| {
+            f.read_to_string(&mut sidecar)
+                .map_err(|e| format!("Cannot read checksum file: {}", e))
+        })?;
+
+    let current = ImageProgress {
+        verify: true,
+        uuid: uuid.to_owned(),
+        path: path.to_owned(),
+        ..Default::default()
+    };
+    Ok(spawn(data, current, move |job| job.verify(image, &sidecar)))
+}
+
+#[cfg(test)]
+#[path = "./image_test.rs"]
+mod image_test;
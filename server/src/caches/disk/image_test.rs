@@ -0,0 +1,100 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+use flate2::read::GzDecoder;
+
+fn new_job() -> ImageJob {
+    let (tx, _rx) = watch::channel(ImageProgress::default());
+    ImageJob {
+        token: Jobs::default().register("test"),
+        progress: tx,
+        current: ImageProgress::default(),
+        last_publish: Instant::now(),
+    }
+}
+
+#[test]
+fn test_zero_free() {
+    let ranges = [(2, 3), (8, 4), (14, 1)];
+    let mut next = 0;
+
+    let mut buf = [1u8; 10];
+    assert_eq!(zero_free(&mut buf, 0, &ranges, &mut next), 5);
+    assert_eq!(buf, [1, 1, 0, 0, 0, 1, 1, 1, 0, 0]);
+
+    let mut buf = [1u8; 10];
+    assert_eq!(zero_free(&mut buf, 10, &ranges, &mut next), 3);
+    assert_eq!(buf, [0, 0, 1, 1, 0, 1, 1, 1, 1, 1]);
+    assert_eq!(next, 1);
+}
+
+#[test]
+fn test_parse_sidecar() {
+    let hash = "ab".repeat(32);
+    assert_eq!(
+        parse_sidecar(&format!("{}  mmcblk0.img.gz\n", hash)),
+        Some(hash.as_str())
+    );
+    assert_eq!(parse_sidecar("abc  mmcblk0.img.gz\n"), None);
+    assert_eq!(parse_sidecar(""), None);
+}
+
+#[test]
+fn test_backup_and_verify() {
+    let dir = TempDir::new("image");
+    let path = dir.join("image");
+    let content = (0..3 * CHUNK_SIZE + 100)
+        .map(|i| (i % 251) as u8 + 1)
+        .collect::<Vec<_>>();
+    fs::write(&path, &content).unwrap();
+    let device = fs::File::open(&path).unwrap();
+
+    // A whole chunk, and part of the last one, are free.
+    let chunk = CHUNK_SIZE as u64;
+    let free = [(chunk - 10, chunk + 10), (3 * chunk + 50, 50)];
+    let mut job = new_job();
+    job.current.total = content.len() as u64;
+    let mut image = Vec::new();
+    let hash = job.backup(&device, &free, &mut image).ok().unwrap();
+    assert_eq!(job.current.done, content.len() as u64);
+    assert_eq!(job.current.skipped, chunk + 60);
+
+    let mut restored = Vec::new();
+    GzDecoder::new(image.as_slice())
+        .read_to_end(&mut restored)
+        .unwrap();
+    let mut expected = content;
+    expected[CHUNK_SIZE - 10..2 * CHUNK_SIZE].fill(0);
+    expected[3 * CHUNK_SIZE + 50..].fill(0);
+    assert!(restored == expected);
+
+    fs::write(&path, &image).unwrap();
+    let sidecar = format!("{}  image.img.gz\n", hash);
+    let mut job = new_job();
+    assert!(job.verify(fs::File::open(&path).unwrap(), &sidecar).is_ok());
+    assert_eq!(job.current.checksum, hash);
+
+    let sidecar = format!("{}  image.img.gz\n", "0".repeat(64));
+    let mut job = new_job();
+    assert!(job
+        .verify(fs::File::open(&path).unwrap(), &sidecar)
+        .is_err());
+}
+
+#[test]
+fn test_read_write_mounts() {
+    let mountinfo = "\
+22 1 179:2 / / rw,noatime shared:1 - ext4 /dev/root rw
+23 22 179:1 / /boot ro,relatime shared:2 - vfat /dev/mmcblk0p1 ro
+24 22 8:1 / /mnt/photos rw,relatime shared:3 - ext4 /dev/sda1 rw
+25 22 0:21 / /proc rw,nosuid shared:4 - proc proc rw
+";
+    let card = vec![
+        String::from("179:0"),
+        String::from("179:1"),
+        String::from("179:2"),
+    ];
+    // The root shows as /dev/root, it is found by its device number.
+    assert_eq!(read_write_mounts(mountinfo, &card), vec!["/"]);
+    assert!(read_write_mounts(mountinfo, &card[..2]).is_empty());
+}
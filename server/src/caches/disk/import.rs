@@ -0,0 +1,353 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use super::fileops::numbered;
+use super::{checksum, exif, share, DiskCacheData, FileSandbox};
+use crate::config::DiskConfig;
+use crate::public::event_queue::EventNotifier;
+use crate::public::job::{JobState, JobToken};
+use crate::public::time::utc_datetime;
+use crate::public::ImportProgress;
+
+const STATE_FILE: &str = "imported.json";
+const CARD_DIR: &str = "DCIM";
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "heic", "heif", "png", "dng", "cr2", "cr3", "nef", "arw", "orf", "rw2", "raf",
+    "mp4", "mov", "avi", "mts", "m2ts", "m4v", "3gp",
+];
+/// Finished imports still reported to watchers.
+const KEEP_FINISHED: usize = 5;
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Hashes of every file imported so far, so a file is imported only once
+/// whatever card it comes from, plus the imports reported to watchers.
+#[derive(Debug, Default)]
+pub(super) struct Imports {
+    path: Option<PathBuf>,
+    hashes: HashSet<String>,
+    progress: Vec<ImportProgress>,
+}
+
+impl Imports {
+    pub(super) fn load(state_dir: &str) -> Self {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let hashes = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<Vec<String>>(&content).unwrap_or_else(|e| {
+                log::error!("Cannot parse {:?}, starting empty: {}", path, e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::error!("Cannot read {:?}, starting empty: {}", path, e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            hashes: hashes.into_iter().collect(),
+            progress: Vec::new(),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut hashes = self.hashes.iter().collect::<Vec<_>>();
+        hashes.sort();
+        let content = serde_json::to_string(&hashes)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(super) fn progress(&self) -> Vec<ImportProgress> {
+        self.progress.clone()
+    }
+
+    fn update(&mut self, current: &ImportProgress) {
+        match self
+            .progress
+            .iter_mut()
+            .find(|p| p.job_id == current.job_id)
+        {
+            Some(p) => *p = current.clone(),
+            None => self.progress.push(current.clone()),
+        }
+
+        let finished = self.progress.iter().filter(|p| p.state.is_done()).count();
+        let mut extra = finished.saturating_sub(KEEP_FINISHED);
+        self.progress.retain(|p| {
+            let drop = extra > 0 && p.state.is_done();
+            extra -= drop as usize;
+            !drop
+        });
+    }
+}
+
+pub(super) fn is_media(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| MEDIA_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// `library/YYYY/YYYY-MM-DD`
+pub(super) fn date_dir(library: &Path, (year, month, day): (i64, u32, u32)) -> PathBuf {
+    library
+        .join(format!("{:04}", year))
+        .join(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+fn date_of(path: &Path, meta: &fs::Metadata) -> (i64, u32, u32) {
+    exif::date_taken(path).unwrap_or_else(|| {
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let (year, month, day, ..) = utc_datetime(mtime);
+        (year, month, day)
+    })
+}
+
+enum Stop {
+    Cancelled,
+    Failed(String),
+}
+
+struct Import {
+    token: JobToken,
+    data: DiskCacheData,
+    event_notifier: EventNotifier,
+    current: ImportProgress,
+    last_publish: Instant,
+    library: PathBuf,
+}
+
+impl Import {
+    /// Report progress to watchers through the disk service data.
+    fn publish(&mut self, force: bool) {
+        if !force && self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        self.data.imports.lock().unwrap().update(&self.current);
+        self.data.publish(&self.event_notifier);
+    }
+
+    /// Publish a pause or resume of the import.
+    fn paused(&mut self, paused: bool) {
+        self.current.state = match paused {
+            true => JobState::Paused,
+            false => JobState::Running,
+        };
+        self.publish(true);
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Stop> {
+        let token = self.token.clone();
+        token
+            .checkpoint_with(|paused| self.paused(paused))
+            .map_err(|_| Stop::Cancelled)
+    }
+
+    /// Copy `src` into the library, returns false if it was imported before.
+    fn import_file(&mut self, src: &Path) -> Result<bool, Stop> {
+        let token = self.token.clone();
+        let checkpoint = |_: &[u8]| {
+            token
+                .checkpoint_with(|paused| self.paused(paused))
+                .map_err(|_| io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+        };
+        let hash = fs::File::open(src)
+            .and_then(|file| checksum::sha256(file, checkpoint))
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::Interrupted => Stop::Cancelled,
+                _ => Stop::Failed(e.to_string()),
+            })?;
+        if self.data.imports.lock().unwrap().hashes.contains(&hash) {
+            return Ok(false);
+        }
+
+        let failed = |e: io::Error| Stop::Failed(e.to_string());
+        let meta = fs::metadata(src).map_err(failed)?;
+        let dir = date_dir(&self.library, date_of(src, &meta));
+        fs::create_dir_all(&dir).map_err(failed)?;
+
+        let name = src.file_name().unwrap_or_default().to_string_lossy();
+        let mut target = dir.join(name.as_ref());
+        let mut n = 1;
+        while target.exists() {
+            target = dir.join(numbered(&name, n));
+            n += 1;
+        }
+        let tmp = dir.join(format!(".{}.picontrolx-import", name));
+        let copied = fs::copy(src, &tmp)
+            .and_then(|_| {
+                let file = fs::OpenOptions::new().write(true).open(&tmp)?;
+                file.set_modified(meta.modified()?)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, &target));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&tmp);
+            return Err(failed(e));
+        }
+
+        self.data.imports.lock().unwrap().hashes.insert(hash);
+        Ok(true)
+    }
+
+    fn run(&mut self, card: &FileSandbox) -> Result<(), Stop> {
+        let files = card
+            .walk(CARD_DIR)
+            .map_err(|e| Stop::Failed(e.to_string()))?
+            .into_iter()
+            .filter(|e| !e.is_dir && !e.is_symlink && is_media(&e.path))
+            .collect::<Vec<_>>();
+        self.current.files_total = files.len() as u64;
+        self.current.bytes_total = files.iter().map(|e| e.size).sum();
+        self.publish(true);
+
+        for entry in files {
+            self.checkpoint()?;
+            let result = card
+                .resolve(&entry.path)
+                .map_err(|e| Stop::Failed(e.to_string()))
+                .and_then(|src| self.import_file(&src));
+            match result {
+                Ok(true) => self.current.imported += 1,
+                Ok(false) => self.current.duplicates += 1,
+                Err(Stop::Cancelled) => return Err(Stop::Cancelled),
+                Err(Stop::Failed(e)) => {
+                    log::warn!("DiskCache - Cannot import {}: {}", entry.path, e);
+                    self.current.failed += 1;
+                }
+            }
+            self.current.files_done += 1;
+            self.current.bytes_done += entry.size;
+            self.publish(false);
+        }
+        Ok(())
+    }
+}
+
+fn unmount(mount_point: &str) -> Result<(), String> {
+    let output = Command::new("umount")
+        .arg(mount_point)
+        .output()
+        .map_err(|e| format!("Cannot run umount: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "umount failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Import the camera card `uuid` mounted at `mount_point`, if importing is
+/// enabled and the card has a DCIM folder.
+pub(super) fn start(
+    data: &DiskCacheData,
+    event_notifier: &EventNotifier,
+    config: &DiskConfig,
+    uuid: &str,
+    mount_point: &str,
+) {
+    let library = match &config.import_library {
+        None => return,
+        Some(library) => PathBuf::from(library),
+    };
+    if !Path::new(mount_point).join(CARD_DIR).is_dir() {
+        return;
+    }
+    let card = match FileSandbox::new(mount_point, true) {
+        Ok(card) => card,
+        Err(e) => {
+            log::error!("DiskCache - Cannot open camera card {}: {}", mount_point, e);
+            return;
+        }
+    };
+
+    let token = data.jobs.register("import");
+    let mut import = Import {
+        current: ImportProgress {
+            job_id: token.id(),
+            uuid: uuid.to_owned(),
+            ..Default::default()
+        },
+        token,
+        data: data.clone(),
+        event_notifier: event_notifier.clone(),
+        last_publish: Instant::now(),
+        library,
+    };
+    let unmount_after = config.import_unmount;
+    let mount_point = mount_point.to_owned();
+    tokio::task::spawn_blocking(move || {
+        log::info!("DiskCache - Importing camera card {}", mount_point);
+        let result = import.run(&card);
+        if let Err(e) = import.data.imports.lock().unwrap().save() {
+            log::error!("DiskCache - Cannot save imported files: {}", e);
+        }
+
+        import.current.state = match result {
+            Ok(()) if import.current.failed == 0 => JobState::Finished,
+            Ok(()) => {
+                import.current.error = Some(format!("{} files failed", import.current.failed));
+                JobState::Failed
+            }
+            Err(Stop::Cancelled) => JobState::Cancelled,
+            Err(Stop::Failed(e)) => {
+                log::error!("DiskCache - Import of {} failed: {}", mount_point, e);
+                import.current.error = Some(e);
+                JobState::Failed
+            }
+        };
+        log::info!(
+            "DiskCache - Import of {} done, {} imported, {} already known",
+            mount_point,
+            import.current.imported,
+            import.current.duplicates
+        );
+
+        if unmount_after && import.current.state == JobState::Finished {
+            drop(card);
+            if let Err(e) = share::withdraw(&import.data, &import.current.uuid).run() {
+                log::warn!(
+                    "DiskCache - Cannot withdraw shares of {}: {}",
+                    mount_point,
+                    e
+                );
+            }
+            match unmount(&mount_point) {
+                Ok(()) => import.current.unmounted = true,
+                Err(e) => {
+                    import.current.error = Some(e);
+                    // Still mounted, share it again.
+                    let _ = share::apply(&import.data).run();
+                }
+            }
+        }
+
+        import.data.jobs.unregister(&import.token);
+        import.publish(true);
+    });
+}
+
+#[cfg(test)]
+#[path = "./import_test.rs"]
+mod import_test;
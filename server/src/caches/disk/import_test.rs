@@ -0,0 +1,41 @@
+use super::*;
+
+#[test]
+fn test_is_media() {
+    assert!(is_media("DCIM/100CANON/IMG_0001.JPG"));
+    assert!(is_media("DCIM/100GOPRO/GX010001.MP4"));
+    assert!(is_media("DCIM/a.cr3"));
+    assert!(!is_media("DCIM/100CANON/IMG_0001.THM"));
+    assert!(!is_media("DCIM/jpg"));
+}
+
+#[test]
+fn test_date_dir() {
+    assert_eq!(
+        date_dir(Path::new("/photos"), (2021, 3, 9)),
+        PathBuf::from("/photos/2021/2021-03-09")
+    );
+}
+
+#[test]
+fn test_keep_finished() {
+    let mut imports = Imports::default();
+    for job_id in 1..=8 {
+        imports.update(&ImportProgress {
+            job_id,
+            state: JobState::Finished,
+            ..Default::default()
+        });
+    }
+    imports.update(&ImportProgress {
+        job_id: 9,
+        ..Default::default()
+    });
+
+    let ids = imports
+        .progress()
+        .iter()
+        .map(|p| p.job_id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![4, 5, 6, 7, 8, 9]);
+}
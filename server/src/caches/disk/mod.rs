@@ -1,40 +1,193 @@
+use futures::future::BoxFuture;
 use log;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::sync::{Arc, Mutex};
-use uuid::Uuid;
+use tokio::sync::watch;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::time::{interval_at, sleep, Duration, Instant};
 
-use super::{Cache, CacheHandler};
+use super::{Cache, Schedule};
+use crate::config::{BackupConfig, Config, DiskConfig};
 use crate::public::event_queue::EventNotifier;
+use crate::public::job::{JobId, Jobs};
 use crate::public::shutdown;
-use crate::public::{DiskServiceData, ServiceData, ServiceType};
+use crate::public::{
+    BackupStatus, BenchProgress, DiskInfo, DiskServiceData, DuplicateFile, DuplicateProgress,
+    FileError, FileOp, FileOpProgress, FileOpRequest, ImageProgress, KnownDisk, LogicalVolume,
+    Partition, RaidArray, ScanProgress, ServiceType, Share, ShareProtocol, TrimResult,
+    UsageProgress,
+};
 
+mod backup;
+mod bench;
+mod checksum;
+mod direct;
+mod duplicates;
+mod exif;
+mod ext4;
 mod fetcher;
+mod fileops;
+mod files;
+mod forecast;
+mod image;
+mod import;
+mod raid;
+mod registry;
+mod scan;
+mod share;
+mod spindown;
+mod trim;
+mod usage;
+use backup::Backups;
+use duplicates::{Deletion, Results as DuplicateResults};
+pub(crate) use files::FileSandbox;
+use forecast::History;
+use import::Imports;
+use registry::KnownDisks;
+use share::{Shares, SystemRunner};
+use spindown::SpinTracker;
 
-const THIS_TYPE: ServiceType = ServiceType::DISK;
+pub(crate) const THIS_TYPE: ServiceType = ServiceType::new("disk");
+const RAID_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const SPIN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const DISK_POLL_INTERVAL: Duration = Duration::from_secs(60);
 
 struct DataGenerator {
     data: DiskCacheData,
     event_notifier: EventNotifier,
+    config: DiskConfig,
 }
 
 impl DataGenerator {
-    fn new(data: DiskCacheData, event_notifier: EventNotifier) -> Self {
+    fn new(data: DiskCacheData, event_notifier: EventNotifier, config: DiskConfig) -> Self {
         Self {
             event_notifier,
             data,
+            config,
         }
     }
 
     fn first_run(&mut self) {
-        fetcher::get_disks();
+        self.rescan(true);
+        self.check_space();
     }
 
-    async fn run(&mut self, mut shutdown: shutdown::Receiver) {
+    fn check_space(&self) {
+        let warn_days = self.config.fill_warning_days;
+        forecast::check(
+            &self.data,
+            &self.event_notifier,
+            warn_days,
+            registry::now_secs(),
+        );
+    }
+
+    /// Scan disks again, so disks plugged in or mounted since the last scan
+    /// are noticed. Watchers are notified if anything changed, or `force`.
+    fn rescan(&mut self, force: bool) {
+        let mut disks = fetcher::get_disks();
+
+        let mut known = self.data.known.lock().unwrap();
+        if known.observe(&disks, registry::now_secs()) {
+            if let Err(e) = known.save() {
+                log::error!("DiskCache - Cannot save known disks: {}", e);
+            }
+        }
+        drop(known);
+
+        let spin = self.data.spin.lock().unwrap();
+        for disk in disks.disks.iter_mut() {
+            disk.spin_state = spin.state(&disk.kernel);
+        }
+        drop(spin);
+
+        let mut data = self.data.data.lock().unwrap();
+        track_raids(&data.raids, &mut disks.raids);
+        if !force && *data == disks {
+            return;
+        }
+        let inserted = if force {
+            Vec::new()
+        } else {
+            newly_mounted(&data, &disks)
+        };
+        *data = disks;
+        drop(data);
+
+        let reload = share::apply(&self.data);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = reload.run() {
+                log::error!("DiskCache - Cannot update shares: {}", e);
+            }
+        });
+        for (uuid, mount_point) in inserted {
+            import::start(
+                &self.data,
+                &self.event_notifier,
+                &self.config,
+                &uuid,
+                &mount_point,
+            );
+        }
+
+        self.data.publish(&self.event_notifier);
+    }
+
+    /// RAID state changes without any device being added or removed, so
+    /// arrays are polled to notice degradation and sync progress.
+    fn check_raids(&mut self) {
+        let mut raids = raid::get_raid_arrays();
+        let mut data = self.data.data.lock().unwrap();
+        track_raids(&data.raids, &mut raids);
+        if data.raids == raids {
+            return;
+        }
+        data.raids = raids;
+        drop(data);
+
+        self.data.publish(&self.event_notifier);
+    }
+
+    async fn run(&mut self, mut shutdown: shutdown::Receiver, schedule: Schedule) {
         log::info!("DiskCache - Data generator is running...");
         self.first_run();
+
+        // An interval of 0 disables scheduled trim, the timer is still
+        // created but its branch is never polled.
+        let trim_enabled = self.config.trim_interval > 0;
+        let trim_period = Duration::from_secs(self.config.trim_interval.max(1));
+        let mut trim_timer = interval_at(Instant::now() + trim_period, trim_period);
+        let mut spin_timer = interval_at(Instant::now() + SPIN_POLL_INTERVAL, SPIN_POLL_INTERVAL);
+        // Backups missed while the server was down run right away.
+        backup::check(&self.data);
+
         loop {
             tokio::select! {
+                _ = sleep(RAID_POLL_INTERVAL) => {
+                    self.check_raids();
+                }
+                _ = trim_timer.tick(), if trim_enabled => {
+                    log::info!("DiskCache - Scheduled trim starts");
+                    let notifier = self.event_notifier.clone();
+                    tokio::spawn(trim::run(self.data.clone(), notifier, None));
+                }
+                _ = spin_timer.tick() => {
+                    let timeouts = &self.config.idle_timeouts;
+                    spindown::check(&self.data, &self.event_notifier, timeouts).await;
+                }
+                refresh = schedule.next() => {
+                    self.rescan(false);
+                    self.check_space();
+                    backup::check(&self.data);
+                    // Answer with the latest state, even if unchanged.
+                    if refresh.requested() {
+                        refresh.done(self.data.publish(&self.event_notifier));
+                    }
+                }
                 _ = shutdown.wait_on() => {
                     log::warn!("Disk Cache - Data generator is shutting down");
+                    self.data.jobs.cancel_all();
                     break;
                 }
             }
@@ -42,61 +195,408 @@ impl DataGenerator {
     }
 }
 
-#[derive(Clone, Debug, Default)]
-struct Partition {
-    kernel: String,
-    size: u64,
-    uuid: Uuid,
-    label: String,
-    mount_path: Option<Vec<String>>,
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Disks {
+    disks: Vec<DiskInfo>,
+    raids: Vec<RaidArray>,
+    volumes: Vec<LogicalVolume>,
 }
 
-#[derive(Clone, Debug, Default)]
-struct DiskInfo {
-    kernel: String,
-    size: u64,        // in kb
-    partitions: Vec<Partition>,
-    // uuid: String,
-    // mount_path: Option<String>,
+/// Warn about the arrays of `new` which were not degraded in `old`, and
+/// tell since when arrays are degraded.
+fn track_raids(old: &[RaidArray], new: &mut [RaidArray]) {
+    for array in raid::newly_degraded(old, new) {
+        log::warn!(
+            "DiskCache - RAID array {} is degraded, failed members: {:?}",
+            array.kernel,
+            array.failed
+        );
+    }
+    raid::carry_degraded_since(old, new, registry::now_secs());
 }
 
-#[derive(Clone, Debug, Default)]
-struct Disks {
-    disks: Vec<DiskInfo>,
+/// (uuid, mount point) of partitions mounted in `new` but not in `old`.
+fn newly_mounted(old: &Disks, new: &Disks) -> Vec<(String, String)> {
+    new.disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .filter_map(|p| Some((p.uuid.to_string(), p.mount_point()?.to_owned())))
+        .filter(|(uuid, _)| {
+            old.find_partition(uuid)
+                .and_then(|(_, p)| p.mount_point())
+                .is_none()
+        })
+        .collect()
+}
+
+impl Disks {
+    fn find_partition(&self, uuid: &str) -> Option<(&DiskInfo, &Partition)> {
+        self.disks.iter().find_map(|d| {
+            d.partitions
+                .iter()
+                .find(|p| p.uuid.to_string() == uuid)
+                .map(|p| (d, p))
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct DiskCacheData {
     data: Arc<Mutex<Disks>>,
+    known: Arc<Mutex<KnownDisks>>,
+    trimming: Arc<TokioMutex<()>>,
+    spin: Arc<Mutex<SpinTracker>>,
+    jobs: Jobs,
+    scanning: Arc<Mutex<HashSet<String>>>,
+    backups: Arc<Mutex<Backups>>,
+    imports: Arc<Mutex<Imports>>,
+    shares: Arc<Mutex<Shares>>,
+    usage: Arc<Mutex<HashMap<String, UsageProgress>>>,
+    duplicates: Arc<Mutex<DuplicateResults>>,
+    history: Arc<Mutex<History>>,
 }
 
 impl DiskCacheData {
-    fn new() -> Self {
+    fn new(
+        known: KnownDisks,
+        backups: Backups,
+        imports: Imports,
+        shares: Shares,
+        history: History,
+    ) -> Self {
         Self {
             data: Arc::new(Mutex::new(Disks::default())),
+            known: Arc::new(Mutex::new(known)),
+            trimming: Arc::new(TokioMutex::new(())),
+            spin: Arc::new(Mutex::new(SpinTracker::default())),
+            jobs: Jobs::default(),
+            scanning: Arc::new(Mutex::new(HashSet::new())),
+            backups: Arc::new(Mutex::new(backups)),
+            imports: Arc::new(Mutex::new(imports)),
+            shares: Arc::new(Mutex::new(shares)),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+            duplicates: Arc::new(Mutex::new(DuplicateResults::default())),
+            history: Arc::new(Mutex::new(history)),
+        }
+    }
+
+    /// Save the known disks and the usage history, on shutdown.
+    fn save(&self) {
+        if let Err(e) = self.known.lock().unwrap().save() {
+            log::error!("DiskCache - Cannot save known disks: {}", e);
+        }
+        if let Err(e) = self.history.lock().unwrap().save() {
+            log::error!("DiskCache - Cannot save usage history: {}", e);
         }
     }
+
+    /// Current state of the service, as sent to watchers.
+    fn snapshot(&self) -> DiskServiceData {
+        let data = self.data.lock().unwrap().clone();
+        let known = self.known.lock().unwrap().list();
+        let imports = self.imports.lock().unwrap().progress();
+        let shares = self.shares.lock().unwrap().active();
+        let forecasts = self.history.lock().unwrap().forecasts();
+        DiskServiceData {
+            disks: data.disks,
+            raids: data.raids,
+            volumes: data.volumes,
+            known,
+            imports,
+            shares,
+            forecasts,
+        }
+    }
+
+    /// Push the current state to watchers, returns its sequence number.
+    fn publish(&self, event_notifier: &EventNotifier) -> u64 {
+        event_notifier.push(THIS_TYPE, || Arc::new(self.snapshot()))
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct DiskCacheHandler {
-    service_type: ServiceType,
     data: DiskCacheData,
+    event_notifier: EventNotifier,
 }
 
 impl DiskCacheHandler {
     pub(crate) fn disk_mount(&self) {
         println!("================ disk_mount ===============");
     }
-}
 
-impl CacheHandler for DiskCacheHandler {
-    fn fetch(&self) -> ServiceData {
-        ServiceData::Disk(DiskServiceData { data: 0 })
+    /// Assign a nickname and notes to a known disk, identified by uuid.
+    pub(crate) fn set_nickname(
+        &self,
+        uuid: &str,
+        nickname: &str,
+        notes: &str,
+    ) -> Result<(), String> {
+        let mut known = self.data.known.lock().unwrap();
+        if !known.set_nickname(uuid, nickname, notes) {
+            return Err(format!("Unknown disk {}", uuid));
+        }
+        if let Err(e) = known.save() {
+            log::error!("DiskCache - Cannot save known disks: {}", e);
+            return Err(format!("Cannot save known disks: {}", e));
+        }
+        drop(known);
+
+        // Let watchers see the new nickname.
+        self.data.publish(&self.event_notifier);
+        Ok(())
     }
 
-    fn get_type(&self) -> ServiceType {
-        self.service_type
+    /// Forbid or allow modifications through the file API on a partition.
+    pub(crate) fn set_read_only(&self, uuid: &str, read_only: bool) -> Result<(), String> {
+        let mut known = self.data.known.lock().unwrap();
+        if !known.set_read_only(uuid, read_only) {
+            return Err(format!("Unknown disk {}", uuid));
+        }
+        if let Err(e) = known.save() {
+            log::error!("DiskCache - Cannot save known disks: {}", e);
+            return Err(format!("Cannot save known disks: {}", e));
+        }
+        drop(known);
+
+        // Let watchers see the new setting.
+        self.data.publish(&self.event_notifier);
+        Ok(())
+    }
+
+    fn mount_point(&self, uuid: &str) -> Result<String, FileError> {
+        let disks = self.data.data.lock().unwrap();
+        let (_, part) = disks
+            .find_partition(uuid)
+            .ok_or_else(|| FileError::NotFound(format!("Unknown disk {}", uuid)))?;
+        part.mount_point()
+            .map(String::from)
+            .ok_or_else(|| FileError::Invalid(format!("{} is not mounted", part.kernel)))
+    }
+
+    /// File access confined to the mount point of partition `uuid`.
+    pub(crate) fn file_sandbox(&self, uuid: &str) -> Result<FileSandbox, FileError> {
+        let mount_point = self.mount_point(uuid)?;
+        let read_only = self.data.known.lock().unwrap().is_read_only(uuid);
+        FileSandbox::new(&mount_point, read_only)
+    }
+
+    /// Start a copy, move or delete job between managed mounts.
+    pub(crate) fn file_op(
+        &self,
+        request: FileOpRequest,
+    ) -> Result<watch::Receiver<FileOpProgress>, FileError> {
+        let source = self.file_sandbox(&request.source)?;
+        let target = match request.op {
+            FileOp::Delete => None,
+            FileOp::Copy | FileOp::Move => Some(self.file_sandbox(&request.target)?),
+        };
+        fileops::start(&self.data, request, source, target)
+    }
+
+    /// Start imaging the SD card into a compressed file on partition `uuid`.
+    pub(crate) fn image_backup(
+        &self,
+        uuid: &str,
+        skip_unused: bool,
+    ) -> Result<watch::Receiver<ImageProgress>, String> {
+        let on_card = self
+            .data
+            .data
+            .lock()
+            .unwrap()
+            .find_partition(uuid)
+            .is_some_and(|(disk, _)| disk.kernel == image::SD_CARD);
+        if on_card {
+            return Err(String::from("Cannot image the SD card onto itself"));
+        }
+        let target = self.file_sandbox(uuid).map_err(|e| e.to_string())?;
+        image::start_backup(&self.data, target, uuid, skip_unused)
+    }
+
+    /// Start checking the image at `path` on partition `uuid` against its
+    /// checksum file.
+    pub(crate) fn image_verify(
+        &self,
+        uuid: &str,
+        path: &str,
+    ) -> Result<watch::Receiver<ImageProgress>, String> {
+        let target = self.file_sandbox(uuid).map_err(|e| e.to_string())?;
+        image::start_verify(&self.data, target, uuid, path)
+    }
+
+    /// Start analyzing what uses the space of the mounted partition `uuid`.
+    pub(crate) fn analyze_usage(
+        &self,
+        uuid: &str,
+    ) -> Result<watch::Receiver<UsageProgress>, String> {
+        let mount_point = self.mount_point(uuid).map_err(|e| e.to_string())?;
+        usage::start(&self.data, uuid, &mount_point)
+    }
+
+    /// Latest space analysis of partition `uuid`, possibly still running.
+    pub(crate) fn cached_usage(&self, uuid: &str) -> Option<UsageProgress> {
+        self.data.usage.lock().unwrap().get(uuid).cloned()
+    }
+
+    /// Start searching the directories `roots` for duplicate files of at
+    /// least `min_size` bytes.
+    pub(crate) fn find_duplicates(
+        &self,
+        roots: Vec<DuplicateFile>,
+        min_size: u64,
+    ) -> Result<watch::Receiver<DuplicateProgress>, String> {
+        let mut sandboxes = HashMap::new();
+        for root in &roots {
+            if !sandboxes.contains_key(&root.uuid) {
+                let sandbox = self.file_sandbox(&root.uuid).map_err(|e| e.to_string())?;
+                sandboxes.insert(root.uuid.clone(), sandbox);
+            }
+        }
+        duplicates::start(&self.data, roots, min_size, sandboxes)
+    }
+
+    /// Delete `files`, duplicates found by search `job_id`. The returned
+    /// future does not borrow the handler, files are hashed again first.
+    pub(crate) fn delete_duplicates(
+        &self,
+        job_id: JobId,
+        files: Vec<DuplicateFile>,
+    ) -> impl Future<Output = Result<Vec<Deletion>, String>> + Send + 'static {
+        let groups = self.data.duplicates.lock().unwrap().search(job_id);
+        let sandboxes = groups
+            .iter()
+            .flatten()
+            .flat_map(|g| g.files.iter())
+            .map(|f| f.uuid.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|uuid| {
+                let sandbox = self.file_sandbox(&uuid);
+                (uuid, sandbox)
+            })
+            .collect::<HashMap<_, _>>();
+
+        async move {
+            let groups = groups.ok_or_else(|| format!("No result for search {}", job_id))?;
+            tokio::task::spawn_blocking(move || duplicates::delete(&groups, &files, &sandboxes))
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    pub(crate) fn known_disks(&self) -> Vec<KnownDisk> {
+        self.data.known.lock().unwrap().list()
+    }
+
+    /// Trim the partition with `uuid`, or every mounted partition which
+    /// supports discard if `uuid` is None. The returned future does not
+    /// borrow the handler, so callers can release it while trim runs.
+    pub(crate) fn trim(
+        &self,
+        uuid: Option<String>,
+    ) -> impl Future<Output = Vec<TrimResult>> + Send + 'static {
+        trim::run(self.data.clone(), self.event_notifier.clone(), uuid)
+    }
+
+    /// Spin down the disk holding the partition with `uuid`.
+    pub(crate) fn standby(
+        &self,
+        uuid: String,
+    ) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        spindown::force_standby(self.data.clone(), self.event_notifier.clone(), uuid)
+    }
+
+    /// Start a read-only surface scan of the whole disk `kernel`, e.g. sda.
+    pub(crate) fn surface_scan(
+        &self,
+        kernel: &str,
+    ) -> Result<watch::Receiver<ScanProgress>, String> {
+        scan::start(&self.data, kernel)
+    }
+
+    /// Benchmark the mounted partition `uuid` with a temporary file of `size` bytes.
+    pub(crate) fn benchmark(
+        &self,
+        uuid: &str,
+        size: u64,
+    ) -> Result<watch::Receiver<BenchProgress>, String> {
+        bench::start(&self.data, uuid, size)
+    }
+
+    pub(crate) fn pause_job(&self, id: JobId) -> Result<(), String> {
+        self.data.jobs.pause(id)
+    }
+
+    pub(crate) fn resume_job(&self, id: JobId) -> Result<(), String> {
+        self.data.jobs.resume(id)
+    }
+
+    pub(crate) fn cancel_job(&self, id: JobId) -> Result<(), String> {
+        self.data.jobs.cancel(id)
+    }
+
+    pub(crate) fn backups(&self) -> Vec<BackupStatus> {
+        self.data.backups.lock().unwrap().list()
+    }
+
+    /// Add a backup, or replace the one with the same name. Its source must
+    /// be on a managed disk.
+    pub(crate) fn set_backup(&self, backup: BackupConfig) -> Result<(), String> {
+        backup::check_source(&self.data, &backup.source)?;
+        let mut backups = self.data.backups.lock().unwrap();
+        backups.set(backup)?;
+        backups
+            .save()
+            .map_err(|e| format!("Cannot save backups: {}", e))
+    }
+
+    pub(crate) fn remove_backup(&self, name: &str) -> Result<(), String> {
+        let mut backups = self.data.backups.lock().unwrap();
+        backups.remove(name)?;
+        backups
+            .save()
+            .map_err(|e| format!("Cannot save backups: {}", e))
+    }
+
+    /// Run backup `name` now, whatever its schedule.
+    pub(crate) fn run_backup(&self, name: &str) -> Result<JobId, String> {
+        backup::start(&self.data, name)
+    }
+
+    /// Every share with whether it is active, i.e. its partition is mounted.
+    pub(crate) fn shares(&self) -> Vec<(Share, bool)> {
+        self.data.shares.lock().unwrap().list()
+    }
+
+    /// Share a partition, or change how it is shared.
+    pub(crate) async fn set_share(&self, share: Share) -> Result<(), String> {
+        if !self.data.known.lock().unwrap().contains(&share.uuid) {
+            return Err(format!("Unknown disk {}", share.uuid));
+        }
+        self.data.shares.lock().unwrap().set(share)?;
+        self.apply_shares().await
+    }
+
+    pub(crate) async fn remove_share(
+        &self,
+        uuid: &str,
+        protocol: ShareProtocol,
+    ) -> Result<(), String> {
+        self.data.shares.lock().unwrap().remove(uuid, protocol)?;
+        self.apply_shares().await
+    }
+
+    async fn apply_shares(&self) -> Result<(), String> {
+        let reload = share::apply(&self.data);
+        self.data.publish(&self.event_notifier);
+        reload.run_blocking().await
+    }
+
+    /// Known disks which are not currently attached.
+    pub(crate) fn absent_disks(&self) -> Vec<KnownDisk> {
+        let disks = self.data.data.lock().unwrap();
+        self.data.known.lock().unwrap().absent(&disks)
     }
 }
 
@@ -104,20 +604,27 @@ pub(crate) struct DiskCache {
     event_notifier: EventNotifier,
     service_type: ServiceType,
     data: DiskCacheData,
+    config: DiskConfig,
 }
 
 impl DiskCache {
-    pub(super) fn new(event_notifier: EventNotifier) -> (Self, DiskCacheHandler) {
-        let data = DiskCacheData::new();
+    pub(crate) fn new(event_notifier: EventNotifier, config: &Config) -> (Self, DiskCacheHandler) {
+        let known = KnownDisks::load(&config.state_dir);
+        let backups = Backups::load(&config.state_dir, &config.disk.backups);
+        let imports = Imports::load(&config.state_dir);
+        let shares = Shares::load(&config.state_dir, &config.disk, Arc::new(SystemRunner));
+        let history = History::load(&config.state_dir);
+        let data = DiskCacheData::new(known, backups, imports, shares, history);
         let cache = Self {
             data: data.clone(),
-            event_notifier,
+            event_notifier: event_notifier.clone(),
             service_type: THIS_TYPE,
+            config: config.disk.clone(),
         };
 
         let cache_handler = DiskCacheHandler {
-            service_type: THIS_TYPE,
             data,
+            event_notifier,
         };
 
         (cache, cache_handler)
@@ -125,15 +632,34 @@ impl DiskCache {
 }
 
 impl Cache for DiskCache {
-    fn run(&self, shutdown: shutdown::Receiver) {
+    fn run(&self, shutdown: shutdown::Receiver, schedule: Schedule) -> BoxFuture<'static, ()> {
         log::info!("DiskCache start running...");
-        let mut generator = DataGenerator::new(self.data.clone(), self.event_notifier.clone());
-        tokio::spawn(async move {
-            generator.run(shutdown).await;
-        });
+        let mut generator = DataGenerator::new(
+            self.data.clone(),
+            self.event_notifier.clone(),
+            self.config.clone(),
+        );
+        Box::pin(async move {
+            generator.run(shutdown, schedule).await;
+        })
     }
 
     fn get_type(&self) -> ServiceType {
         self.service_type
     }
+
+    fn refresh_interval(&self) -> Duration {
+        DISK_POLL_INTERVAL
+    }
+
+    /// Cancelled jobs save their progress as they exit, wait for them before
+    /// saving the rest of the state.
+    fn flush(&self) -> BoxFuture<'static, ()> {
+        let data = self.data.clone();
+        Box::pin(async move {
+            data.jobs.cancel_all();
+            data.jobs.wait_all().await;
+            data.save();
+        })
+    }
 }
@@ -0,0 +1,227 @@
+use std::fs;
+use std::path::Path;
+
+use crate::public::{LogicalVolume, RaidArray};
+
+const SYS_BLOCK: &str = "/sys/block";
+const MDSTAT: &str = "/proc/mdstat";
+
+fn is_md(kernel: &str) -> bool {
+    kernel.len() > 2 && kernel.starts_with("md") && kernel[2..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Software RAID arrays (mdX) and device mapper volumes (dm-X).
+pub(super) fn is_virtual_disk(kernel: &str) -> bool {
+    is_md(kernel) || kernel.starts_with("dm-")
+}
+
+fn read_attr(kernel: &str, attr: &str) -> Option<String> {
+    fs::read_to_string(Path::new(SYS_BLOCK).join(kernel).join(attr))
+        .ok()
+        .map(|v| v.trim().to_owned())
+}
+
+/// Device mapper name of a dm-X device, e.g. vg0-data.
+pub(super) fn dm_name(kernel: &str) -> Option<String> {
+    if !kernel.starts_with("dm-") {
+        return None;
+    }
+    read_attr(kernel, "dm/name")
+}
+
+fn parse_member(token: &str, array: &mut RaidArray) {
+    // Members look like sdb1[1], sdc1[2](F) or sdd1[3](S)
+    let name = match token.find('[') {
+        None => return,
+        Some(idx) => token[..idx].to_owned(),
+    };
+
+    if token.ends_with("(F)") {
+        array.failed.push(name.clone());
+    } else if token.ends_with("(S)") {
+        array.spares.push(name.clone());
+    }
+    array.members.push(name);
+}
+
+fn parse_status(line: &str, array: &mut RaidArray) {
+    // e.g. "976630464 blocks super 1.2 [2/1] [U_]"
+    for token in line.split_whitespace() {
+        if !(token.starts_with('[') && token.ends_with(']')) {
+            continue;
+        }
+        let inner = &token[1..token.len() - 1];
+        if let Some((total, active)) = inner.split_once('/') {
+            if let (Ok(total), Ok(active)) = (total.parse::<u32>(), active.parse::<u32>()) {
+                array.degraded |= active < total;
+            }
+        } else if inner.contains('_') {
+            array.degraded = true;
+        }
+    }
+}
+
+fn parse_progress(line: &str, array: &mut RaidArray) {
+    // e.g. "[==>......]  recovery = 12.6% (123/976) finish=80.1min speed=100000K/sec"
+    // or   "resync=DELAYED"
+    for action in ["resync", "recovery", "reshape", "check", "repair"].iter() {
+        let rest = match line.find(action) {
+            None => continue,
+            Some(idx) => line[idx + action.len()..].trim_start(),
+        };
+        let rest = match rest.strip_prefix('=') {
+            None => continue,
+            Some(rest) => rest.trim_start(),
+        };
+
+        array.sync_action = action.to_string();
+        array.sync_progress = rest
+            .split('%')
+            .next()
+            .filter(|_| rest.contains('%'))
+            .and_then(|v| v.trim().parse::<f32>().ok());
+        return;
+    }
+}
+
+/// Parse the content of /proc/mdstat.
+pub(super) fn parse_mdstat(content: &str) -> Vec<RaidArray> {
+    let mut arrays: Vec<RaidArray> = Vec::new();
+
+    for line in content.lines() {
+        let is_header =
+            !line.starts_with(char::is_whitespace) && line.split(" : ").next().is_some_and(is_md);
+        if is_header {
+            let (kernel, rest) = line.split_once(" : ").unwrap();
+            let mut array = RaidArray {
+                kernel: kernel.to_owned(),
+                sync_action: String::from("idle"),
+                ..Default::default()
+            };
+
+            let mut tokens = rest.split_whitespace().peekable();
+            array.state = tokens.next().unwrap_or_default().to_owned();
+            // Skip flags like (auto-read-only)
+            while tokens.peek().is_some_and(|t| t.starts_with('(')) {
+                tokens.next();
+            }
+            // Inactive arrays do not report their level
+            if tokens.peek().is_some_and(|t| !t.contains('[')) {
+                array.level = tokens.next().unwrap().to_owned();
+            }
+            for token in tokens {
+                parse_member(token, &mut array);
+            }
+            array.degraded = !array.failed.is_empty();
+
+            arrays.push(array);
+            continue;
+        }
+
+        let array = match arrays.last_mut() {
+            None => continue,
+            Some(array) => array,
+        };
+        if line.trim().is_empty() || !line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        if line.contains(" blocks") {
+            parse_status(line, array);
+        } else {
+            parse_progress(line, array);
+        }
+    }
+
+    arrays
+}
+
+/// Software RAID arrays from /proc/mdstat, refined with the md sysfs attributes.
+pub(super) fn get_raid_arrays() -> Vec<RaidArray> {
+    let content = match fs::read_to_string(MDSTAT) {
+        Err(_) => return Vec::new(), // md driver is not loaded
+        Ok(content) => content,
+    };
+
+    let mut arrays = parse_mdstat(&content);
+    for array in arrays.iter_mut() {
+        if let Some(state) = read_attr(&array.kernel, "md/array_state") {
+            array.state = state;
+        }
+        if let Some(degraded) = read_attr(&array.kernel, "md/degraded") {
+            array.degraded |= degraded.parse::<u32>().unwrap_or(0) > 0;
+        }
+        if let Some(action) = read_attr(&array.kernel, "md/sync_action") {
+            array.sync_action = action;
+        }
+        if let Some(size) = read_attr(&array.kernel, "size") {
+            array.size = size.parse::<u64>().unwrap_or(0) << 9; // * 512
+        }
+    }
+
+    arrays
+}
+
+/// LVM logical volumes, i.e. device mapper devices whose uuid starts with LVM-.
+pub(super) fn get_logical_volumes() -> Vec<LogicalVolume> {
+    let entries = match fs::read_dir(SYS_BLOCK) {
+        Err(_) => return Vec::new(),
+        Ok(entries) => entries,
+    };
+
+    let mut volumes = entries
+        .filter_map(|x| x.ok())
+        .filter_map(|x| x.file_name().into_string().ok())
+        .filter(|x| x.starts_with("dm-"))
+        .filter_map(|kernel| {
+            let uuid = read_attr(&kernel, "dm/uuid")?;
+            if !uuid.starts_with("LVM-") {
+                return None;
+            }
+
+            let members = fs::read_dir(Path::new(SYS_BLOCK).join(&kernel).join("slaves"))
+                .map(|it| {
+                    it.filter_map(|x| x.ok())
+                        .filter_map(|x| x.file_name().into_string().ok())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            Some(LogicalVolume {
+                name: read_attr(&kernel, "dm/name").unwrap_or_default(),
+                size: read_attr(&kernel, "size")
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0)
+                    << 9,
+                kernel,
+                uuid,
+                members,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    volumes.sort_by(|a, b| a.name.cmp(&b.name));
+    volumes
+}
+
+/// Arrays which are degraded in `new` but were healthy (or absent) in `old`.
+pub(super) fn newly_degraded<'a>(old: &[RaidArray], new: &'a [RaidArray]) -> Vec<&'a RaidArray> {
+    new.iter()
+        .filter(|a| a.degraded)
+        .filter(|a| !old.iter().any(|o| o.kernel == a.kernel && o.degraded))
+        .collect()
+}
+
+/// Keep `degraded_since` of the arrays of `old` which are still degraded in
+/// `new`, the ones which just became degraded are degraded since `now`.
+pub(super) fn carry_degraded_since(old: &[RaidArray], new: &mut [RaidArray], now: u64) {
+    for array in new.iter_mut().filter(|a| a.degraded) {
+        array.degraded_since = old
+            .iter()
+            .find(|o| o.kernel == array.kernel && o.degraded)
+            .map_or(now, |o| o.degraded_since);
+    }
+}
+
+#[cfg(test)]
+#[path = "./raid_test.rs"]
+mod raid_test;
@@ -0,0 +1,84 @@
+use super::*;
+
+const MDSTAT_CONTENT: &str = "\
+Personalities : [raid1] [raid6] [raid5] [raid4]
+md1 : active raid5 sdd1[3] sdc1[1](F) sdb1[0]
+      1953260544 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [U_U]
+      [==>..................]  recovery = 12.6% (123456/976630272) finish=80.1min speed=100000K/sec
+      bitmap: 0/8 pages [0KB], 65536KB chunk
+
+md0 : active (auto-read-only) raid1 sda1[0] sde1[1] sdf1[2](S)
+      976630464 blocks super 1.2 [2/2] [UU]
+        resync=PENDING
+
+md127 : inactive sdg[0](S)
+      976630464 blocks super 1.2
+
+unused devices: <none>
+";
+
+#[test]
+fn test_parse_mdstat() {
+    let arrays = parse_mdstat(MDSTAT_CONTENT);
+    assert_eq!(arrays.len(), 3);
+
+    let md1 = &arrays[0];
+    assert_eq!(md1.kernel, "md1");
+    assert_eq!(md1.state, "active");
+    assert_eq!(md1.level, "raid5");
+    assert_eq!(md1.members, vec!["sdd1", "sdc1", "sdb1"]);
+    assert_eq!(md1.failed, vec!["sdc1"]);
+    assert!(md1.degraded);
+    assert_eq!(md1.sync_action, "recovery");
+    assert_eq!(md1.sync_progress, Some(12.6));
+
+    let md0 = &arrays[1];
+    assert_eq!(md0.level, "raid1");
+    assert_eq!(md0.spares, vec!["sdf1"]);
+    assert!(!md0.degraded);
+    assert_eq!(md0.sync_action, "resync");
+    assert_eq!(md0.sync_progress, None);
+
+    let md127 = &arrays[2];
+    assert_eq!(md127.state, "inactive");
+    assert_eq!(md127.level, "");
+    assert_eq!(md127.members, vec!["sdg"]);
+    assert_eq!(md127.sync_action, "idle");
+}
+
+#[test]
+fn test_newly_degraded() {
+    let old = parse_mdstat(MDSTAT_CONTENT);
+    assert!(newly_degraded(&old, &old).is_empty());
+
+    let mut healthy = old.clone();
+    healthy[0].degraded = false;
+    let v = newly_degraded(&healthy, &old);
+    assert_eq!(v.len(), 1);
+    assert_eq!(v[0].kernel, "md1");
+}
+
+#[test]
+fn test_carry_degraded_since() {
+    let mut old = parse_mdstat(MDSTAT_CONTENT);
+    carry_degraded_since(&[], &mut old, 100);
+    assert_eq!(old[0].degraded_since, 100);
+    assert_eq!(old[1].degraded_since, 0);
+
+    // md1 is still degraded since it was first seen so, md0 just became.
+    let mut new = parse_mdstat(MDSTAT_CONTENT);
+    new[1].degraded = true;
+    carry_degraded_since(&old, &mut new, 200);
+    assert_eq!(new[0].degraded_since, 100);
+    assert_eq!(new[1].degraded_since, 200);
+}
+
+#[test]
+fn test_is_virtual_disk() {
+    assert!(is_virtual_disk("md0"));
+    assert!(is_virtual_disk("md127"));
+    assert!(is_virtual_disk("dm-3"));
+    assert!(!is_virtual_disk("mdx"));
+    assert!(!is_virtual_disk("mmcblk0"));
+    assert!(!is_virtual_disk("sda"));
+}
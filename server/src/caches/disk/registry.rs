@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Disks;
+use crate::public::KnownDisk;
+
+const STATE_FILE: &str = "known_disks.json";
+/// How stale the saved `last_seen` of an attached disk may get, in seconds.
+/// It is saved exactly once the disk is removed.
+const LAST_SEEN_PRECISION: u64 = 3600;
+
+pub(super) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Every disk partition the server has ever seen, keyed by filesystem uuid.
+/// The uuid is the identity: a reformatted partition is a new entry, and
+/// cloned filesystems share one whatever disk they are on.
+#[derive(Debug, Default)]
+pub(super) struct KnownDisks {
+    path: Option<PathBuf>,
+    disks: BTreeMap<String, KnownDisk>,
+    /// Uuids of the last observation.
+    present: HashSet<String>,
+    /// When an observation last returned a change.
+    changed_at: u64,
+}
+
+impl KnownDisks {
+    /// Load the registry from `state_dir`. A missing or unreadable file
+    /// results in an empty registry, which is saved back on the next change.
+    pub(super) fn load(state_dir: &str) -> Self {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let disks = match fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Vec<KnownDisk>>(&content) {
+                Ok(v) => v.into_iter().map(|d| (d.uuid.clone(), d)).collect(),
+                Err(e) => {
+                    log::error!("Cannot parse {:?}, starting empty: {}", path, e);
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                log::error!("Cannot read {:?}, starting empty: {}", path, e);
+                BTreeMap::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            disks,
+            present: HashSet::new(),
+            changed_at: 0,
+        }
+    }
+
+    pub(super) fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(&self.list())?;
+        // Write to a temporary file first, so a crash never leaves a truncated state file.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Record the result of a scan. Returns true if anything worth saving
+    /// changed: `last_seen` is only worth it hourly, or once a disk is gone.
+    pub(super) fn observe(&mut self, disks: &Disks, now: u64) -> bool {
+        let mut changed = false;
+        let mut present = HashSet::new();
+        for disk in disks.disks.iter() {
+            for part in disk.partitions.iter() {
+                if part.uuid.is_nil() {
+                    continue;
+                }
+                let uuid = part.uuid.to_string();
+                present.insert(uuid.clone());
+                let entry = self.disks.entry(uuid.clone()).or_insert_with(|| {
+                    log::info!("New disk {} ({}) is registered", uuid, part.kernel);
+                    changed = true;
+                    KnownDisk {
+                        uuid,
+                        first_seen: now,
+                        ..Default::default()
+                    }
+                });
+
+                let mount_point = part
+                    .mount_path
+                    .as_ref()
+                    .and_then(|v| v.first().cloned())
+                    .unwrap_or_default();
+                if !mount_point.is_empty() && entry.last_mount_point != mount_point {
+                    entry.last_mount_point = mount_point;
+                    changed = true;
+                }
+                if entry.kernel != part.kernel || entry.label != part.label {
+                    entry.kernel = part.kernel.clone();
+                    entry.label = part.label.clone();
+                    changed = true;
+                }
+                if entry.serial != disk.serial || entry.size != part.size {
+                    entry.serial = disk.serial.clone();
+                    entry.size = part.size;
+                    changed = true;
+                }
+                entry.last_seen = now;
+            }
+        }
+
+        if self.present.iter().any(|uuid| !present.contains(uuid)) {
+            changed = true;
+        }
+        if !present.is_empty() && now >= self.changed_at + LAST_SEEN_PRECISION {
+            changed = true;
+        }
+        if changed {
+            self.changed_at = now;
+        }
+        self.present = present;
+        changed
+    }
+
+    pub(super) fn set_nickname(&mut self, uuid: &str, nickname: &str, notes: &str) -> bool {
+        match self.disks.get_mut(uuid) {
+            None => false,
+            Some(disk) => {
+                disk.nickname = nickname.to_owned();
+                disk.notes = notes.to_owned();
+                true
+            }
+        }
+    }
+
+    pub(super) fn set_read_only(&mut self, uuid: &str, read_only: bool) -> bool {
+        match self.disks.get_mut(uuid) {
+            None => false,
+            Some(disk) => {
+                disk.read_only = read_only;
+                true
+            }
+        }
+    }
+
+    pub(super) fn is_read_only(&self, uuid: &str) -> bool {
+        self.disks.get(uuid).is_some_and(|d| d.read_only)
+    }
+
+    pub(super) fn record_trim(&mut self, uuid: &str, trimmed: u64, now: u64) -> bool {
+        match self.disks.get_mut(uuid) {
+            None => false,
+            Some(disk) => {
+                disk.last_trim = now;
+                disk.last_trimmed_bytes = trimmed;
+                true
+            }
+        }
+    }
+
+    pub(super) fn contains(&self, uuid: &str) -> bool {
+        self.disks.contains_key(uuid)
+    }
+
+    pub(super) fn list(&self) -> Vec<KnownDisk> {
+        self.disks.values().cloned().collect()
+    }
+
+    /// Known disks which are not part of `disks`.
+    pub(super) fn absent(&self, disks: &Disks) -> Vec<KnownDisk> {
+        let present = disks
+            .disks
+            .iter()
+            .flat_map(|d| d.partitions.iter())
+            .map(|p| p.uuid.to_string())
+            .collect::<Vec<_>>();
+
+        self.disks
+            .values()
+            .filter(|d| !present.contains(&d.uuid))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "./registry_test.rs"]
+mod registry_test;
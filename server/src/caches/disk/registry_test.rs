@@ -0,0 +1,80 @@
+use super::*;
+use crate::public::{DiskInfo, Partition};
+use crate::test_util::TempDir;
+use uuid::Uuid;
+
+const UUID_A: &str = "0b7f5c7e-6c1b-4a0e-9a3a-2d1f1d8a1c01";
+const UUID_B: &str = "6a8e3c1d-2f4b-4f7a-8c9d-0e1f2a3b4c02";
+
+fn disks_with(parts: &[(&str, &str, Option<&str>)]) -> Disks {
+    let partitions = parts
+        .iter()
+        .map(|(kernel, uuid, mount)| Partition {
+            kernel: kernel.to_string(),
+            size: 1024,
+            uuid: Uuid::parse_str(uuid).unwrap(),
+            label: String::new(),
+            mount_path: Some(mount.iter().map(|m| m.to_string()).collect()),
+        })
+        .collect();
+
+    Disks {
+        disks: vec![DiskInfo {
+            kernel: "sda".into(),
+            size: 2048,
+            serial: "SERIAL0".into(),
+            partitions,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_observe_and_absent() {
+    let mut known = KnownDisks::default();
+
+    let disks = disks_with(&[("sda1", UUID_A, Some("/mnt/a")), ("sda2", UUID_B, None)]);
+    assert!(known.observe(&disks, 100));
+    assert!(!known.observe(&disks, 200));
+
+    let list = known.list();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].first_seen, 100);
+    assert_eq!(list[0].last_seen, 200);
+    assert_eq!(list[0].last_mount_point, "/mnt/a");
+    assert_eq!(list[0].serial, "SERIAL0");
+
+    // sda1 is unplugged, its last mount point is still remembered.
+    let disks = disks_with(&[("sda2", UUID_B, None)]);
+    assert!(known.observe(&disks, 300));
+    assert!(!known.observe(&disks, 400));
+    let absent = known.absent(&disks);
+    assert_eq!(absent.len(), 1);
+    assert_eq!(absent[0].uuid, UUID_A);
+    assert_eq!(absent[0].last_seen, 200);
+    assert_eq!(absent[0].last_mount_point, "/mnt/a");
+
+    // Being seen again is saved hourly.
+    assert!(!known.observe(&disks, 200 + LAST_SEEN_PRECISION));
+    assert!(known.observe(&disks, 300 + LAST_SEEN_PRECISION));
+    assert!(!known.observe(&disks, 400 + LAST_SEEN_PRECISION));
+}
+
+#[test]
+fn test_nickname_persisted() {
+    let temp = TempDir::new("registry");
+    let dir = temp.to_str().unwrap();
+
+    let mut known = KnownDisks::load(dir);
+    known.observe(&disks_with(&[("sda1", UUID_A, None)]), 100);
+    assert!(known.set_nickname(UUID_A, "drawer disk", "blue enclosure"));
+    assert!(!known.set_nickname(UUID_B, "nope", ""));
+    known.save().unwrap();
+
+    let known = KnownDisks::load(dir);
+    let list = known.list();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].nickname, "drawer disk");
+    assert_eq!(list[0].notes, "blue enclosure");
+}
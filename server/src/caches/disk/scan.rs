@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use super::direct::{read_exact_at, AlignedBuf};
+use super::DiskCacheData;
+use crate::public::job::{JobState, JobToken};
+use crate::public::ScanProgress;
+
+const CHUNK_SIZE: usize = 1 << 20; // 1 MiB per read
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(super) fn read_attr_u64(kernel: &str, attr: &str) -> Option<u64> {
+    fs::read_to_string(format!("/sys/block/{}/{}", kernel, attr))
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+}
+
+/// Add an unreadable LBA, merging it into the previous range if adjacent.
+/// Ranges are (first LBA, number of sectors) and LBAs arrive in order.
+pub(super) fn add_bad_lba(ranges: &mut Vec<(u64, u64)>, lba: u64) {
+    if let Some(last) = ranges.last_mut() {
+        if last.0 + last.1 == lba {
+            last.1 += 1;
+            return;
+        }
+    }
+    ranges.push((lba, 1));
+}
+
+struct Scanner {
+    kernel: String,
+    token: JobToken,
+    progress: watch::Sender<ScanProgress>,
+    current: ScanProgress,
+    last_publish: Instant,
+    last_scanned: u64,
+}
+
+impl Scanner {
+    fn publish(&mut self, force: bool) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_publish);
+        if !force && elapsed < PUBLISH_INTERVAL {
+            return;
+        }
+
+        if !elapsed.is_zero() {
+            let bytes = self.current.scanned - self.last_scanned;
+            self.current.throughput = (bytes as f64 / elapsed.as_secs_f64()) as u64;
+        }
+        self.last_publish = now;
+        self.last_scanned = self.current.scanned;
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    /// Publish a pause or resume. The paused time does not count for the
+    /// throughput.
+    fn paused(&mut self, paused: bool) {
+        if paused {
+            self.publish(true);
+            self.current.state = JobState::Paused;
+            self.current.throughput = 0;
+        } else {
+            self.current.state = JobState::Running;
+            self.last_publish = Instant::now();
+            self.last_scanned = self.current.scanned;
+        }
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    fn run(&mut self) -> io::Result<JobState> {
+        let sector = read_attr_u64(&self.kernel, "queue/logical_block_size").unwrap_or(512);
+        let total = read_attr_u64(&self.kernel, "size").unwrap_or(0) << 9; // * 512
+        self.current.total = total;
+
+        // Read only, the device is never opened for writing.
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(format!("/dev/{}", self.kernel))?;
+        let mut buf = AlignedBuf::new(CHUNK_SIZE);
+
+        let mut offset = 0u64;
+        while offset < total {
+            let token = self.token.clone();
+            if token.checkpoint_with(|paused| self.paused(paused)).is_err() {
+                return Ok(JobState::Cancelled);
+            }
+
+            let len = (total - offset).min(CHUNK_SIZE as u64) as usize;
+            if read_exact_at(&file, buf.get(len), offset).is_err() {
+                // Narrow the failure down to single sectors.
+                let mut lba_offset = offset;
+                while lba_offset < offset + len as u64 {
+                    if read_exact_at(&file, buf.get(sector as usize), lba_offset).is_err() {
+                        add_bad_lba(&mut self.current.bad_ranges, lba_offset / sector);
+                    }
+                    lba_offset += sector;
+                }
+                log::warn!(
+                    "DiskCache - Surface scan of {} hit unreadable sectors near {}",
+                    self.kernel,
+                    offset
+                );
+            }
+
+            offset += len as u64;
+            self.current.scanned = offset;
+            self.publish(false);
+        }
+
+        Ok(JobState::Finished)
+    }
+}
+
+/// Start a read-only surface scan of the whole disk `kernel`.
+pub(super) fn start(
+    data: &DiskCacheData,
+    kernel: &str,
+) -> Result<watch::Receiver<ScanProgress>, String> {
+    if !data
+        .data
+        .lock()
+        .unwrap()
+        .disks
+        .iter()
+        .any(|d| d.kernel == kernel)
+    {
+        return Err(format!("Unknown disk {}", kernel));
+    }
+    if !data.scanning.lock().unwrap().insert(kernel.to_owned()) {
+        return Err(format!("{} is already being scanned", kernel));
+    }
+
+    let token = data.jobs.register("surface-scan");
+    let current = ScanProgress {
+        job_id: token.id(),
+        kernel: kernel.to_owned(),
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(current.clone());
+
+    let mut scanner = Scanner {
+        kernel: kernel.to_owned(),
+        token,
+        progress: tx,
+        current,
+        last_publish: Instant::now(),
+        last_scanned: 0,
+    };
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!("DiskCache - Surface scan of {} starts", scanner.kernel);
+        match scanner.run() {
+            Ok(state) => scanner.current.state = state,
+            Err(e) => {
+                scanner.current.state = JobState::Failed;
+                scanner.current.error = Some(e.to_string());
+            }
+        }
+        log::info!(
+            "DiskCache - Surface scan of {} is {:?}, {} bad ranges",
+            scanner.kernel,
+            scanner.current.state,
+            scanner.current.bad_ranges.len()
+        );
+
+        data.jobs.unregister(&scanner.token);
+        data.scanning.lock().unwrap().remove(&scanner.kernel);
+        scanner.publish(true);
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+#[path = "./scan_test.rs"]
+mod scan_test;
@@ -0,0 +1,19 @@
+use super::super::direct::ALIGNMENT;
+use super::*;
+
+#[test]
+fn test_add_bad_lba() {
+    let mut ranges = Vec::new();
+    for lba in [10, 11, 12, 20, 22, 23].iter() {
+        add_bad_lba(&mut ranges, *lba);
+    }
+    assert_eq!(ranges, vec![(10, 3), (20, 1), (22, 2)]);
+}
+
+#[test]
+fn test_aligned_buf() {
+    let mut buf = AlignedBuf::new(CHUNK_SIZE);
+    let slice = buf.get(CHUNK_SIZE);
+    assert_eq!(slice.len(), CHUNK_SIZE);
+    assert_eq!(slice.as_ptr() as usize % ALIGNMENT, 0);
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Write};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+use super::{DiskCacheData, Disks};
+use crate::config::DiskConfig;
+use crate::public::{Share, ShareProtocol};
+
+const STATE_FILE: &str = "shares.json";
+const HEADER: &str = "# Managed by picontrolx, changes are overwritten.\n";
+
+/// Runs the commands which make Samba and the NFS server reload their
+/// configuration, replaced in tests.
+pub(crate) trait CommandRunner: Debug + Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> Result<(), String>;
+}
+
+#[derive(Debug)]
+pub(crate) struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<(), String> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|e| format!("Cannot run {}: {}", program, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn reload(protocol: ShareProtocol, runner: &dyn CommandRunner) -> Result<(), String> {
+    match protocol {
+        ShareProtocol::Smb => runner.run("smbcontrol", &["all", "reload-config"]),
+        ShareProtocol::Nfs => runner.run("exportfs", &["-ra"]),
+    }
+}
+
+/// Sections of smb.conf which are not shares.
+const SMB_RESERVED: [&str; 3] = ["global", "homes", "printers"];
+
+fn validate(share: &Share) -> Result<(), String> {
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    // A hostname, address, CIDR network, wildcard or @netgroup, but no
+    // export options.
+    let valid_client = |c: char| c.is_ascii_alphanumeric() || ".-_:/*?@".contains(c);
+    let reserved = SMB_RESERVED
+        .iter()
+        .any(|name| share.name.eq_ignore_ascii_case(name));
+    match share.protocol {
+        ShareProtocol::Smb
+            if share.name.is_empty() || !share.name.chars().all(valid_name) || reserved =>
+        {
+            Err(format!("Invalid share name {:?}", share.name))
+        }
+        ShareProtocol::Nfs if !share.clients.chars().all(valid_client) => {
+            Err(format!("Invalid NFS clients {:?}", share.clients))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A mount point which is safe to write into the generated files.
+fn safe_path(path: &str) -> bool {
+    !path.contains(|c: char| c.is_control() || c == '"')
+}
+
+pub(super) fn render_smb(shares: &[(&Share, &str)]) -> String {
+    let mut out = String::from(HEADER);
+    for (share, path) in shares {
+        let _ = write!(
+            out,
+            "\n[{}]\n   path = {}\n   read only = {}\n   browseable = yes\n",
+            share.name,
+            path,
+            if share.read_only { "yes" } else { "no" }
+        );
+    }
+    out
+}
+
+pub(super) fn render_nfs(shares: &[(&Share, &str)]) -> String {
+    let mut out = String::from(HEADER);
+    for (share, path) in shares {
+        let clients = match share.clients.as_str() {
+            "" => "*",
+            clients => clients,
+        };
+        let mode = if share.read_only { "ro" } else { "rw" };
+        let _ = writeln!(
+            out,
+            "\"{}\" {}({},sync,no_subtree_check)",
+            path, clients, mode
+        );
+    }
+    out
+}
+
+/// Shares of disk partitions over SMB and NFS. A share is active while its
+/// partition is mounted, the generated files only hold active shares.
+#[derive(Debug)]
+pub(super) struct Shares {
+    path: Option<PathBuf>,
+    shares: Vec<Share>,
+    smb_conf: PathBuf,
+    nfs_exports: PathBuf,
+    runner: Arc<dyn CommandRunner>,
+    active: Vec<Share>,
+}
+
+impl Shares {
+    pub(super) fn load(
+        state_dir: &str,
+        config: &DiskConfig,
+        runner: Arc<dyn CommandRunner>,
+    ) -> Self {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let shares = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<Vec<Share>>(&content).unwrap_or_else(|e| {
+                log::error!("Cannot parse {:?}, starting empty: {}", path, e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::error!("Cannot read {:?}, starting empty: {}", path, e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            shares,
+            smb_conf: PathBuf::from(&config.smb_conf),
+            nfs_exports: PathBuf::from(&config.nfs_exports),
+            runner,
+            active: Vec::new(),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(&self.shares)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(super) fn list(&self) -> Vec<(Share, bool)> {
+        self.shares
+            .iter()
+            .map(|s| (s.clone(), self.active.contains(s)))
+            .collect()
+    }
+
+    pub(super) fn active(&self) -> Vec<Share> {
+        self.active.clone()
+    }
+
+    /// Add a share, or replace the one of the same partition and protocol.
+    pub(super) fn set(&mut self, share: Share) -> Result<(), String> {
+        validate(&share)?;
+        let name_taken = self.shares.iter().any(|s| {
+            s.protocol == ShareProtocol::Smb
+                && share.protocol == ShareProtocol::Smb
+                && s.name.eq_ignore_ascii_case(&share.name)
+                && s.uuid != share.uuid
+        });
+        if name_taken {
+            return Err(format!("Share name {} is taken", share.name));
+        }
+
+        self.shares
+            .retain(|s| s.uuid != share.uuid || s.protocol != share.protocol);
+        self.shares.push(share);
+        self.save()
+            .map_err(|e| format!("Cannot save shares: {}", e))
+    }
+
+    pub(super) fn remove(&mut self, uuid: &str, protocol: ShareProtocol) -> Result<(), String> {
+        let count = self.shares.len();
+        self.shares
+            .retain(|s| s.uuid != uuid || s.protocol != protocol);
+        if self.shares.len() == count {
+            return Err(format!("{} is not shared over {:?}", uuid, protocol));
+        }
+        self.save()
+            .map_err(|e| format!("Cannot save shares: {}", e))
+    }
+
+    fn write(&self, protocol: ShareProtocol, content: &str) -> Result<bool, String> {
+        let path = match protocol {
+            ShareProtocol::Smb => &self.smb_conf,
+            ShareProtocol::Nfs => &self.nfs_exports,
+        };
+        if fs::read_to_string(path).ok().as_deref() == Some(content) {
+            return Ok(false);
+        }
+
+        let write = || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("picontrolx-tmp");
+            fs::write(&tmp, content)?;
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|e| format!("Cannot write {:?}: {}", path, e))?;
+        Ok(true)
+    }
+
+    /// Regenerate the files for the partitions mounted according to
+    /// `mounts` (uuid to mount point). The services whose file changed are
+    /// reloaded by the returned `Reload`.
+    pub(super) fn apply(&mut self, mounts: &HashMap<String, String>) -> Reload {
+        let mut active = Vec::new();
+        for share in self.shares.iter() {
+            match mounts.get(&share.uuid) {
+                Some(path) if safe_path(path) => active.push((share, path.as_str())),
+                Some(path) => log::warn!("DiskCache - Cannot share {:?}", path),
+                None => {}
+            }
+        }
+
+        let mut reload = Reload {
+            runner: self.runner.clone(),
+            protocols: Vec::new(),
+            errors: Vec::new(),
+        };
+        for protocol in [ShareProtocol::Smb, ShareProtocol::Nfs] {
+            let shares = active
+                .iter()
+                .filter(|(s, _)| s.protocol == protocol)
+                .cloned()
+                .collect::<Vec<_>>();
+            let content = match protocol {
+                ShareProtocol::Smb => render_smb(&shares),
+                ShareProtocol::Nfs => render_nfs(&shares),
+            };
+            match self.write(protocol, &content) {
+                Ok(true) => reload.protocols.push(protocol),
+                Ok(false) => {}
+                Err(e) => {
+                    log::error!("DiskCache - Cannot update {:?} shares: {}", protocol, e);
+                    reload.errors.push(e);
+                }
+            }
+        }
+
+        self.active = active.into_iter().map(|(s, _)| s.clone()).collect();
+        reload
+    }
+}
+
+/// Services to reload after their generated file changed. Run once the
+/// shares are unlocked: publishing reads them, and must not wait for a slow
+/// exportfs.
+#[derive(Debug)]
+#[must_use]
+pub(super) struct Reload {
+    runner: Arc<dyn CommandRunner>,
+    protocols: Vec<ShareProtocol>,
+    errors: Vec<String>,
+}
+
+impl Reload {
+    /// Reload on this thread, which may block.
+    pub(super) fn run(mut self) -> Result<(), String> {
+        for protocol in self.protocols.iter() {
+            if let Err(e) = reload(*protocol, self.runner.as_ref()) {
+                log::error!("DiskCache - Cannot reload {:?} shares: {}", protocol, e);
+                self.errors.push(e);
+            }
+        }
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.errors.join(", ")),
+        }
+    }
+
+    /// Reload on a blocking thread.
+    pub(super) async fn run_blocking(self) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || self.run())
+            .await
+            .map_err(|e| format!("Reload failed: {}", e))?
+    }
+}
+
+fn mounts(disks: &Disks) -> HashMap<String, String> {
+    disks
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .filter_map(|p| Some((p.uuid.to_string(), p.mount_point()?.to_owned())))
+        .collect()
+}
+
+/// Bring the generated files in line with the mounted partitions, the
+/// services are reloaded by running the result.
+pub(super) fn apply(data: &DiskCacheData) -> Reload {
+    let mounts = mounts(&data.data.lock().unwrap());
+    data.shares.lock().unwrap().apply(&mounts)
+}
+
+/// Stop sharing partition `uuid`, which is about to be unmounted. It is
+/// shared again on the next scan if it stays mounted.
+pub(super) fn withdraw(data: &DiskCacheData, uuid: &str) -> Reload {
+    let mut mounts = mounts(&data.data.lock().unwrap());
+    mounts.remove(uuid);
+    data.shares.lock().unwrap().apply(&mounts)
+}
+
+#[cfg(test)]
+#[path = "./share_test.rs"]
+mod share_test;
@@ -0,0 +1,116 @@
+use super::*;
+use crate::test_util::TempDir;
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct Recorder {
+    calls: Mutex<Vec<String>>,
+}
+
+impl CommandRunner for Recorder {
+    fn run(&self, program: &str, args: &[&str]) -> Result<(), String> {
+        let call = format!("{} {}", program, args.join(" "));
+        self.calls.lock().unwrap().push(call);
+        Ok(())
+    }
+}
+
+fn setup(name: &str) -> (TempDir, Arc<Recorder>, Shares) {
+    let base = TempDir::new(&format!("share-{}", name));
+    let config = DiskConfig {
+        smb_conf: base.join("smb/picontrolx.conf").to_string_lossy().into(),
+        nfs_exports: base
+            .join("exports.d/picontrolx.exports")
+            .to_string_lossy()
+            .into(),
+        ..Default::default()
+    };
+    let runner = Arc::new(Recorder::default());
+    let shares = Shares::load(base.to_str().unwrap(), &config, runner.clone());
+    (base, runner, shares)
+}
+
+fn share(uuid: &str, protocol: ShareProtocol, name: &str) -> Share {
+    Share {
+        uuid: uuid.into(),
+        protocol,
+        name: name.into(),
+        read_only: false,
+        clients: String::new(),
+    }
+}
+
+#[test]
+fn test_validate() {
+    let (_base, _, mut shares) = setup("validate");
+
+    assert!(shares
+        .set(share("a", ShareProtocol::Smb, "bad name"))
+        .is_err());
+    assert!(shares.set(share("a", ShareProtocol::Smb, "")).is_err());
+    let mut nfs = share("a", ShareProtocol::Nfs, "");
+    nfs.clients = "* # comment".into();
+    assert!(shares.set(nfs).is_err());
+    for clients in ["*(rw,no_root_squash)", "a,b", "host)"] {
+        let mut nfs = share("a", ShareProtocol::Nfs, "");
+        nfs.clients = clients.into();
+        assert!(shares.set(nfs).is_err(), "{}", clients);
+    }
+    for clients in ["", "*.lan", "192.168.1.0/24", "fe80::1", "@trusted", "pi-?"] {
+        let mut nfs = share("b", ShareProtocol::Nfs, "");
+        nfs.clients = clients.into();
+        assert!(shares.set(nfs).is_ok(), "{}", clients);
+    }
+    shares.remove("b", ShareProtocol::Nfs).unwrap();
+    assert!(shares
+        .set(share("a", ShareProtocol::Smb, "Global"))
+        .is_err());
+    assert!(shares.set(share("a", ShareProtocol::Smb, "homes")).is_err());
+    assert!(shares
+        .set(share("a", ShareProtocol::Smb, "PRINTERS"))
+        .is_err());
+
+    shares.set(share("a", ShareProtocol::Smb, "media")).unwrap();
+    assert!(shares.set(share("b", ShareProtocol::Smb, "MEDIA")).is_err());
+    // Replaces the share of the same partition and protocol.
+    shares
+        .set(share("a", ShareProtocol::Smb, "photos"))
+        .unwrap();
+    assert_eq!(shares.list().len(), 1);
+}
+
+#[test]
+fn test_apply() {
+    let (base, runner, mut shares) = setup("apply");
+    shares.set(share("a", ShareProtocol::Smb, "media")).unwrap();
+    let mut nfs = share("a", ShareProtocol::Nfs, "");
+    nfs.read_only = true;
+    shares.set(nfs).unwrap();
+    shares.set(share("b", ShareProtocol::Smb, "away")).unwrap();
+
+    let mut mounts = HashMap::new();
+    mounts.insert(String::from("a"), String::from("/media/usb a"));
+    shares.apply(&mounts).run().unwrap();
+    assert_eq!(shares.active().len(), 2);
+    assert_eq!(
+        *runner.calls.lock().unwrap(),
+        vec!["smbcontrol all reload-config", "exportfs -ra"]
+    );
+
+    let smb = fs::read_to_string(base.join("smb/picontrolx.conf")).unwrap();
+    assert!(smb.contains("[media]\n   path = /media/usb a\n   read only = no\n"));
+    assert!(!smb.contains("[away]"));
+    let nfs = fs::read_to_string(base.join("exports.d/picontrolx.exports")).unwrap();
+    assert!(nfs.ends_with("\"/media/usb a\" *(ro,sync,no_subtree_check)\n"));
+
+    // Nothing changed, nothing reloaded.
+    shares.apply(&mounts).run().unwrap();
+    assert_eq!(runner.calls.lock().unwrap().len(), 2);
+
+    // Withdrawn before unmount.
+    shares.apply(&HashMap::new()).run().unwrap();
+    assert!(shares.active().is_empty());
+    assert_eq!(runner.calls.lock().unwrap().len(), 4);
+    let smb = fs::read_to_string(base.join("smb/picontrolx.conf")).unwrap();
+    assert_eq!(smb, HEADER);
+}
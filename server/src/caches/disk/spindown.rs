@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+use super::DiskCacheData;
+use crate::public::event_queue::EventNotifier;
+use crate::public::{DiskInfo, SpinState};
+
+/// I/O counters of a disk, only completed reads and writes are considered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct IoCounters {
+    reads: u64,
+    writes: u64,
+}
+
+/// Parse /sys/block/<dev>/stat, see Documentation/block/stat.rst
+pub(super) fn parse_stat(content: &str) -> Option<IoCounters> {
+    let fields = content
+        .split_whitespace()
+        .map(|v| v.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if fields.len() < 5 {
+        return None;
+    }
+
+    Some(IoCounters {
+        reads: fields[0],
+        writes: fields[4],
+    })
+}
+
+fn read_stat(kernel: &str) -> Option<IoCounters> {
+    parse_stat(&fs::read_to_string(format!("/sys/block/{}/stat", kernel)).ok()?)
+}
+
+pub(super) fn is_rotational(kernel: &str) -> bool {
+    fs::read_to_string(format!("/sys/block/{}/queue/rotational", kernel))
+        .map(|v| v.trim() == "1")
+        .unwrap_or(false)
+}
+
+#[derive(Debug)]
+struct DiskActivity {
+    counters: IoCounters,
+    last_active: Instant,
+    state: SpinState,
+}
+
+/// Follows the activity of spinning disks to decide when to spin them down.
+#[derive(Debug, Default)]
+pub(super) struct SpinTracker {
+    disks: HashMap<String, DiskActivity>,
+}
+
+impl SpinTracker {
+    /// Record the current counters of a disk. Returns true if its spin
+    /// state changed.
+    pub(super) fn update(&mut self, kernel: &str, counters: IoCounters, now: Instant) -> bool {
+        let activity = self
+            .disks
+            .entry(kernel.to_owned())
+            .or_insert_with(|| DiskActivity {
+                counters,
+                last_active: now,
+                state: SpinState::Unknown,
+            });
+
+        if activity.counters == counters {
+            return false;
+        }
+        activity.counters = counters;
+        activity.last_active = now;
+        if activity.state != SpinState::Active {
+            activity.state = SpinState::Active;
+            return true;
+        }
+        false
+    }
+
+    pub(super) fn set_standby(&mut self, kernel: &str) {
+        if let Some(activity) = self.disks.get_mut(kernel) {
+            activity.state = SpinState::Standby;
+        }
+    }
+
+    /// Restart the idle timer, e.g. after a failed standby attempt.
+    pub(super) fn postpone(&mut self, kernel: &str, now: Instant) {
+        if let Some(activity) = self.disks.get_mut(kernel) {
+            activity.last_active = now;
+        }
+    }
+
+    pub(super) fn state(&self, kernel: &str) -> SpinState {
+        self.disks
+            .get(kernel)
+            .map(|a| a.state)
+            .unwrap_or(SpinState::Unknown)
+    }
+
+    /// Disks idle for longer than their timeout, which are not in standby yet.
+    pub(super) fn idle_disks(
+        &self,
+        timeouts: &HashMap<String, Duration>,
+        now: Instant,
+    ) -> Vec<String> {
+        self.disks
+            .iter()
+            .filter(|(_, a)| a.state != SpinState::Standby)
+            .filter(|(kernel, a)| {
+                timeouts
+                    .get(kernel.as_str())
+                    .is_some_and(|t| now.duration_since(a.last_active) >= *t)
+            })
+            .map(|(kernel, _)| kernel.clone())
+            .collect()
+    }
+
+    /// Forget disks which are gone.
+    pub(super) fn retain(&mut self, disks: &[DiskInfo]) {
+        self.disks
+            .retain(|kernel, _| disks.iter().any(|d| d.kernel == *kernel));
+    }
+}
+
+/// Poll the counters of every rotational disk. Returns true if the spin
+/// state of any disk changed.
+pub(super) fn poll(tracker: &mut SpinTracker, disks: &[DiskInfo], now: Instant) -> bool {
+    tracker.retain(disks);
+
+    let mut changed = false;
+    for disk in disks.iter().filter(|d| d.rotational) {
+        if let Some(counters) = read_stat(&disk.kernel) {
+            changed |= tracker.update(&disk.kernel, counters, now);
+        }
+    }
+    changed
+}
+
+/// Idle timeouts in config are keyed by serial number or kernel name, this
+/// resolves them to kernel names of the attached disks.
+pub(super) fn resolve_timeouts(
+    config: &HashMap<String, u64>,
+    disks: &[DiskInfo],
+) -> HashMap<String, Duration> {
+    disks
+        .iter()
+        .filter_map(|d| {
+            let timeout = config
+                .get(&d.kernel)
+                .or_else(|| config.get(&d.serial).filter(|_| !d.serial.is_empty()))?;
+            Some((d.kernel.clone(), Duration::from_secs(*timeout)))
+        })
+        .filter(|(_, timeout)| !timeout.is_zero())
+        .collect()
+}
+
+/// Put the disk into standby mode, i.e. spin it down.
+pub(super) async fn standby(kernel: &str) -> Result<(), String> {
+    let output = Command::new("hdparm")
+        .arg("-y")
+        .arg(format!("/dev/{}", kernel))
+        .output()
+        .await
+        .map_err(|e| format!("Cannot run hdparm: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "hdparm failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Copy tracked spin states into the disk list and notify watchers.
+fn publish(data: &DiskCacheData, event_notifier: &EventNotifier) {
+    let spin = data.spin.lock().unwrap();
+    for disk in data.data.lock().unwrap().disks.iter_mut() {
+        disk.spin_state = spin.state(&disk.kernel);
+    }
+    drop(spin);
+
+    data.publish(event_notifier);
+}
+
+/// Poll disk activity and spin down disks idle for longer than `timeouts`,
+/// which are keyed by serial number or kernel name.
+pub(super) async fn check(
+    data: &DiskCacheData,
+    event_notifier: &EventNotifier,
+    timeouts: &HashMap<String, u64>,
+) {
+    let disks = data.data.lock().unwrap().disks.clone();
+    let timeouts = resolve_timeouts(timeouts, &disks);
+    let now = Instant::now();
+
+    let (mut changed, idle) = {
+        let mut spin = data.spin.lock().unwrap();
+        let changed = poll(&mut spin, &disks, now);
+        (changed, spin.idle_disks(&timeouts, now))
+    };
+
+    for kernel in idle.iter() {
+        let result = standby(kernel).await;
+        let mut spin = data.spin.lock().unwrap();
+        match result {
+            Ok(()) => {
+                log::info!("DiskCache - {} is idle, spun down", kernel);
+                spin.set_standby(kernel);
+                changed = true;
+            }
+            Err(e) => {
+                log::error!("DiskCache - Cannot spin down {}: {}", kernel, e);
+                spin.postpone(kernel, Instant::now());
+            }
+        }
+    }
+
+    if changed {
+        publish(data, event_notifier);
+    }
+}
+
+/// Spin down the disk holding the partition with `uuid` right now.
+pub(super) async fn force_standby(
+    data: DiskCacheData,
+    event_notifier: EventNotifier,
+    uuid: String,
+) -> Result<(), String> {
+    let kernel = data
+        .data
+        .lock()
+        .unwrap()
+        .disks
+        .iter()
+        .find(|d| d.partitions.iter().any(|p| p.uuid.to_string() == uuid))
+        .map(|d| d.kernel.clone())
+        .ok_or_else(|| format!("Unknown disk {}", uuid))?;
+
+    standby(&kernel).await?;
+    log::info!("DiskCache - {} is spun down on request", kernel);
+
+    let mut spin = data.spin.lock().unwrap();
+    if let Some(counters) = read_stat(&kernel) {
+        spin.update(&kernel, counters, Instant::now());
+    }
+    spin.set_standby(&kernel);
+    drop(spin);
+
+    publish(&data, &event_notifier);
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "./spindown_test.rs"]
+mod spindown_test;
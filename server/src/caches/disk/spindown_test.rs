@@ -0,0 +1,56 @@
+use super::*;
+
+const STAT: &str = "    4250      156   445034     2680     1133     1030    19512     4424        0     5552     7105        0        0        0        0";
+
+#[test]
+fn test_parse_stat() {
+    let counters = parse_stat(STAT).unwrap();
+    assert_eq!(counters.reads, 4250);
+    assert_eq!(counters.writes, 1133);
+    assert!(parse_stat("1 2 3").is_none());
+    assert!(parse_stat("").is_none());
+}
+
+#[test]
+fn test_idle_disks() {
+    let mut tracker = SpinTracker::default();
+    let start = Instant::now();
+    let busy = IoCounters {
+        reads: 1,
+        writes: 1,
+    };
+
+    assert!(!tracker.update("sda", busy, start));
+    assert_eq!(tracker.state("sda"), SpinState::Unknown);
+
+    let mut timeouts = HashMap::new();
+    timeouts.insert(String::from("sda"), Duration::from_secs(60));
+    assert!(tracker
+        .idle_disks(&timeouts, start + Duration::from_secs(30))
+        .is_empty());
+    assert_eq!(
+        tracker.idle_disks(&timeouts, start + Duration::from_secs(60)),
+        vec![String::from("sda")]
+    );
+
+    tracker.set_standby("sda");
+    assert_eq!(tracker.state("sda"), SpinState::Standby);
+    assert!(tracker
+        .idle_disks(&timeouts, start + Duration::from_secs(90))
+        .is_empty());
+
+    // Any I/O wakes the disk up and restarts the idle timer.
+    let later = start + Duration::from_secs(100);
+    assert!(tracker.update(
+        "sda",
+        IoCounters {
+            reads: 2,
+            writes: 1
+        },
+        later
+    ));
+    assert_eq!(tracker.state("sda"), SpinState::Active);
+    assert!(tracker
+        .idle_disks(&timeouts, later + Duration::from_secs(59))
+        .is_empty());
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use super::{registry, DiskCacheData};
+use crate::public::event_queue::EventNotifier;
+use crate::public::{DiskInfo, TrimResult};
+
+// _IOWR('X', 121, struct fstrim_range), see linux/fs.h
+const FITRIM: u64 = 0xc018_5879;
+
+#[repr(C)]
+struct FstrimRange {
+    start: u64,
+    len: u64,
+    minlen: u64,
+}
+
+/// Parse /sys/block/<dev>/queue/discard_max_bytes, 0 if the disk does not
+/// accept discard requests.
+pub(super) fn parse_discard_max(content: &str) -> bool {
+    content.trim().parse::<u64>().is_ok_and(|v| v > 0)
+}
+
+/// Whether the disk accepts discard requests at all.
+pub(super) fn supports_discard(kernel: &str) -> bool {
+    fs::read_to_string(format!("/sys/block/{}/queue/discard_max_bytes", kernel))
+        .is_ok_and(|v| parse_discard_max(&v))
+}
+
+/// Issue FITRIM on the filesystem mounted at `mount_point`, returning the
+/// number of bytes the filesystem reports as trimmed.
+fn fitrim(mount_point: &str) -> io::Result<u64> {
+    let dir = fs::File::open(mount_point)?;
+
+    let mut range = FstrimRange {
+        start: 0,
+        len: u64::MAX,
+        minlen: 0,
+    };
+    let ret = unsafe { libc::ioctl(dir.as_raw_fd(), FITRIM as _, &mut range as *mut FstrimRange) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(range.len)
+}
+
+/// Partitions of `disks` to trim: every mounted one on a discard capable
+/// disk, or only the one with `uuid`. A partition given by `uuid` which
+/// cannot be trimmed comes with the reason.
+pub(super) fn targets(disks: &[DiskInfo], uuid: Option<&str>) -> Vec<TrimResult> {
+    let mut targets = Vec::new();
+    for disk in disks {
+        for part in disk.partitions.iter() {
+            let part_uuid = part.uuid.to_string();
+            if uuid.is_some_and(|uuid| uuid != part_uuid) {
+                continue;
+            }
+
+            let mut result = TrimResult {
+                uuid: part_uuid,
+                kernel: part.kernel.clone(),
+                mount_point: part.mount_point().unwrap_or_default().to_owned(),
+                ..Default::default()
+            };
+            if !disk.discard {
+                result.error = Some(format!("{} does not support discard", disk.kernel));
+            } else if result.mount_point.is_empty() {
+                result.error = Some(format!("{} is not mounted", part.kernel));
+            }
+            targets.push(result);
+        }
+    }
+
+    match uuid {
+        Some(uuid) if targets.is_empty() => vec![TrimResult {
+            uuid: uuid.to_owned(),
+            error: Some(format!("Unknown disk {}", uuid)),
+            ..Default::default()
+        }],
+        Some(_) => targets,
+        None => {
+            // Trimming everything silently skips what cannot be trimmed.
+            targets.retain(|t| t.error.is_none());
+            targets
+        }
+    }
+}
+
+/// Trim every mounted partition on discard capable disks, or only the one
+/// with `uuid`. Results are recorded in the known disk registry.
+pub(super) async fn run(
+    data: DiskCacheData,
+    event_notifier: EventNotifier,
+    uuid: Option<String>,
+) -> Vec<TrimResult> {
+    // Only one trim pass runs at a time, a second request waits for the first.
+    let _guard = data.trimming.lock().await;
+
+    let targets = targets(&data.data.lock().unwrap().disks, uuid.as_deref());
+
+    let mut results = Vec::with_capacity(targets.len());
+    for mut target in targets.into_iter() {
+        if target.error.is_none() {
+            let mount_point = target.mount_point.clone();
+            match tokio::task::spawn_blocking(move || fitrim(&mount_point)).await {
+                Ok(Ok(trimmed)) => {
+                    log::info!(
+                        "DiskCache - Trimmed {} bytes on {}",
+                        trimmed,
+                        target.mount_point
+                    );
+                    target.trimmed = trimmed;
+                }
+                Ok(Err(e)) => target.error = Some(format!("FITRIM failed: {}", e)),
+                Err(e) => target.error = Some(format!("FITRIM task failed: {}", e)),
+            }
+        }
+        results.push(target);
+    }
+
+    let now = registry::now_secs();
+    let mut known = data.known.lock().unwrap();
+    let mut changed = false;
+    for r in results.iter().filter(|r| r.error.is_none()) {
+        changed |= known.record_trim(&r.uuid, r.trimmed, now);
+    }
+    if changed {
+        if let Err(e) = known.save() {
+            log::error!("DiskCache - Cannot save known disks: {}", e);
+        }
+        drop(known);
+        data.publish(&event_notifier);
+    }
+
+    results
+}
+
+#[cfg(test)]
+#[path = "./trim_test.rs"]
+mod trim_test;
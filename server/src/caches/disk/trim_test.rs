@@ -0,0 +1,67 @@
+use super::*;
+use crate::public::Partition;
+use uuid::Uuid;
+
+const UUID_A: &str = "0b7f5c7e-6c1b-4a0e-9a3a-2d1f1d8a1c01";
+const UUID_B: &str = "6a8e3c1d-2f4b-4f7a-8c9d-0e1f2a3b4c02";
+const UUID_C: &str = "9c1d2e3f-4a5b-4c6d-8e7f-0a1b2c3d4e03";
+
+fn partition(kernel: &str, uuid: &str, mount: Option<&str>) -> Partition {
+    Partition {
+        kernel: kernel.to_owned(),
+        uuid: Uuid::parse_str(uuid).unwrap(),
+        mount_path: mount.map(|m| vec![m.to_owned()]),
+        ..Default::default()
+    }
+}
+
+fn disks() -> Vec<DiskInfo> {
+    vec![
+        DiskInfo {
+            kernel: String::from("sda"),
+            discard: true,
+            partitions: vec![
+                partition("sda1", UUID_A, Some("/mnt/a")),
+                partition("sda2", UUID_B, None),
+            ],
+            ..Default::default()
+        },
+        DiskInfo {
+            kernel: String::from("sdb"),
+            discard: false,
+            partitions: vec![partition("sdb1", UUID_C, Some("/mnt/c"))],
+            ..Default::default()
+        },
+    ]
+}
+
+#[test]
+fn test_parse_discard_max() {
+    assert!(parse_discard_max("2147450880\n"));
+    assert!(!parse_discard_max("0\n"));
+    assert!(!parse_discard_max(""));
+}
+
+#[test]
+fn test_targets() {
+    // Everything: only mounted partitions of discard capable disks.
+    let all = targets(&disks(), None);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].uuid, UUID_A);
+    assert_eq!(all[0].mount_point, "/mnt/a");
+    assert!(all[0].error.is_none());
+
+    // A single partition tells why it cannot be trimmed.
+    let one = targets(&disks(), Some(UUID_B));
+    assert_eq!(one.len(), 1);
+    assert_eq!(one[0].error.as_deref(), Some("sda2 is not mounted"));
+    let one = targets(&disks(), Some(UUID_C));
+    assert_eq!(
+        one[0].error.as_deref(),
+        Some("sdb does not support discard")
+    );
+
+    let unknown = targets(&disks(), Some("missing"));
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(unknown[0].error.as_deref(), Some("Unknown disk missing"));
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+use super::DiskCacheData;
+use crate::public::job::{JobState, JobToken};
+use crate::public::{UsageNode, UsageProgress};
+
+/// Directories read at the same time.
+const WORKERS: usize = 4;
+const TREE_DEPTH: usize = 4;
+/// Largest subdirectories kept per directory of the tree.
+const TREE_CHILDREN: usize = 10;
+const LARGEST_FILES: usize = 20;
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Bytes and number of files directly in a directory, not in its
+/// subdirectories.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct DirUsage {
+    pub(super) bytes: u64,
+    pub(super) files: u64,
+}
+
+fn display(path: &Path) -> String {
+    format!("/{}", path.to_string_lossy())
+}
+
+/// The tree of the largest directories of `dirs`, keyed by path relative
+/// to the root, with sizes including subdirectories.
+pub(super) fn tree(dirs: &HashMap<PathBuf, DirUsage>) -> UsageNode {
+    let mut paths = dirs.keys().map(|p| p.as_path()).collect::<Vec<_>>();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+
+    let mut totals = dirs
+        .iter()
+        .map(|(p, u)| (p.as_path(), *u))
+        .collect::<HashMap<_, _>>();
+    let mut children = HashMap::<&Path, Vec<&Path>>::new();
+    for path in paths {
+        let parent = match path.parent() {
+            None => continue,
+            Some(parent) => parent,
+        };
+        let total = totals[path];
+        let parent_total = totals.entry(parent).or_default();
+        parent_total.bytes += total.bytes;
+        parent_total.files += total.files;
+        children.entry(parent).or_default().push(path);
+    }
+
+    fn build(
+        path: &Path,
+        depth: usize,
+        totals: &HashMap<&Path, DirUsage>,
+        children: &HashMap<&Path, Vec<&Path>>,
+    ) -> UsageNode {
+        let total = totals.get(path).copied().unwrap_or_default();
+        let mut node = UsageNode {
+            path: display(path),
+            bytes: total.bytes,
+            files: total.files,
+            children: Vec::new(),
+        };
+        if depth < TREE_DEPTH {
+            let mut subdirs = children.get(path).cloned().unwrap_or_default();
+            subdirs.sort_by_key(|p| std::cmp::Reverse(totals[p].bytes));
+            node.children = subdirs
+                .into_iter()
+                .take(TREE_CHILDREN)
+                .map(|p| build(p, depth + 1, totals, children))
+                .collect();
+        }
+        node
+    }
+    build(Path::new(""), 0, &totals, &children)
+}
+
+/// Insert `(bytes, path)` into `largest`, kept sorted from the largest.
+fn add_largest(largest: &mut Vec<(u64, PathBuf)>, bytes: u64, path: PathBuf) {
+    if largest.len() >= LARGEST_FILES && largest.last().is_some_and(|(b, _)| *b >= bytes) {
+        return;
+    }
+    let pos = largest.partition_point(|(b, _)| *b >= bytes);
+    largest.insert(pos, (bytes, path));
+    largest.truncate(LARGEST_FILES);
+}
+
+#[derive(Default)]
+struct Walk {
+    queue: Vec<PathBuf>,
+    busy: usize,
+    dirs: HashMap<PathBuf, DirUsage>,
+    largest: Vec<(u64, PathBuf)>,
+    errors: u64,
+    cancelled: bool,
+    /// Workers blocked on a pause of the job.
+    paused: usize,
+}
+
+impl Walk {
+    fn done(&self) -> bool {
+        self.cancelled || (self.queue.is_empty() && self.busy == 0)
+    }
+
+    fn state(&self) -> JobState {
+        match self.paused {
+            0 => JobState::Running,
+            _ => JobState::Paused,
+        }
+    }
+}
+
+/// State shared by the workers walking the partition.
+struct Walker {
+    token: JobToken,
+    root: PathBuf,
+    dev: u64,
+    walk: Mutex<Walk>,
+    cond: Condvar,
+}
+
+impl Walker {
+    /// Read the directory `dir`, relative to the root.
+    fn read_dir(&self, dir: &Path) {
+        let mut usage = DirUsage::default();
+        let mut subdirs = Vec::new();
+        let mut files = Vec::new();
+        let mut errors = 0;
+        match fs::read_dir(self.root.join(dir)) {
+            Err(_) => errors += 1,
+            Ok(entries) => {
+                for entry in entries {
+                    let (name, meta) = match entry.and_then(|e| Ok((e.file_name(), e.metadata()?)))
+                    {
+                        Ok(entry) => entry,
+                        Err(_) => {
+                            errors += 1;
+                            continue;
+                        }
+                    };
+                    // Other filesystems mounted below are not counted.
+                    if meta.is_dir() && meta.dev() == self.dev {
+                        subdirs.push(dir.join(name));
+                    } else if meta.is_file() {
+                        let bytes = meta.blocks() * 512;
+                        usage.bytes += bytes;
+                        usage.files += 1;
+                        files.push((bytes, dir.join(name)));
+                    }
+                }
+            }
+        }
+
+        let mut walk = self.walk.lock().unwrap();
+        walk.dirs.insert(dir.to_owned(), usage);
+        walk.queue.extend(subdirs);
+        for (bytes, path) in files {
+            add_largest(&mut walk.largest, bytes, path);
+        }
+        walk.errors += errors;
+        walk.busy -= 1;
+        self.cond.notify_all();
+    }
+
+    fn work(&self) {
+        loop {
+            let dir = {
+                let mut walk = self.walk.lock().unwrap();
+                loop {
+                    if walk.done() {
+                        return;
+                    }
+                    if let Some(dir) = walk.queue.pop() {
+                        walk.busy += 1;
+                        break dir;
+                    }
+                    walk = self.cond.wait(walk).unwrap();
+                }
+            };
+            let paused = |paused: bool| {
+                let mut walk = self.walk.lock().unwrap();
+                match paused {
+                    true => walk.paused += 1,
+                    false => walk.paused -= 1,
+                }
+                self.cond.notify_all();
+            };
+            if self.token.checkpoint_with(paused).is_err() {
+                let mut walk = self.walk.lock().unwrap();
+                walk.cancelled = true;
+                walk.busy -= 1;
+                self.cond.notify_all();
+                return;
+            }
+            self.read_dir(&dir);
+        }
+    }
+}
+
+struct Analyzer {
+    progress: watch::Sender<UsageProgress>,
+    current: UsageProgress,
+    cache: Arc<Mutex<HashMap<String, UsageProgress>>>,
+}
+
+impl Analyzer {
+    /// Report what `walk` found so far to the stream and the cache of the
+    /// partition.
+    fn publish(&mut self, walk: &Walk) {
+        self.current.root = tree(&walk.dirs);
+        self.current.largest_files = walk
+            .largest
+            .iter()
+            .map(|(bytes, path)| (display(path), *bytes))
+            .collect();
+        self.current.dirs = walk.dirs.len() as u64;
+        self.current.files = self.current.root.files;
+        self.current.bytes = self.current.root.bytes;
+        self.current.errors = walk.errors;
+        if !self.current.state.is_done() {
+            self.current.state = walk.state();
+        }
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(self.current.uuid.clone(), self.current.clone());
+        let _ = self.progress.send(self.current.clone());
+    }
+
+    fn run(&mut self, walker: &Walker) -> JobState {
+        walker.walk.lock().unwrap().queue.push(PathBuf::new());
+        thread::scope(|s| {
+            for _ in 0..WORKERS {
+                s.spawn(|| walker.work());
+            }
+            let mut last_publish = Instant::now();
+            let mut walk = walker.walk.lock().unwrap();
+            while !walk.done() {
+                let wait = PUBLISH_INTERVAL.saturating_sub(last_publish.elapsed());
+                if wait.is_zero() || walk.state() != self.current.state {
+                    self.publish(&walk);
+                    last_publish = Instant::now();
+                    continue;
+                }
+                walk = walker.cond.wait_timeout(walk, wait).unwrap().0;
+            }
+        });
+
+        let walk = walker.walk.lock().unwrap();
+        self.current.state = match walk.cancelled {
+            true => JobState::Cancelled,
+            false => JobState::Finished,
+        };
+        self.current.finished = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.publish(&walk);
+        self.current.state
+    }
+}
+
+/// Start analyzing the space used on partition `uuid` mounted at
+/// `mount_point`. The latest result is cached per partition.
+pub(super) fn start(
+    data: &DiskCacheData,
+    uuid: &str,
+    mount_point: &str,
+) -> Result<watch::Receiver<UsageProgress>, String> {
+    let dev = fs::metadata(mount_point)
+        .map_err(|e| format!("Cannot read {}: {}", mount_point, e))?
+        .dev();
+    // The run is cached right away, so that a second one is refused even
+    // before the first publishes.
+    let mut cache = data.usage.lock().unwrap();
+    let previous = cache.get(uuid).cloned();
+    if previous.as_ref().is_some_and(|p| !p.state.is_done()) {
+        return Err(format!("{} is already being analyzed", mount_point));
+    }
+    let token = data.jobs.register("usage");
+    let current = UsageProgress {
+        job_id: token.id(),
+        uuid: uuid.to_owned(),
+        mount_point: mount_point.to_owned(),
+        ..Default::default()
+    };
+    cache.insert(uuid.to_owned(), current.clone());
+    drop(cache);
+
+    let (tx, rx) = watch::channel(current.clone());
+    let mut analyzer = Analyzer {
+        progress: tx,
+        current,
+        cache: data.usage.clone(),
+    };
+    let walker = Walker {
+        token,
+        root: PathBuf::from(mount_point),
+        dev,
+        walk: Mutex::new(Walk::default()),
+        cond: Condvar::new(),
+    };
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || {
+        log::info!(
+            "DiskCache - Analyzing space used on {}",
+            analyzer.current.mount_point
+        );
+        let state = analyzer.run(&walker);
+        data.jobs.unregister(&walker.token);
+
+        // A cancelled run does not replace the previous result.
+        if state != JobState::Finished {
+            if let Some(previous) = previous.filter(|p| p.state == JobState::Finished) {
+                data.usage
+                    .lock()
+                    .unwrap()
+                    .insert(previous.uuid.clone(), previous);
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+#[path = "./usage_test.rs"]
+mod usage_test;
@@ -0,0 +1,77 @@
+use super::*;
+use crate::public::job::Jobs;
+use crate::test_util::TempDir;
+
+fn usage(bytes: u64, files: u64) -> DirUsage {
+    DirUsage { bytes, files }
+}
+
+#[test]
+fn test_tree() {
+    let dirs = vec![
+        ("", usage(1, 1)),
+        ("a", usage(10, 1)),
+        ("a/x", usage(100, 2)),
+        ("b", usage(50, 5)),
+    ]
+    .into_iter()
+    .map(|(p, u)| (PathBuf::from(p), u))
+    .collect();
+
+    let root = tree(&dirs);
+    assert_eq!(root.path, "/");
+    assert_eq!((root.bytes, root.files), (161, 9));
+    let children = root
+        .children
+        .iter()
+        .map(|c| (c.path.as_str(), c.bytes))
+        .collect::<Vec<_>>();
+    assert_eq!(children, vec![("/a", 110), ("/b", 50)]);
+    assert_eq!(root.children[0].children[0].path, "/a/x");
+}
+
+#[test]
+fn test_add_largest() {
+    let mut largest = Vec::new();
+    for bytes in 0..(LARGEST_FILES as u64 + 5) {
+        add_largest(&mut largest, bytes, PathBuf::from(bytes.to_string()));
+    }
+    assert_eq!(largest.len(), LARGEST_FILES);
+    assert_eq!(largest[0].0, LARGEST_FILES as u64 + 4);
+    assert_eq!(largest.last().unwrap().0, 5);
+}
+
+#[test]
+fn test_run() {
+    let base = TempDir::new("usage");
+    fs::create_dir_all(base.join("photos/2021")).unwrap();
+    fs::create_dir_all(base.join("empty")).unwrap();
+    fs::write(base.join("photos/2021/a.jpg"), vec![1u8; 64 << 10]).unwrap();
+    fs::write(base.join("photos/b.jpg"), vec![1u8; 8 << 10]).unwrap();
+
+    let (tx, _rx) = watch::channel(UsageProgress::default());
+    let cache = Arc::new(Mutex::new(HashMap::new()));
+    let mut analyzer = Analyzer {
+        progress: tx,
+        current: UsageProgress {
+            uuid: String::from("uuid"),
+            ..Default::default()
+        },
+        cache: cache.clone(),
+    };
+    let walker = Walker {
+        token: Jobs::default().register("test"),
+        dev: fs::metadata(&base).unwrap().dev(),
+        root: base.to_path_buf(),
+        walk: Mutex::new(Walk::default()),
+        cond: Condvar::new(),
+    };
+    assert_eq!(analyzer.run(&walker), JobState::Finished);
+
+    let result = cache.lock().unwrap()["uuid"].clone();
+    assert_eq!((result.dirs, result.files), (4, 2));
+    assert!(result.bytes >= 72 << 10);
+    assert_eq!(result.root.children[0].path, "/photos");
+    assert_eq!(result.root.children[0].children[0].path, "/photos/2021");
+    assert_eq!(result.largest_files[0].0, "/photos/2021/a.jpg");
+}
@@ -1,13 +1,15 @@
+use futures::future::BoxFuture;
 use log;
 use std::sync::{Arc, Mutex};
-use tokio::time::{sleep, Duration};
+use tokio::time::Duration;
 
-use super::{Cache, CacheHandler};
-use crate::public::event_queue::{Event, EventNotifier};
+use super::{Cache, Schedule};
+use crate::public::event_queue::EventNotifier;
 use crate::public::shutdown;
-use crate::public::{PreservedServiceData, ServiceData, ServiceType};
+use crate::public::{PreservedServiceData, ServiceType};
 
-const THIS_TYPE: ServiceType = ServiceType::_PRESERVED;
+const THIS_TYPE: ServiceType = ServiceType::new("hello");
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
 
 struct DataGenerator {
     data: HelloCacheData,
@@ -22,15 +24,15 @@ impl DataGenerator {
         }
     }
 
-    async fn run(&mut self, mut shutdown: shutdown::Receiver) {
+    async fn run(&mut self, mut shutdown: shutdown::Receiver, schedule: Schedule) {
         log::info!("Hello Cache - Data generator is running");
         loop {
             tokio::select! {
-                _ = sleep(Duration::from_millis(5000)) => {
+                refresh = schedule.next() => {
                     log::info!("Hello Cache - New data is generated");
-                    let mut d = self.data.data.lock().unwrap();
-                    *d += 1;
-                    self.event_notifier.push(Event{ service_type: THIS_TYPE });
+                    *self.data.data.lock().unwrap() += 1;
+                    let data = self.data.clone();
+                    refresh.done(self.event_notifier.push(THIS_TYPE, || Arc::new(data.snapshot())));
                 }
                 _ = shutdown.wait_on() => {
                     log::warn!("Hello Cache - Data generator is shutting down");
@@ -52,13 +54,15 @@ impl HelloCacheData {
             data: Arc::new(Mutex::new(0)),
         }
     }
+
+    fn snapshot(&self) -> PreservedServiceData {
+        let d = self.data.lock().unwrap();
+        PreservedServiceData { data: *d }
+    }
 }
 
 #[derive(Debug)]
-pub(crate) struct HelloCacheHandler {
-    service_type: ServiceType,
-    data: HelloCacheData,
-}
+pub(crate) struct HelloCacheHandler {}
 
 impl HelloCacheHandler {
     pub(crate) fn disk_mount(&self) {
@@ -66,53 +70,41 @@ impl HelloCacheHandler {
     }
 }
 
-impl CacheHandler for HelloCacheHandler {
-    fn fetch(&self) -> ServiceData {
-        let d = self.data.data.lock().unwrap();
-        ServiceData::Preserved(PreservedServiceData { data: *d })
-    }
-
-    fn get_type(&self) -> ServiceType {
-        self.service_type
-    }
-}
-
 impl HelloCacheHandler {}
 
-pub(super) struct HelloCache {
+pub(crate) struct HelloCache {
     event_notifier: EventNotifier,
     service_type: ServiceType,
     data: HelloCacheData,
 }
 
 impl HelloCache {
-    pub(super) fn new(event_notifier: EventNotifier) -> (Self, HelloCacheHandler) {
+    pub(crate) fn new(event_notifier: EventNotifier) -> (Self, HelloCacheHandler) {
         let data = HelloCacheData::new();
         let cache = HelloCache {
-            data: data.clone(),
+            data,
             event_notifier,
             service_type: THIS_TYPE,
         };
 
-        let cache_handler = HelloCacheHandler {
-            service_type: THIS_TYPE,
-            data,
-        };
-
-        (cache, cache_handler)
+        (cache, HelloCacheHandler {})
     }
 }
 
 impl Cache for HelloCache {
-    fn run(&self, shutdown: shutdown::Receiver) {
+    fn run(&self, shutdown: shutdown::Receiver, schedule: Schedule) -> BoxFuture<'static, ()> {
         log::info!("HelloCache is running");
         let mut generator = DataGenerator::new(self.data.clone(), self.event_notifier.clone());
-        tokio::spawn(async move {
-            generator.run(shutdown).await;
-        });
+        Box::pin(async move {
+            generator.run(shutdown, schedule).await;
+        })
     }
 
     fn get_type(&self) -> ServiceType {
         self.service_type
     }
+
+    fn refresh_interval(&self) -> Duration {
+        REFRESH_INTERVAL
+    }
 }
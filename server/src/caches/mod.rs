@@ -1,83 +1,208 @@
-use crate::public::event_queue::EventNotifier;
+use futures::future::{join_all, BoxFuture};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::time::Duration;
+
+use crate::metrics::Histogram;
 use crate::public::shutdown;
-use crate::public::{ServiceData, ServiceType};
+use crate::public::{HealthState, ServiceType};
 
 mod hello;
-use hello::{HelloCache, HelloCacheHandler};
+pub(crate) use hello::{HelloCache, HelloCacheHandler};
 mod disk;
-use disk::{DiskCache, DiskCacheHandler};
+pub(crate) use disk::{DiskCache, DiskCacheHandler, FileSandbox, THIS_TYPE as DISK_SERVICE};
+mod schedule;
+pub(crate) use schedule::{Refreshers, Schedule};
+mod supervisor;
+pub(crate) use supervisor::Health;
+use supervisor::Policy;
 
 pub(crate) trait Cache {
-    fn run(&self, shutdown: shutdown::Receiver);
+    /// Task generating the data of the cache, refreshing it as `schedule`
+    /// says. Started again by the supervisor if it fails.
+    fn run(&self, shutdown: shutdown::Receiver, schedule: Schedule) -> BoxFuture<'static, ()>;
     fn get_type(&self) -> ServiceType;
+    /// Interval between scheduled refreshes, unless configured.
+    fn refresh_interval(&self) -> Duration;
+    /// Persist the state of the cache once its task is stopped, on shutdown.
+    fn flush(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
 }
 
-/// Used to fetch data from cache
-pub(crate) trait CacheHandler {
-    fn fetch(&self) -> ServiceData;
-    fn get_type(&self) -> ServiceType;
+/// A registered cache, with its supervisor while it runs.
+struct Service {
+    cache: Arc<dyn Cache + Send + Sync>,
+    schedule: Schedule,
+    running: Option<shutdown::Sender>,
 }
 
-pub(crate) struct CacheManagerHandler {
-    shutdown: shutdown::Sender,
+impl Service {
+    fn name(&self) -> &'static str {
+        self.cache.get_type().name()
+    }
+
+    /// Run the cache under a supervisor, which restarts it if it crashes.
+    /// Its health is entry `index` of `health`.
+    fn start(&mut self, index: usize, health: &Health) {
+        let (sender, receiver) = shutdown::new();
+        health.update(index, |h| h.state = HealthState::Running);
+        tokio::spawn(supervisor::supervise(
+            self.cache.clone(),
+            self.schedule.clone(),
+            health.clone(),
+            index,
+            Policy::default(),
+            receiver,
+        ));
+        self.running = Some(sender);
+    }
+
+    /// Stop the cache task, then flush the cache.
+    async fn stop(&mut self) {
+        if let Some(sender) = self.running.take() {
+            sender.shutdown().await;
+            log::warn!("Cache manager flushes {:?}...", self.cache.get_type());
+            self.cache.flush().await;
+        }
+    }
 }
 
-#[derive(Debug)]
-pub(crate) enum Handler {
-    Hello(HelloCacheHandler),
-    Disk(DiskCacheHandler),
+/// Refresh interval of `cache`, as configured or its own.
+fn refresh_interval(cache: &dyn Cache, refresh_intervals: &HashMap<String, u64>) -> Duration {
+    match refresh_intervals.get(cache.get_type().name()) {
+        Some(secs) => Duration::from_secs(*secs),
+        None => cache.refresh_interval(),
+    }
+}
+
+/// Controls the running caches, shared by shutdown and config reload.
+#[derive(Clone)]
+pub(crate) struct CacheManagerHandler {
+    inner: Arc<TokioMutex<CacheManager>>,
 }
 
 impl CacheManagerHandler {
+    /// Stop the cache tasks and flush the caches, for good.
     pub(crate) async fn shutdown(&self) {
-        self.shutdown.shutdown().await;
+        let mut manager = self.inner.lock().await;
+        manager.shut_down = true;
+        log::warn!("Cache manager stops caches...");
+        join_all(manager.services.iter_mut().map(Service::stop)).await;
+    }
+
+    /// Run the caches of the services not in `disabled`, stop the others.
+    pub(crate) async fn set_disabled(&self, disabled: &HashSet<String>) {
+        let mut manager = self.inner.lock().await;
+        if manager.shut_down {
+            return;
+        }
+        let health = manager.health.clone();
+        for (index, service) in manager.services.iter_mut().enumerate() {
+            let enabled = !disabled.contains(service.name());
+            if enabled && service.running.is_none() {
+                log::warn!("Cache manager starts {}", service.name());
+                service.start(index, &health);
+            } else if !enabled && service.running.is_some() {
+                log::warn!("Cache manager stops {}", service.name());
+                service.stop().await;
+            }
+        }
+    }
+
+    /// Refresh intervals in seconds keyed by service name, services not in
+    /// `refresh_intervals` go back to their own interval.
+    pub(crate) async fn set_refresh_intervals(&self, refresh_intervals: &HashMap<String, u64>) {
+        let manager = self.inner.lock().await;
+        for service in &manager.services {
+            let interval = refresh_interval(&*service.cache, refresh_intervals);
+            service.schedule.set_interval(interval);
+        }
     }
 }
 
 pub(crate) struct CacheManager {
-    caches: Vec<Option<Box<dyn Cache + Send + Sync>>>,
+    services: Vec<Service>,
+    health: Health,
+    refreshers: Refreshers,
+    shut_down: bool,
 }
 
 impl CacheManager {
-    pub(crate) fn new() -> Self {
-        let mut caches = Vec::with_capacity(ServiceType::LEN as usize);
-        caches.resize_with(ServiceType::LEN as usize, || None);
+    /// Manage the caches of the registered services, `refresh_intervals`
+    /// overrides their refresh interval in seconds, keyed by service name.
+    pub(crate) fn new(
+        caches: Vec<Box<dyn Cache + Send + Sync>>,
+        refresh_intervals: &HashMap<String, u64>,
+    ) -> Self {
+        for name in refresh_intervals.keys() {
+            if !caches.iter().any(|c| c.get_type().name() == name) {
+                log::warn!("Refresh interval of unknown service {}", name);
+            }
+        }
 
-        CacheManager { caches }
-    }
+        let health = Health::default();
+        let mut refreshers = HashMap::new();
+        let services = caches
+            .into_iter()
+            .map(|cache| {
+                let service_type = cache.get_type();
+                health.add(service_type);
+                let interval = refresh_interval(&*cache, refresh_intervals);
+                let (schedule, requests) = Schedule::new(interval);
+                refreshers.insert(service_type, requests);
+                Service {
+                    cache: Arc::from(cache),
+                    schedule,
+                    running: None,
+                }
+            })
+            .collect();
 
-    pub(crate) fn create_caches(
-        &mut self,
-        event_notifier: EventNotifier,
-    ) -> Vec<(ServiceType, Handler)> {
-        let mut ret = Vec::new();
+        CacheManager {
+            services,
+            health,
+            refreshers: Refreshers::new(refreshers),
+            shut_down: false,
+        }
+    }
 
-        // Add hello cache
-        let (cache, handler) = HelloCache::new(event_notifier.clone());
-        ret.push((handler.get_type(), Handler::Hello(handler)));
-        self.add_cache(cache.get_type(), Box::new(cache));
+    pub(crate) fn health(&self) -> Health {
+        self.health.clone()
+    }
 
-        // Add disk cache
-        let (cache, handler) = DiskCache::new(event_notifier.clone());
-        ret.push((handler.get_type(), Handler::Disk(handler)));
-        self.add_cache(cache.get_type(), Box::new(cache));
+    pub(crate) fn refreshers(&self) -> Refreshers {
+        self.refreshers.clone()
+    }
 
-        ret
+    /// How long the refreshes of every service took.
+    pub(crate) fn refresh_durations(&self) -> Vec<(ServiceType, Histogram)> {
+        self.services
+            .iter()
+            .map(|s| (s.cache.get_type(), s.schedule.durations()))
+            .collect()
     }
 
-    pub(crate) fn run(&mut self) -> CacheManagerHandler {
-        let (sender, receiver) = shutdown::new();
-        for c in self.caches.iter() {
-            match c {
-                Some(c) => c.run(receiver.clone()),
-                _ => continue,
+    /// Run every cache but the `disabled` ones, each under a supervisor.
+    /// Disabled caches can be started later through the handler.
+    pub(crate) fn run(mut self, disabled: &HashSet<String>) -> CacheManagerHandler {
+        for name in disabled {
+            if !self.services.iter().any(|s| s.name() == name) {
+                log::warn!("Unknown service {} is disabled", name);
             }
         }
-        CacheManagerHandler { shutdown: sender }
-    }
-
-    pub fn add_cache(&mut self, service_type: ServiceType, cache: Box<dyn Cache + Send + Sync>) {
-        let idx = service_type as usize;
-        self.caches[idx] = Some(cache);
+        for (index, service) in self.services.iter_mut().enumerate() {
+            if disabled.contains(service.name()) {
+                log::warn!("Service {} is disabled", service.name());
+                self.health
+                    .update(index, |h| h.state = HealthState::Stopped);
+            } else {
+                service.start(index, &self.health);
+            }
+        }
+        CacheManagerHandler {
+            inner: Arc::new(TokioMutex::new(self)),
+        }
     }
 }
@@ -0,0 +1,151 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as TokioMutex;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::metrics::Histogram;
+use crate::public::ServiceType;
+
+/// Scheduled refreshes are spread by up to this fraction of the interval,
+/// so services polled at the same interval do not refresh together.
+const JITTER: f64 = 0.1;
+const PENDING_REQUESTS: usize = 8;
+
+/// Replies with the sequence number of the event carrying the new data.
+type Request = oneshot::Sender<u64>;
+
+/// `interval` randomly stretched or shrunk by up to `JITTER`.
+pub(super) fn jitter(interval: Duration) -> Duration {
+    // The std hasher is randomly keyed, hashing nothing gives a random u64.
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = random as f64 / u64::MAX as f64 * 2.0 - 1.0;
+    interval.mul_f64(1.0 + JITTER * fraction)
+}
+
+/// A refresh a cache is due for, scheduled or requested through the API.
+/// It lasts until dropped.
+pub(crate) struct Refresh {
+    reply: Option<Request>,
+    started: Instant,
+    durations: Histogram,
+}
+
+impl Refresh {
+    pub(crate) fn requested(&self) -> bool {
+        self.reply.is_some()
+    }
+
+    /// Report the new data was published with `sequence`.
+    pub(crate) fn done(mut self, sequence: u64) {
+        if let Some(reply) = self.reply.take() {
+            let _ = reply.send(sequence);
+        }
+    }
+}
+
+impl Drop for Refresh {
+    fn drop(&mut self) {
+        self.durations.observe(self.started.elapsed());
+    }
+}
+
+/// When a cache refreshes its data: every interval, with jitter, and on
+/// request. Kept across restarts of the cache task.
+#[derive(Clone)]
+pub(crate) struct Schedule {
+    interval: Arc<Mutex<Duration>>,
+    /// Notified when the interval changes, to schedule again.
+    reset: Arc<Notify>,
+    next: Arc<TokioMutex<Instant>>,
+    requests: Arc<TokioMutex<mpsc::Receiver<Request>>>,
+    durations: Histogram,
+}
+
+impl Schedule {
+    pub(super) fn new(interval: Duration) -> (Self, mpsc::Sender<Request>) {
+        let (tx, rx) = mpsc::channel(PENDING_REQUESTS);
+        let schedule = Self {
+            interval: Arc::new(Mutex::new(interval)),
+            reset: Arc::new(Notify::new()),
+            next: Arc::new(TokioMutex::new(Instant::now() + jitter(interval))),
+            requests: Arc::new(TokioMutex::new(rx)),
+            durations: Histogram::default(),
+        };
+        (schedule, tx)
+    }
+
+    /// Wait for the next refresh. The deadline is kept if the future is
+    /// dropped, so it can be polled in a select loop.
+    pub(crate) async fn next(&self) -> Refresh {
+        let mut next = self.next.lock().await;
+        let mut requests = self.requests.lock().await;
+        let reply = loop {
+            tokio::select! {
+                _ = sleep_until(*next) => break None,
+                Some(reply) = requests.recv() => break Some(reply),
+                _ = self.reset.notified() => *next = Instant::now() + jitter(self.interval()),
+            }
+        };
+        *next = Instant::now() + jitter(self.interval());
+        Refresh {
+            reply,
+            started: Instant::now(),
+            durations: self.durations.clone(),
+        }
+    }
+
+    /// How long the refreshes took.
+    pub(super) fn durations(&self) -> Histogram {
+        self.durations.clone()
+    }
+
+    fn interval(&self) -> Duration {
+        *self.interval.lock().unwrap()
+    }
+
+    /// Refresh every `interval` from now on, the next refresh is scheduled
+    /// again.
+    pub(super) fn set_interval(&self, interval: Duration) {
+        *self.interval.lock().unwrap() = interval;
+        self.reset.notify_one();
+    }
+}
+
+/// Triggers of the refreshes of every service, used by the Refresh RPC.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Refreshers {
+    inner: Arc<HashMap<ServiceType, mpsc::Sender<Request>>>,
+}
+
+impl Refreshers {
+    pub(super) fn new(inner: HashMap<ServiceType, mpsc::Sender<Request>>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Refresh the service named `service` now, returns the sequence number
+    /// of the event carrying the new data once it is published.
+    pub(crate) async fn refresh(&self, service: &str) -> Result<u64, String> {
+        let requests = self
+            .inner
+            .iter()
+            .find(|(s, _)| s.name() == service)
+            .map(|(_, requests)| requests)
+            .ok_or_else(|| format!("Unknown service {}", service))?;
+        let (tx, rx) = oneshot::channel();
+        requests
+            .send(tx)
+            .await
+            .map_err(|_| format!("Service {} is not running", service))?;
+        rx.await
+            .map_err(|_| format!("Service {} stopped before refreshing", service))
+    }
+}
+
+#[cfg(test)]
+#[path = "./schedule_test.rs"]
+mod schedule_test;
@@ -0,0 +1,48 @@
+use super::*;
+use tokio::time::sleep;
+
+#[test]
+fn test_jitter() {
+    let interval = Duration::from_secs(100);
+    let delays = (0..100).map(|_| jitter(interval)).collect::<Vec<_>>();
+    assert!(delays
+        .iter()
+        .all(|d| *d >= Duration::from_secs(90) && *d <= Duration::from_secs(110)));
+    assert!(delays.iter().any(|d| *d != delays[0]));
+}
+
+#[tokio::test]
+async fn test_scheduled_and_requested() {
+    const THIS_TYPE: ServiceType = ServiceType::new("test");
+    let (schedule, requests) = Schedule::new(Duration::from_millis(10));
+    let mut map = HashMap::new();
+    map.insert(THIS_TYPE, requests);
+    let refreshers = Refreshers::new(map);
+
+    assert!(!schedule.next().await.requested());
+
+    let cache = tokio::spawn(async move {
+        // A long interval, only the request wakes it up.
+        schedule.set_interval(Duration::from_secs(3600));
+        let refresh = schedule.next().await;
+        assert!(refresh.requested());
+        refresh.done(42);
+    });
+    assert_eq!(refreshers.refresh("test").await, Ok(42));
+    cache.await.unwrap();
+
+    assert!(refreshers.refresh("other").await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_interval() {
+    let (schedule, _requests) = Schedule::new(Duration::from_secs(3600));
+    let waiting = schedule.clone();
+    let refresh = tokio::spawn(async move { waiting.next().await.requested() });
+    sleep(Duration::from_millis(10)).await;
+
+    // The pending refresh is scheduled again with the new interval.
+    schedule.set_interval(Duration::from_millis(10));
+    let refresh = tokio::time::timeout(Duration::from_secs(1), refresh).await;
+    assert!(!refresh.unwrap().unwrap());
+}
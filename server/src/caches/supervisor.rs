@@ -0,0 +1,137 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinError;
+use tokio::time::{sleep, Duration, Instant};
+
+use super::{Cache, Schedule};
+use crate::public::shutdown;
+use crate::public::{HealthState, ServiceHealth, ServiceType};
+
+/// When crashed cache tasks are started again.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Policy {
+    /// Delay before the first restart, doubled after every failure.
+    pub(super) initial_backoff: Duration,
+    pub(super) max_backoff: Duration,
+    /// A task running for this long is healthy again, its next failure is
+    /// restarted after `initial_backoff`.
+    pub(super) stable_after: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            stable_after: Duration::from_secs(600),
+        }
+    }
+}
+
+impl Policy {
+    /// Delay before restarting a task which failed `failures` times in a
+    /// row.
+    pub(super) fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |d| d.min(self.max_backoff))
+    }
+}
+
+/// Health of every supervised service, in registration order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Health {
+    inner: Arc<Mutex<Vec<ServiceHealth>>>,
+}
+
+impl Health {
+    pub(crate) fn list(&self) -> Vec<ServiceHealth> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    pub(super) fn add(&self, service: ServiceType) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        inner.push(ServiceHealth {
+            service,
+            state: HealthState::Running,
+            restarts: 0,
+            last_error: None,
+            last_error_time: 0,
+        });
+        inner.len() - 1
+    }
+
+    pub(super) fn update<F: FnOnce(&mut ServiceHealth)>(&self, index: usize, f: F) {
+        f(&mut self.inner.lock().unwrap()[index]);
+    }
+}
+
+fn panic_message(e: JoinError) -> String {
+    if !e.is_panic() {
+        return e.to_string();
+    }
+    let payload: Box<dyn Any + Send> = e.into_panic();
+    match payload.downcast::<String>() {
+        Ok(message) => format!("Panicked: {}", message),
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => format!("Panicked: {}", message),
+            Err(_) => String::from("Panicked"),
+        },
+    }
+}
+
+/// Run the task of `cache` until shutdown, starting it again with
+/// exponential backoff whenever it panics or returns early. Its health is
+/// entry `index` of `health`.
+pub(super) async fn supervise(
+    cache: Arc<dyn Cache + Send + Sync>,
+    schedule: Schedule,
+    health: Health,
+    index: usize,
+    policy: Policy,
+    mut shutdown: shutdown::Receiver,
+) {
+    let service = cache.get_type();
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        let result = tokio::spawn(cache.run(shutdown.clone(), schedule.clone())).await;
+        if shutdown.is_shutdown() {
+            break;
+        }
+
+        let error = match result {
+            Ok(()) => String::from("Exited before shutdown"),
+            Err(e) => panic_message(e),
+        };
+        if started.elapsed() >= policy.stable_after {
+            failures = 0;
+        }
+        failures += 1;
+        let delay = policy.backoff(failures);
+        log::error!("{:?} failed: {}, restarting in {:?}", service, error, delay);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        health.update(index, |h| {
+            h.state = HealthState::Restarting;
+            h.restarts += 1;
+            h.last_error = Some(error);
+            h.last_error_time = now;
+        });
+
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = shutdown.wait_on() => break,
+        }
+        health.update(index, |h| h.state = HealthState::Running);
+    }
+    health.update(index, |h| h.state = HealthState::Stopped);
+}
+
+#[cfg(test)]
+#[path = "./supervisor_test.rs"]
+mod supervisor_test;
@@ -0,0 +1,83 @@
+use super::*;
+use futures::future::BoxFuture;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const THIS_TYPE: ServiceType = ServiceType::new("test");
+
+/// Panics on its first `failures` runs, then runs until shutdown.
+struct FlakyCache {
+    runs: Arc<AtomicU32>,
+    failures: u32,
+}
+
+impl Cache for FlakyCache {
+    fn run(&self, mut shutdown: shutdown::Receiver, _: Schedule) -> BoxFuture<'static, ()> {
+        let run = self.runs.fetch_add(1, Ordering::SeqCst);
+        let failures = self.failures;
+        Box::pin(async move {
+            if run < failures {
+                panic!("run {}", run);
+            }
+            shutdown.wait_on().await;
+        })
+    }
+
+    fn get_type(&self) -> ServiceType {
+        THIS_TYPE
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let policy = Policy::default();
+    assert_eq!(policy.backoff(1), Duration::from_secs(1));
+    assert_eq!(policy.backoff(4), Duration::from_secs(8));
+    assert_eq!(policy.backoff(9), Duration::from_secs(256));
+    assert_eq!(policy.backoff(10), Duration::from_secs(300));
+    assert_eq!(policy.backoff(100), Duration::from_secs(300));
+}
+
+#[tokio::test]
+async fn test_restart_crashed_task() {
+    let runs = Arc::new(AtomicU32::new(0));
+    let cache = FlakyCache {
+        runs: runs.clone(),
+        failures: 3,
+    };
+    let policy = Policy {
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(10),
+        stable_after: Duration::from_secs(60),
+    };
+    let health = Health::default();
+    let index = health.add(THIS_TYPE);
+    let (sender, receiver) = shutdown::new();
+    let (schedule, _requests) = Schedule::new(Duration::from_secs(60));
+    let task = tokio::spawn(supervise(
+        Arc::new(cache),
+        schedule,
+        health.clone(),
+        index,
+        policy,
+        receiver,
+    ));
+
+    while runs.load(Ordering::SeqCst) < 4 {
+        sleep(Duration::from_millis(5)).await;
+    }
+    let status = &health.list()[0];
+    assert_eq!(status.service, THIS_TYPE);
+    assert_eq!(status.state, HealthState::Running);
+    assert_eq!(status.restarts, 3);
+    assert_eq!(status.last_error.as_deref(), Some("Panicked: run 2"));
+    assert!(status.last_error_time > 0);
+
+    sender.shutdown().await;
+    task.await.unwrap();
+    assert_eq!(health.list()[0].state, HealthState::Stopped);
+    assert_eq!(runs.load(Ordering::SeqCst), 4);
+}
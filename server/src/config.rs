@@ -1,5 +1,786 @@
+use log::LevelFilter;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use toml::Spanned;
+
+/// Configuration file read when neither --config nor PICONTROLX_CONFIG is
+/// given, it may be missing.
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/picontrolx/server.toml";
+/// Every setting can be given as an environment variable, named after its
+/// flag, e.g. PICONTROLX_STATE_DIR for --state-dir.
+pub const ENV_PREFIX: &str = "PICONTROLX_";
+/// Settings of `Config::set`, named after their command line flag.
+pub const SETTINGS: &[&str] = &[
+    "ip",
+    "port",
+    "state-dir",
+    "shutdown-timeout",
+    "metrics-listen",
+    "log-level",
+    "log-output",
+    "log-format",
+    "log-file",
+    "log-file-size",
+    "log-file-count",
+    "disable-service",
+    "refresh-interval",
+    "trim-interval",
+    "idle-timeout",
+    "backup",
+    "import-library",
+    "import-unmount",
+    "smb-conf",
+    "nfs-exports",
+    "fill-warning-days",
+];
+/// Settings a reload applies while running, others need a restart.
+pub const LIVE_SETTINGS: &[&str] = &["log-level", "disable-service", "refresh-interval"];
+/// Shortest refresh interval of a service, in seconds. Shorter ones keep
+/// the caches busy and disks awake for nothing.
+const MIN_REFRESH_INTERVAL: u64 = 5;
+/// Settings which may be given several times. Their environment variables
+/// hold whitespace separated values.
+const MULTIPLE: &[&str] = &[
+    "disable-service",
+    "refresh-interval",
+    "idle-timeout",
+    "backup",
+];
+
+#[derive(Clone, Debug)]
 pub struct Config {
+    /// Path of the configuration file.
     pub config: String,
     pub ip: String,
     pub port: u16,
+    pub state_dir: String,
+    pub log: LogConfig,
+    /// Names of the services which are not started.
+    pub disabled_services: HashSet<String>,
+    /// Seconds between scheduled refreshes of a service, keyed by service
+    /// name, overriding the interval of the service.
+    pub refresh_intervals: HashMap<String, u64>,
+    /// Seconds the graceful shutdown may take before the process exits
+    /// anyway.
+    pub shutdown_timeout: u64,
+    /// Address of the HTTP listener serving Prometheus metrics, none if
+    /// metrics are not served.
+    pub metrics_listen: Option<SocketAddr>,
+    pub disk: DiskConfig,
 }
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config: DEFAULT_CONFIG_FILE.to_owned(),
+            ip: String::from("[::1]"),
+            port: 50051,
+            state_dir: String::from("/var/lib/picontrolx"),
+            log: LogConfig::default(),
+            disabled_services: HashSet::new(),
+            refresh_intervals: HashMap::new(),
+            shutdown_timeout: 10,
+            metrics_listen: None,
+            disk: DiskConfig {
+                smb_conf: String::from("/etc/samba/picontrolx.conf"),
+                nfs_exports: String::from("/etc/exports.d/picontrolx.exports"),
+                fill_warning_days: 7.0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from, by increasing precedence: defaults,
+    /// the configuration file, PICONTROLX_* variables of `env` and command
+    /// line `flags`. Settings given several times are merged by key, e.g.
+    /// a flag overrides the idle timeout of one disk only.
+    pub fn load<'a, E, F>(path: Option<&str>, env: E, flags: F) -> Result<Config, String>
+    where
+        E: IntoIterator<Item = (String, String)>,
+        F: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let env = env
+            .into_iter()
+            .filter(|(var, _)| var.starts_with(ENV_PREFIX))
+            .collect::<HashMap<_, _>>();
+        let explicit = path.or_else(|| env.get(&env_var("config")).map(String::as_str));
+        let path = explicit.unwrap_or(DEFAULT_CONFIG_FILE);
+
+        let mut config = Config {
+            config: path.to_owned(),
+            ..Default::default()
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => config
+                .apply_file(&text)
+                .map_err(|e| format!("{}: {}", path, e))?,
+            Err(e) if explicit.is_none() && e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Cannot read {}: {}", path, e)),
+        }
+
+        for setting in SETTINGS {
+            let var = env_var(setting);
+            if let Some(value) = env.get(&var) {
+                let values = match MULTIPLE.contains(setting) {
+                    true => value.split_whitespace().collect(),
+                    false => vec![value.as_str()],
+                };
+                for value in values {
+                    config
+                        .set(setting, value)
+                        .map_err(|e| format!("{}: {}", var, e))?;
+                }
+            }
+        }
+
+        for (setting, value) in flags {
+            config
+                .set(setting, value)
+                .map_err(|e| format!("--{}: {}", setting, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Apply the settings of a configuration file, errors tell the line.
+    /// Nothing is applied if the file is invalid.
+    pub fn apply_file(&mut self, text: &str) -> Result<(), String> {
+        let file: FileConfig = toml::from_str(text).map_err(|e| e.to_string())?;
+        file.apply(self, text)
+    }
+
+    /// Apply `value` to the setting named after its command line flag.
+    pub fn set(&mut self, setting: &str, value: &str) -> Result<(), String> {
+        match setting {
+            "ip" => {
+                check_ip(value)?;
+                self.ip = value.to_owned();
+            }
+            "port" => self.port = parse(value)?,
+            "state-dir" => self.state_dir = value.to_owned(),
+            "shutdown-timeout" => self.shutdown_timeout = parse(value)?,
+            "metrics-listen" => self.metrics_listen = parse_listen(value)?,
+            "log-level" => self.log.level = value.parse()?,
+            "log-output" => self.log.output = value.parse()?,
+            "log-format" => self.log.format = value.parse()?,
+            "log-file" => self.log.file = value.to_owned(),
+            "log-file-size" => self.log.file_size = parse(value)?,
+            "log-file-count" => self.log.file_count = parse(value)?,
+            "disable-service" => {
+                self.disabled_services.insert(value.to_owned());
+            }
+            "refresh-interval" => {
+                let (service, secs) = split_pair(value, "SERVICE=SECONDS")?;
+                let secs = check_refresh_interval(parse(secs)?)?;
+                self.refresh_intervals.insert(service.to_owned(), secs);
+            }
+            "trim-interval" => self.disk.trim_interval = parse(value)?,
+            "idle-timeout" => {
+                let (disk, secs) = split_pair(value, "DISK=SECONDS")?;
+                self.disk
+                    .idle_timeouts
+                    .insert(disk.to_owned(), parse(secs)?);
+            }
+            "backup" => {
+                let backup = value.parse::<BackupConfig>()?;
+                self.disk.backups.retain(|b| b.name != backup.name);
+                self.disk.backups.push(backup);
+            }
+            "import-library" => self.disk.import_library = Some(value.to_owned()),
+            "import-unmount" => self.disk.import_unmount = parse(value)?,
+            "smb-conf" => self.disk.smb_conf = value.to_owned(),
+            "nfs-exports" => self.disk.nfs_exports = value.to_owned(),
+            "fill-warning-days" => self.disk.fill_warning_days = parse(value)?,
+            _ => return Err(format!("Unknown setting {}", setting)),
+        }
+        Ok(())
+    }
+
+    pub fn service_enabled(&self, service: &str) -> bool {
+        !self.disabled_services.contains(service)
+    }
+
+    /// Settings which differ in `new`, named after their command line flag.
+    pub fn changes(&self, new: &Config) -> Vec<&'static str> {
+        let (old_disk, new_disk) = (&self.disk, &new.disk);
+        let differs = [
+            ("ip", self.ip != new.ip),
+            ("port", self.port != new.port),
+            ("state-dir", self.state_dir != new.state_dir),
+            (
+                "shutdown-timeout",
+                self.shutdown_timeout != new.shutdown_timeout,
+            ),
+            ("metrics-listen", self.metrics_listen != new.metrics_listen),
+            ("log-level", self.log.level != new.log.level),
+            ("log-output", self.log.output != new.log.output),
+            ("log-format", self.log.format != new.log.format),
+            ("log-file", self.log.file != new.log.file),
+            ("log-file-size", self.log.file_size != new.log.file_size),
+            ("log-file-count", self.log.file_count != new.log.file_count),
+            (
+                "disable-service",
+                self.disabled_services != new.disabled_services,
+            ),
+            (
+                "refresh-interval",
+                self.refresh_intervals != new.refresh_intervals,
+            ),
+            (
+                "trim-interval",
+                old_disk.trim_interval != new_disk.trim_interval,
+            ),
+            (
+                "idle-timeout",
+                old_disk.idle_timeouts != new_disk.idle_timeouts,
+            ),
+            ("backup", old_disk.backups != new_disk.backups),
+            (
+                "import-library",
+                old_disk.import_library != new_disk.import_library,
+            ),
+            (
+                "import-unmount",
+                old_disk.import_unmount != new_disk.import_unmount,
+            ),
+            ("smb-conf", old_disk.smb_conf != new_disk.smb_conf),
+            ("nfs-exports", old_disk.nfs_exports != new_disk.nfs_exports),
+            (
+                "fill-warning-days",
+                old_disk.fill_warning_days != new_disk.fill_warning_days,
+            ),
+        ];
+        differs
+            .iter()
+            .filter(|(_, differs)| *differs)
+            .map(|(setting, _)| *setting)
+            .collect()
+    }
+}
+
+/// Where the configuration comes from, kept to load it again on reload
+/// with the same environment and flags.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSource {
+    /// Configuration file given with --config.
+    pub path: Option<String>,
+    pub env: Vec<(String, String)>,
+    /// (setting, value) of the command line flags.
+    pub flags: Vec<(String, String)>,
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config, String> {
+        let flags = self.flags.iter().map(|(s, v)| (s.as_str(), v.as_str()));
+        Config::load(self.path.as_deref(), self.env.clone(), flags)
+    }
+}
+
+/// PICONTROLX_STATE_DIR for state-dir.
+pub fn env_var(setting: &str) -> String {
+    format!("{}{}", ENV_PREFIX, setting.replace('-', "_").to_uppercase())
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {}", value))
+}
+
+fn split_pair<'a>(value: &'a str, format: &str) -> Result<(&'a str, &'a str), String> {
+    value
+        .split_once('=')
+        .ok_or_else(|| format!("Expect {}, got {}", format, value))
+}
+
+fn check_ip(ip: &str) -> Result<(), String> {
+    format!("{}:0", ip)
+        .parse::<SocketAddr>()
+        .map(|_| ())
+        .map_err(|_| {
+            format!(
+                "Invalid listen address {}, IPv6 addresses need brackets",
+                ip
+            )
+        })
+}
+
+/// A refresh interval of at least `MIN_REFRESH_INTERVAL` seconds.
+fn check_refresh_interval(secs: u64) -> Result<u64, String> {
+    match secs < MIN_REFRESH_INTERVAL {
+        true => Err(format!(
+            "Invalid refresh interval {}, expect at least {} seconds",
+            secs, MIN_REFRESH_INTERVAL
+        )),
+        false => Ok(secs),
+    }
+}
+
+/// `ADDR:PORT` to listen on, empty to not listen.
+fn parse_listen(value: &str) -> Result<Option<SocketAddr>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("Invalid listen address {}, expect ADDR:PORT", value))
+}
+
+/// The configuration file, e.g.
+///
+/// ```toml
+/// ip = "0.0.0.0"
+/// port = 50051
+/// state_dir = "/var/lib/picontrolx"
+/// shutdown_timeout = 10
+/// metrics_listen = "0.0.0.0:9184"
+///
+/// [log]
+/// level = "info,lib::caches::disk=debug"
+/// output = "file"
+/// format = "json"
+/// file = "/var/log/picontrolx/server.log"
+/// file_size = 10485760
+/// file_count = 5
+///
+/// [services.hello]
+/// enabled = false
+///
+/// [services.disk]
+/// refresh_interval = 60
+/// trim_interval = 604800
+/// idle_timeouts = { sdb = 600 }
+/// import_library = "/srv/photos"
+///
+/// [[services.disk.backups]]
+/// name = "home"
+/// source = "/home"
+/// target = "0b6c2f3e-..."
+/// interval = 86400
+/// keep = 7
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    #[serde(deserialize_with = "de_ip")]
+    ip: Option<String>,
+    port: Option<u16>,
+    state_dir: Option<String>,
+    shutdown_timeout: Option<u64>,
+    #[serde(deserialize_with = "de_listen")]
+    metrics_listen: Option<Option<SocketAddr>>,
+    log: LogSection,
+    services: ServicesSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    #[serde(deserialize_with = "de_parsed")]
+    level: Option<LogLevels>,
+    #[serde(deserialize_with = "de_parsed")]
+    output: Option<LogOutput>,
+    #[serde(deserialize_with = "de_parsed")]
+    format: Option<LogFormat>,
+    file: Option<String>,
+    file_size: Option<u64>,
+    file_count: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServicesSection {
+    hello: ServiceSection,
+    disk: DiskSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServiceSection {
+    enabled: Option<bool>,
+    #[serde(deserialize_with = "de_refresh_interval")]
+    refresh_interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DiskSection {
+    enabled: Option<bool>,
+    #[serde(deserialize_with = "de_refresh_interval")]
+    refresh_interval: Option<u64>,
+    trim_interval: Option<u64>,
+    idle_timeouts: HashMap<String, u64>,
+    backups: Vec<Spanned<BackupConfig>>,
+    import_library: Option<String>,
+    import_unmount: Option<bool>,
+    smb_conf: Option<String>,
+    nfs_exports: Option<String>,
+    fill_warning_days: Option<f64>,
+}
+
+fn de_ip<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let ip = String::deserialize(deserializer)?;
+    check_ip(&ip).map_err(de::Error::custom)?;
+    Ok(Some(ip))
+}
+
+fn de_listen<'de, D>(deserializer: D) -> Result<Option<Option<SocketAddr>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse_listen(&value).map(Some).map_err(de::Error::custom)
+}
+
+fn de_refresh_interval<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    let secs = u64::deserialize(deserializer)?;
+    check_refresh_interval(secs)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// A string parsed with `FromStr`, so the file and the flags accept the
+/// same values.
+fn de_parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn apply_service(service: &str, enabled: Option<bool>, interval: Option<u64>, config: &mut Config) {
+    match enabled {
+        Some(true) => {
+            config.disabled_services.remove(service);
+        }
+        Some(false) => {
+            config.disabled_services.insert(service.to_owned());
+        }
+        None => {}
+    }
+    if let Some(secs) = interval {
+        config.refresh_intervals.insert(service.to_owned(), secs);
+    }
+}
+
+/// Line of `text` at byte `offset`, counted from 1.
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+impl FileConfig {
+    /// Apply to `config` once valid, `text` is the file, to locate errors.
+    fn apply(mut self, config: &mut Config, text: &str) -> Result<(), String> {
+        // Tables of an array share the location of the array while
+        // deserializing, so backups are validated here.
+        let backups = std::mem::take(&mut self.services.disk.backups)
+            .into_iter()
+            .map(|backup| {
+                let line = line_at(text, backup.span().start);
+                backup
+                    .into_inner()
+                    .complete()
+                    .map_err(|e| format!("Invalid backup at line {}: {}", line, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        set(&mut config.ip, self.ip);
+        set(&mut config.port, self.port);
+        set(&mut config.state_dir, self.state_dir);
+        set(&mut config.shutdown_timeout, self.shutdown_timeout);
+        set(&mut config.metrics_listen, self.metrics_listen);
+        let log = self.log;
+        let l = &mut config.log;
+        set(&mut l.level, log.level);
+        set(&mut l.output, log.output);
+        set(&mut l.format, log.format);
+        set(&mut l.file, log.file);
+        set(&mut l.file_size, log.file_size);
+        set(&mut l.file_count, log.file_count);
+
+        let hello = self.services.hello;
+        apply_service("hello", hello.enabled, hello.refresh_interval, config);
+        let disk = self.services.disk;
+        apply_service("disk", disk.enabled, disk.refresh_interval, config);
+        let d = &mut config.disk;
+        set(&mut d.trim_interval, disk.trim_interval);
+        d.idle_timeouts.extend(disk.idle_timeouts);
+        for backup in backups {
+            d.backups.retain(|b| b.name != backup.name);
+            d.backups.push(backup);
+        }
+        if disk.import_library.is_some() {
+            d.import_library = disk.import_library;
+        }
+        set(&mut d.import_unmount, disk.import_unmount);
+        set(&mut d.smb_conf, disk.smb_conf);
+        set(&mut d.nfs_exports, disk.nfs_exports);
+        set(&mut d.fill_warning_days, disk.fill_warning_days);
+        Ok(())
+    }
+}
+
+/// Where and how the server logs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    pub level: LogLevels,
+    pub output: LogOutput,
+    pub format: LogFormat,
+    /// Log file of the file output.
+    pub file: String,
+    /// Bytes a log file grows to before it is rotated.
+    pub file_size: u64,
+    /// Rotated log files kept, as FILE.1 (newest) to FILE.N.
+    pub file_count: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LogLevels::new(LevelFilter::Info),
+            output: LogOutput::Terminal,
+            format: LogFormat::Text,
+            file: String::from("/var/log/picontrolx/server.log"),
+            file_size: 10 * 1024 * 1024,
+            file_count: 5,
+        }
+    }
+}
+
+/// A default log level and levels of modules, e.g.
+/// `info,lib::caches::disk=debug,h2=warn`. A module level applies to its
+/// submodules too.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLevels {
+    pub default: LevelFilter,
+    /// (module, level), longest module first so the first match is the
+    /// most specific one.
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogLevels {
+    pub fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+
+    /// Level of log records of `target`, a module path.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target.starts_with(module.as_str())
+                    && (target.len() == module.len() || target[module.len()..].starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The most verbose level of any module.
+    pub fn max(&self) -> LevelFilter {
+        let modules = self.modules.iter().map(|(_, level)| *level);
+        modules.fold(self.default, Ord::max)
+    }
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = |l: &str| {
+            l.trim()
+                .parse::<LevelFilter>()
+                .map_err(|_| format!("Invalid log level {}", l))
+        };
+        let mut levels = LogLevels::new(LevelFilter::Info);
+        for entry in s.split(',').filter(|e| !e.trim().is_empty()) {
+            match entry.split_once('=') {
+                Some((module, l)) => {
+                    let module = module.trim().to_owned();
+                    levels.modules.retain(|(m, _)| *m != module);
+                    levels.modules.push((module, level(l)?));
+                }
+                None => levels.default = level(entry)?,
+            }
+        }
+        levels
+            .modules
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Ok(levels)
+    }
+}
+
+impl fmt::Display for LogLevels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogOutput {
+    /// Standard error.
+    Terminal,
+    /// `LogConfig::file`, rotated by size.
+    File,
+    /// The native protocol of the systemd journal, with structured fields.
+    Journald,
+}
+
+impl FromStr for LogOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(LogOutput::Terminal),
+            "file" => Ok(LogOutput::File),
+            "journald" => Ok(LogOutput::Journald),
+            _ => Err(format!(
+                "Invalid log output {}, expect terminal, file or journald",
+                s
+            )),
+        }
+    }
+}
+
+/// Format of the terminal and file outputs, journald records are always
+/// structured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// A JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format {}, expect text or json", s)),
+        }
+    }
+}
+
+/// Settings of the disk service.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiskConfig {
+    /// Seconds between two scheduled TRIM passes, 0 disables scheduled TRIM.
+    pub trim_interval: u64,
+    /// Seconds of inactivity before a spinning disk is put into standby,
+    /// keyed by disk serial number or kernel name.
+    pub idle_timeouts: HashMap<String, u64>,
+    /// Backups defined on the command line, more can be added at runtime.
+    pub backups: Vec<BackupConfig>,
+    /// Photos and videos of camera cards are imported into this directory
+    /// when a card is inserted, None disables importing.
+    pub import_library: Option<String>,
+    /// Unmount a camera card once everything on it is imported.
+    pub import_unmount: bool,
+    /// Samba configuration generated for shared partitions, included from smb.conf.
+    pub smb_conf: String,
+    /// NFS exports generated for shared partitions.
+    pub nfs_exports: String,
+    /// Warn when a partition is forecast to be full within this many days,
+    /// 0 disables the warning.
+    pub fill_warning_days: f64,
+}
+
+/// A directory of this machine backed up to a managed disk as hard-linked
+/// snapshots. The disk is matched by uuid, so the backup runs whenever it
+/// is plugged in.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub name: String,
+    /// Absolute path of the directory to back up.
+    pub source: String,
+    /// Uuid of the destination partition.
+    pub target: String,
+    /// Directory below the mount point of the destination holding the snapshots.
+    pub target_dir: String,
+    /// Seconds between two runs, 0 to only run on request.
+    pub interval: u64,
+    /// Number of snapshots kept, 0 keeps all of them.
+    pub keep: u32,
+}
+
+impl FromStr for BackupConfig {
+    type Err = String;
+
+    /// Parse `name=NAME,source=PATH,target=UUID[,dir=DIR][,interval=SECONDS][,keep=N]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut backup = BackupConfig::default();
+        for field in s.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("Expect KEY=VALUE, got {}", field))?;
+            let number = |v: &str| v.parse().map_err(|_| format!("Invalid {}: {}", key, v));
+            match key {
+                "name" => backup.name = value.to_owned(),
+                "source" => backup.source = value.to_owned(),
+                "target" => backup.target = value.to_owned(),
+                "dir" => backup.target_dir = value.to_owned(),
+                "interval" => backup.interval = number(value)?,
+                "keep" => {
+                    backup.keep = u32::try_from(number(value)?)
+                        .map_err(|_| format!("Invalid {}: {}", key, value))?
+                }
+                _ => return Err(format!("Unknown backup field {}", key)),
+            }
+        }
+        backup.complete()
+    }
+}
+
+impl BackupConfig {
+    /// Fill in the default target directory and validate.
+    pub(crate) fn complete(mut self) -> Result<Self, String> {
+        if self.target_dir.is_empty() {
+            self.target_dir = format!("picontrolx-backups/{}", self.name);
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(format!("Invalid backup name {:?}", self.name));
+        }
+        if !self.source.starts_with('/') {
+            return Err(format!("Backup source {:?} is not absolute", self.source));
+        }
+        if self.target.is_empty() {
+            return Err(String::from("Backup target is empty"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "./config_test.rs"]
+mod config_test;
@@ -0,0 +1,174 @@
+use super::*;
+use crate::test_util::TempDir;
+use std::path::PathBuf;
+
+fn temp_file(dir: &TempDir, name: &str, text: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_precedence() {
+    let dir = TempDir::new("config");
+    let path = temp_file(
+        &dir,
+        "precedence.toml",
+        r#"
+port = 1000
+state_dir = "/srv/state"
+shutdown_timeout = 30
+
+[log]
+level = "info"
+output = "file"
+
+[services.hello]
+enabled = false
+
+[services.disk]
+idle_timeouts = { sda = 60, sdb = 120 }
+"#,
+    );
+    let path = path.to_str().unwrap();
+    let env = env(&[
+        ("PICONTROLX_PORT", "2000"),
+        ("PICONTROLX_SHUTDOWN_TIMEOUT", "20"),
+        ("PICONTROLX_IDLE_TIMEOUT", "sdb=180 sdc=240"),
+        ("HOME", "/root"),
+    ]);
+    let flags = vec![("port", "3000"), ("idle-timeout", "sdc=300")];
+    let config = Config::load(Some(path), env, flags).unwrap();
+
+    assert_eq!(config.config, path);
+    assert_eq!(config.port, 3000);
+    assert_eq!(config.shutdown_timeout, 20);
+    assert_eq!(config.state_dir, "/srv/state");
+    assert_eq!(config.ip, "[::1]");
+    assert_eq!(config.log.level, LogLevels::new(LevelFilter::Info));
+    assert_eq!(config.log.output, LogOutput::File);
+    assert!(!config.service_enabled("hello"));
+    assert!(config.service_enabled("disk"));
+    let timeouts = &config.disk.idle_timeouts;
+    assert_eq!(timeouts.len(), 3);
+    assert_eq!(timeouts["sda"], 60);
+    assert_eq!(timeouts["sdb"], 180);
+    assert_eq!(timeouts["sdc"], 300);
+    assert_eq!(config.disk.fill_warning_days, 7.0);
+}
+
+#[test]
+fn test_config_file_from_env() {
+    let dir = TempDir::new("config");
+    let path = temp_file(&dir, "env.toml", "port = 1000\n");
+    let env = env(&[("PICONTROLX_CONFIG", path.to_str().unwrap())]);
+    let config = Config::load(None, env, vec![]).unwrap();
+    assert_eq!(config.port, 1000);
+
+    let missing = Some("/nonexistent/picontrolx.toml");
+    assert!(Config::load(missing, vec![], vec![]).is_err());
+}
+
+#[test]
+fn test_errors_tell_line() {
+    let mut config = Config::default();
+    let e = config
+        .apply_file("port = 1000\n\n[log]\nlevel = \"loud\"\n")
+        .unwrap_err();
+    assert!(e.contains("line 4"), "{}", e);
+    assert!(e.contains("Invalid log level loud"), "{}", e);
+
+    let e = config
+        .apply_file("[log]\noutput = \"syslog\"\n")
+        .unwrap_err();
+    assert!(e.contains("line 2"), "{}", e);
+
+    let e = config.apply_file("port = 1000\nprot = 1\n").unwrap_err();
+    assert!(e.contains("line 2"), "{}", e);
+    assert!(e.contains("unknown field `prot`"), "{}", e);
+
+    let e = config.apply_file("ip = \"::1\"\n").unwrap_err();
+    assert!(e.contains("line 1"), "{}", e);
+
+    let e = config
+        .apply_file("[services.hello]\nenabled = true\nrefresh_interval = 0\n")
+        .unwrap_err();
+    assert!(e.contains("line 3"), "{}", e);
+    assert!(e.contains("Invalid refresh interval 0"), "{}", e);
+
+    let text = r#"
+[[services.disk.backups]]
+name = "home"
+source = "/home"
+target = "uuid"
+
+[[services.disk.backups]]
+name = "etc"
+source = "etc"
+target = "uuid"
+"#;
+    let e = config.apply_file(text).unwrap_err();
+    assert!(e.contains("line 7"), "{}", e);
+    assert!(e.contains("is not absolute"), "{}", e);
+}
+
+#[test]
+fn test_set() {
+    let mut config = Config::default();
+    config
+        .set("backup", "name=home,source=/home,target=a")
+        .unwrap();
+    config
+        .set("backup", "name=home,source=/root,target=b")
+        .unwrap();
+    assert_eq!(config.disk.backups.len(), 1);
+    assert_eq!(config.disk.backups[0].source, "/root");
+    assert_eq!(config.disk.backups[0].target_dir, "picontrolx-backups/home");
+
+    assert!(config
+        .set("backup", "name=home,source=/home,target=a,keep=4294967296")
+        .is_err());
+    config.set("import-unmount", "true").unwrap();
+    assert!(config.disk.import_unmount);
+    assert!(config.set("port", "70000").is_err());
+    assert!(config.set("refresh-interval", "disk").is_err());
+    assert!(config.set("refresh-interval", "disk=0").is_err());
+    assert!(config.set("refresh-interval", "disk=1").is_err());
+    config.set("refresh-interval", "disk=5").unwrap();
+    assert_eq!(config.refresh_intervals["disk"], 5);
+    assert!(config.set("colour", "blue").is_err());
+
+    config.set("metrics-listen", "0.0.0.0:9184").unwrap();
+    assert_eq!(config.metrics_listen, Some("0.0.0.0:9184".parse().unwrap()));
+    config.set("metrics-listen", "").unwrap();
+    assert_eq!(config.metrics_listen, None);
+    assert!(config.set("metrics-listen", "9184").is_err());
+    assert_eq!(env_var("fill-warning-days"), "PICONTROLX_FILL_WARNING_DAYS");
+}
+
+#[test]
+fn test_log_levels() {
+    let levels = "warn, lib::caches=debug,lib::caches::disk=trace,h2=off"
+        .parse::<LogLevels>()
+        .unwrap();
+    assert_eq!(levels.level("lib"), LevelFilter::Warn);
+    assert_eq!(levels.level("lib::caches"), LevelFilter::Debug);
+    assert_eq!(levels.level("lib::caches::hello"), LevelFilter::Debug);
+    assert_eq!(levels.level("lib::caches::disk::scan"), LevelFilter::Trace);
+    assert_eq!(levels.level("lib::cachesx"), LevelFilter::Warn);
+    assert_eq!(levels.level("h2::codec"), LevelFilter::Off);
+    assert_eq!(levels.max(), LevelFilter::Trace);
+    assert_eq!(
+        levels.to_string(),
+        "warn,lib::caches::disk=trace,lib::caches=debug,h2=off"
+    );
+    assert_eq!(levels.to_string().parse::<LogLevels>(), Ok(levels));
+
+    assert!("lib=loud".parse::<LogLevels>().is_err());
+}
@@ -1,52 +1,167 @@
 use log;
-use simplelog::{ColorChoice, LevelFilter, TermLogger, TerminalMode};
+use std::time::Duration;
 use tokio::signal;
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
 
 pub mod config;
 
 mod caches;
+mod logging;
+mod metrics;
 mod public;
+mod registry;
+mod reload;
 mod server;
-use crate::config::Config;
+#[cfg(test)]
+mod test_util;
+use crate::caches::{DiskCache, HelloCache};
+use crate::config::{Config, ConfigSource, LogConfig};
+use crate::logging::LogHandle;
 use crate::public::event_queue::EventQ;
+use crate::registry::{Parts, Registry};
+use crate::reload::Reloader;
 use server::server::Server;
+use server::{converter, exporter};
 
-fn setup_logger() {
-    TermLogger::init(
-        LevelFilter::Debug,
-        // simplelog::Config::default(),
-        simplelog::ConfigBuilder::new()
-            .set_location_level(LevelFilter::Debug)
-            .set_target_level(LevelFilter::Debug)
-            .build(),
-        TerminalMode::Mixed,
-        ColorChoice::Auto,
-    )
-    .unwrap();
+/// Install the configured logger, or a terminal one if that fails.
+fn setup_logger(config: &LogConfig) -> LogHandle {
+    match logging::init(config) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Cannot set up logging: {}", e);
+            LogHandle::new(config.level.clone())
+        }
+    }
 }
 
-pub async fn lib_main(config: Config) {
-    setup_logger();
+/// Every service of the server, with its cache, its handler, how clients
+/// watch it and its metrics. Disabled services are registered too, the cache manager does
+/// not run them until enabled by a reload.
+fn register_services(registry: &mut Registry, event_q: &EventQ, config: &Config) {
+    let (cache, handler) = HelloCache::new(event_q.get_notifier());
+    registry
+        .register(cache, handler)
+        .watch(converter::preserved_to_disk_list_and_watch_response);
+
+    let (cache, handler) = DiskCache::new(event_q.get_notifier(), config);
+    registry
+        .register(cache, handler)
+        .watch(converter::data_to_disk_list_and_watch_response)
+        .export(exporter::disk_metrics);
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM (systemd stop).
+async fn wait_for_signal() {
+    let mut terminate = match unix_signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(e) => {
+            log::error!("Cannot handle SIGTERM: {}", e);
+            None
+        }
+    };
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(terminate) => {
+                terminate.recv().await;
+            }
+            None => futures::future::pending::<()>().await,
+        }
+    };
+    tokio::select! {
+        result = signal::ctrl_c() => {
+            if let Err(e) = result {
+                log::error!("Cannot handle SIGINT: {}", e);
+                futures::future::pending::<()>().await;
+            }
+            log::warn!("SIGINT received");
+        }
+        _ = terminated => log::warn!("SIGTERM received"),
+    }
+}
+
+/// Reload the configuration on every SIGHUP.
+async fn reload_on_hangup(reloader: Reloader) {
+    let mut hangup = match unix_signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Cannot handle SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        log::warn!("SIGHUP received, reloading configuration");
+        // Outcome is logged by the reloader.
+        let _ = reloader.reload().await;
+    }
+}
+
+pub async fn lib_main(config: Config, source: ConfigSource) {
+    let log_handle = setup_logger(&config.log);
 
     log::warn!("async main start...");
 
     let event_q = EventQ::new();
 
-    let mut cache_manager = caches::CacheManager::new();
-    let cache_handlers = cache_manager.create_caches(event_q.get_notifier());
+    let mut registry = Registry::default();
+    register_services(&mut registry, &event_q, &config);
+    let Parts {
+        caches,
+        handlers,
+        converters,
+        exporters,
+    } = registry.into_parts();
+    let cache_manager = caches::CacheManager::new(caches, &config.refresh_intervals);
+    let health = cache_manager.health();
+    let refreshers = cache_manager.refreshers();
+    let refresh_durations = cache_manager.refresh_durations();
+    let cache_manager_handler = cache_manager.run(&config.disabled_services);
 
     let addr = format!("{}:{}", config.ip, config.port);
     let addr = addr.parse().unwrap();
+    let deadline = Duration::from_secs(config.shutdown_timeout);
+    let metrics_addr = config.metrics_listen;
+    let reloader = Reloader::new(
+        config,
+        source,
+        cache_manager_handler.clone(),
+        log_handle.clone(),
+    );
+    tokio::spawn(reload_on_hangup(reloader.clone()));
+
     let (mut server, server_handler) = Server::new(addr);
-    server.add_caches(cache_handlers).await;
+    server.add_services(handlers, converters, exporters);
+    server.add_health(health);
+    server.add_refreshers(refreshers);
+    server.add_reloader(reloader);
+    server.add_log_handle(log_handle);
+    if let Some(addr) = metrics_addr {
+        server.add_metrics(addr, refresh_durations);
+    }
 
     let handler1 = tokio::spawn(async move {
         server.serve(event_q).await;
     });
 
-    let cache_manager_handler = cache_manager.run();
+    wait_for_signal().await;
 
-    signal::ctrl_c().await.unwrap();
-    tokio::join!(cache_manager_handler.shutdown(), server_handler.shutdown());
-    handler1.await.unwrap();
+    // Stop clients first, so nothing reaches the caches while they stop,
+    // then let the caches save their state.
+    let shutdown = async {
+        server_handler.shutdown().await;
+        cache_manager_handler.shutdown().await;
+        if let Err(e) = handler1.await {
+            log::error!("Server task failed: {}", e);
+        }
+    };
+    tokio::select! {
+        _ = shutdown => log::warn!("Shut down"),
+        _ = tokio::time::sleep(deadline) => {
+            log::error!("Shutdown takes longer than {:?}, exiting anyway", deadline);
+            std::process::exit(1);
+        }
+        _ = wait_for_signal() => {
+            log::error!("Second signal during shutdown, exiting anyway");
+            std::process::exit(1);
+        }
+    }
 }
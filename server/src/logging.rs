@@ -0,0 +1,323 @@
+use log::{Level, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{LogConfig, LogFormat, LogLevels, LogOutput};
+use crate::public::time::utc_datetime;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_IDENTIFIER: &str = "picontrolx";
+/// How long levels set through the API last, unless told.
+pub(crate) const TEMPORARY_LEVELS_DURATION: Duration = Duration::from_secs(600);
+
+struct Levels {
+    configured: LogLevels,
+    /// Set through the API, used instead of the configured levels until
+    /// it expires.
+    temporary: Option<LogLevels>,
+    /// Bumped on every temporary change, so an expiry only undoes its own.
+    generation: u64,
+}
+
+impl Levels {
+    fn effective(&self) -> &LogLevels {
+        self.temporary.as_ref().unwrap_or(&self.configured)
+    }
+}
+
+/// Changes the levels of the installed logger.
+#[derive(Clone)]
+pub(crate) struct LogHandle {
+    levels: Arc<RwLock<Levels>>,
+}
+
+impl LogHandle {
+    pub(crate) fn new(levels: LogLevels) -> Self {
+        Self {
+            levels: Arc::new(RwLock::new(Levels {
+                configured: levels,
+                temporary: None,
+                generation: 0,
+            })),
+        }
+    }
+
+    /// Levels in effect.
+    pub(crate) fn levels(&self) -> LogLevels {
+        self.levels.read().unwrap().effective().clone()
+    }
+
+    fn level(&self, target: &str) -> log::LevelFilter {
+        self.levels.read().unwrap().effective().level(target)
+    }
+
+    /// Levels of the configuration, on reload.
+    pub(crate) fn set_levels(&self, levels: LogLevels) {
+        self.levels.write().unwrap().configured = levels;
+        self.update_max();
+    }
+
+    /// Use `levels` for `duration`, then the configured levels again. None
+    /// restores the configured levels now.
+    pub(crate) fn set_temporary(&self, levels: Option<LogLevels>, duration: Duration) {
+        let mut inner = self.levels.write().unwrap();
+        inner.generation += 1;
+        let generation = inner.generation;
+        let expires = levels.is_some();
+        inner.temporary = levels;
+        drop(inner);
+        self.update_max();
+
+        if expires {
+            let handle = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                let mut inner = handle.levels.write().unwrap();
+                if inner.generation == generation {
+                    inner.temporary = None;
+                    drop(inner);
+                    handle.update_max();
+                    log::warn!("Temporary log levels expired");
+                }
+            });
+        }
+    }
+
+    /// Records above the max level are dropped before reaching the logger.
+    fn update_max(&self) {
+        log::set_max_level(self.levels.read().unwrap().effective().max());
+    }
+}
+
+/// A log file which is rotated once it grows over `max_size`, FILE.1 is
+/// the newest rotated file and FILE.`keep` the oldest.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, keep: u32) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), n))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.keep).rev() {
+            // Missing ones were not rotated yet.
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+enum Output {
+    Terminal,
+    File(RotatingFile),
+    Journald(UnixDatagram),
+}
+
+impl Output {
+    fn open(config: &LogConfig) -> io::Result<Self> {
+        Ok(match config.output {
+            LogOutput::Terminal => Output::Terminal,
+            LogOutput::File => Output::File(RotatingFile::open(
+                &config.file,
+                config.file_size,
+                config.file_count,
+            )?),
+            LogOutput::Journald => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(JOURNALD_SOCKET)?;
+                Output::Journald(socket)
+            }
+        })
+    }
+}
+
+/// `time` in RFC 3339, UTC with milliseconds.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (year, month, day, hour, minute, second) = utc_datetime(since_epoch.as_secs());
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn format_record(format: LogFormat, time: SystemTime, record: &Record) -> String {
+    match format {
+        LogFormat::Text => format!(
+            "{} {:<5} [{}] {}\n",
+            timestamp(time),
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+        LogFormat::Json => {
+            let mut line = serde_json::json!({
+                "time": timestamp(time),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            if let (Some(file), Some(line_no)) = (record.file(), record.line()) {
+                line["file"] = file.into();
+                line["line"] = line_no.into();
+            }
+            format!("{}\n", line)
+        }
+    }
+}
+
+/// Syslog priority of `level`.
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// `record` in the native journal protocol: a FIELD=value line per field,
+/// values holding a newline are length prefixed.
+fn journald_record(record: &Record) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        buf.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    };
+    field("PRIORITY", &priority(record.level()).to_string());
+    field("MESSAGE", &record.args().to_string());
+    field("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    field("TARGET", record.target());
+    if let Some(file) = record.file() {
+        field("CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field("CODE_LINE", &line.to_string());
+    }
+    buf
+}
+
+struct Logger {
+    levels: LogHandle,
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let time = SystemTime::now();
+        let mut output = self.output.lock().unwrap();
+        let result = match &mut *output {
+            Output::Terminal => {
+                io::stderr().write_all(format_record(self.format, time, record).as_bytes())
+            }
+            Output::File(file) => file.write(format_record(self.format, time, record).as_bytes()),
+            Output::Journald(socket) => socket.send(&journald_record(record)).map(|_| ()),
+        };
+        // Logging the failure would fail again, fall back to the terminal.
+        if let Err(e) = result {
+            let line = format_record(LogFormat::Text, time, record);
+            eprint!("Cannot log ({}): {}", e, line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Output::File(file) = &mut *self.output.lock().unwrap() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Install the logger described by `config`. If its output cannot be
+/// opened, it logs to the terminal instead.
+pub(crate) fn init(config: &LogConfig) -> Result<LogHandle, String> {
+    let (output, error) = match Output::open(config) {
+        Ok(output) => (output, None),
+        Err(e) => (Output::Terminal, Some(e)),
+    };
+    let handle = LogHandle::new(config.level.clone());
+    let logger = Logger {
+        levels: handle.clone(),
+        format: config.format,
+        output: Mutex::new(output),
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| e.to_string())?;
+    handle.update_max();
+
+    if let Some(e) = error {
+        log::error!(
+            "Cannot log to {:?}, logging to the terminal: {}",
+            config.output,
+            e
+        );
+    }
+    Ok(handle)
+}
+
+#[cfg(test)]
+#[path = "./logging_test.rs"]
+mod logging_test;
@@ -0,0 +1,96 @@
+use super::*;
+use crate::test_util::TempDir;
+use log::LevelFilter;
+
+#[test]
+fn test_timestamp() {
+    assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    let time = UNIX_EPOCH + Duration::from_millis(951_782_400_250);
+    assert_eq!(timestamp(time), "2000-02-29T00:00:00.250Z");
+    let time = UNIX_EPOCH + Duration::from_secs(1_790_000_000);
+    assert_eq!(timestamp(time), "2026-09-21T14:13:20.000Z");
+}
+
+#[test]
+fn test_formats() {
+    let time = UNIX_EPOCH;
+    let text = format_record(
+        LogFormat::Text,
+        time,
+        &Record::builder()
+            .args(format_args!("disk {} is full", "sda"))
+            .level(Level::Warn)
+            .target("lib::caches::disk")
+            .build(),
+    );
+    assert_eq!(
+        text,
+        "1970-01-01T00:00:00.000Z WARN  [lib::caches::disk] disk sda is full\n"
+    );
+
+    let json = format_record(
+        LogFormat::Json,
+        time,
+        &Record::builder()
+            .args(format_args!("say \"hi\""))
+            .level(Level::Info)
+            .target("lib")
+            .file(Some("src/lib.rs"))
+            .line(Some(7))
+            .build(),
+    );
+    assert!(json.ends_with('\n'));
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["level"], "INFO");
+    assert_eq!(json["message"], "say \"hi\"");
+    assert_eq!(json["line"], 7);
+}
+
+#[test]
+fn test_journald_record() {
+    let record = journald_record(
+        &Record::builder()
+            .args(format_args!("two\nlines"))
+            .level(Level::Error)
+            .target("lib")
+            .build(),
+    );
+    let mut expected = b"PRIORITY=3\nMESSAGE\n".to_vec();
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(b"two\nlines\nSYSLOG_IDENTIFIER=picontrolx\nTARGET=lib\n");
+    assert_eq!(record, expected);
+}
+
+#[test]
+fn test_rotating_file() {
+    let dir = TempDir::new("log");
+    let path = dir.join("server.log");
+    let mut file = RotatingFile::open(path.to_str().unwrap(), 10, 2).unwrap();
+    for line in &["1111111\n", "2222222\n", "3333333\n", "4444444\n"] {
+        file.write(line.as_bytes()).unwrap();
+    }
+    let read = |suffix: &str| fs::read_to_string(format!("{}{}", path.display(), suffix)).unwrap();
+    assert_eq!(read(""), "4444444\n");
+    assert_eq!(read(".1"), "3333333\n");
+    assert_eq!(read(".2"), "2222222\n");
+    assert!(!dir.join("server.log.3").exists());
+}
+
+#[tokio::test]
+async fn test_temporary_levels() {
+    let handle = LogHandle::new(LogLevels::new(LevelFilter::Info));
+    let debug = LogLevels::new(LevelFilter::Debug);
+    handle.set_temporary(Some(debug.clone()), Duration::from_millis(20));
+    assert_eq!(handle.levels(), debug);
+
+    // A reload changes the configured levels, not the temporary ones.
+    handle.set_levels(LogLevels::new(LevelFilter::Warn));
+    assert_eq!(handle.level("lib"), LevelFilter::Debug);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(handle.levels(), LogLevels::new(LevelFilter::Warn));
+
+    handle.set_temporary(Some(debug), Duration::from_secs(60));
+    handle.set_temporary(None, Duration::from_secs(0));
+    assert_eq!(handle.levels(), LogLevels::new(LevelFilter::Warn));
+}
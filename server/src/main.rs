@@ -1,30 +1,42 @@
+use std::env;
+use std::process;
 use tokio::runtime::Runtime;
 
 // extern crate lib;
 use clap::{App, Arg};
-use lib::config::Config;
+use lib::config::{Config, ConfigSource, DEFAULT_CONFIG_FILE, SETTINGS};
 
-async fn tokio_main(config: Config) {
-    lib::lib_main(config).await;
+async fn tokio_main(config: Config, source: ConfigSource) {
+    lib::lib_main(config, source).await;
 }
 
-fn setup_opts() -> Config {
+fn setup_opts() -> (Config, ConfigSource) {
     let matches = App::new("PiControlX server")
         .version("1.0")
         .author("Douglas Su")
+        .after_help(
+            "Settings are read from the configuration file, then from PICONTROLX_* \
+             environment variables named after the flags (e.g. PICONTROLX_STATE_DIR), \
+             then from the flags, each overriding the previous ones.",
+        )
         .arg(
             Arg::with_name("config")
                 .short("c")
                 .long("config")
                 .value_name("FILE")
-                .default_value("/etc/xxx")
+                .help(&format!("TOML configuration file [default: {}]", DEFAULT_CONFIG_FILE))
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("check-config")
+                .long("check-config")
+                .help("Validate the configuration and exit"),
+        )
         .arg(
             Arg::with_name("ip")
                 .long("ip")
                 .value_name("Address")
-                .default_value("[::1]")
+                .help("Address to listen on [default: [::1]]")
                 .takes_value(true),
         )
         .arg(
@@ -32,24 +44,196 @@ fn setup_opts() -> Config {
                 .short("p")
                 .long("port")
                 .value_name("PORT")
-                .default_value("50051")
+                .help("Port to listen on [default: 50051]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-dir")
+                .long("state-dir")
+                .value_name("DIR")
+                .help("Directory of the saved state [default: /var/lib/picontrolx]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-listen")
+                .long("metrics-listen")
+                .value_name("ADDR:PORT")
+                .help(
+                    "Serve Prometheus metrics over HTTP at ADDR:PORT/metrics, empty to not \
+                     serve them [default: not served]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVELS")
+                .help(
+                    "off, error, warn, info, debug or trace, optionally per module as in \
+                     info,lib::caches::disk=debug [default: info]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-output")
+                .long("log-output")
+                .value_name("OUTPUT")
+                .help("terminal, file or journald [default: terminal]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .help("text or json, one object per line [default: text]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .help("Log file of the file output [default: /var/log/picontrolx/server.log]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file-size")
+                .long("log-file-size")
+                .value_name("BYTES")
+                .help("Rotate the log file once it grows over BYTES [default: 10485760]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file-count")
+                .long("log-file-count")
+                .value_name("N")
+                .help("Rotated log files kept [default: 5]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("disable-service")
+                .long("disable-service")
+                .value_name("SERVICE")
+                .help("Do not start SERVICE (e.g. hello)")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("refresh-interval")
+                .long("refresh-interval")
+                .value_name("SERVICE=SECONDS")
+                .help("Refresh the data of SERVICE (e.g. disk) every SECONDS, at least 5")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_name("SECONDS")
+                .help("Exit anyway if shutting down takes longer than SECONDS [default: 10]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trim-interval")
+                .long("trim-interval")
+                .value_name("SECONDS")
+                .help("Seconds between scheduled TRIM of mounted SSDs, 0 to disable [default: 0]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("idle-timeout")
+                .long("idle-timeout")
+                .value_name("DISK=SECONDS")
+                .help("Spin down DISK (serial number or kernel name) after SECONDS of inactivity")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup")
+                .long("backup")
+                .value_name("SPEC")
+                .help(
+                    "Back up a directory to a disk, SPEC is \
+                     name=NAME,source=PATH,target=UUID[,dir=DIR][,interval=SECONDS][,keep=N]",
+                )
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import-library")
+                .long("import-library")
+                .value_name("DIR")
+                .help("Import photos and videos of inserted camera cards into DIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import-unmount")
+                .long("import-unmount")
+                .help("Unmount camera cards once imported"),
+        )
+        .arg(
+            Arg::with_name("smb-conf")
+                .long("smb-conf")
+                .value_name("FILE")
+                .help(
+                    "Samba configuration generated for shared disks, to be included from smb.conf \
+                     [default: /etc/samba/picontrolx.conf]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nfs-exports")
+                .long("nfs-exports")
+                .value_name("FILE")
+                .help(
+                    "NFS exports generated for shared disks \
+                     [default: /etc/exports.d/picontrolx.exports]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fill-warning-days")
+                .long("fill-warning-days")
+                .value_name("DAYS")
+                .help("Warn when a disk is forecast to be full within DAYS, 0 to disable [default: 7]")
                 .takes_value(true),
         )
         .get_matches();
 
-    let config = matches.value_of("config").unwrap();
-    let ip = matches.value_of("ip").unwrap();
-    let port = matches.value_of("port").unwrap();
+    let mut flags = Vec::new();
+    for setting in SETTINGS {
+        if let Some(values) = matches.values_of(setting) {
+            flags.extend(values.map(|value| (setting.to_string(), value.to_owned())));
+        }
+    }
+    if matches.is_present("import-unmount") {
+        flags.push((String::from("import-unmount"), String::from("true")));
+    }
 
-    Config {
-        config: config.into(),
-        ip: ip.into(),
-        port: port.parse().unwrap(),
+    let source = ConfigSource {
+        path: matches.value_of("config").map(String::from),
+        env: env::vars().collect(),
+        flags,
+    };
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
+        }
+    };
+    if matches.is_present("check-config") {
+        println!("Configuration {} is valid", config.config);
+        process::exit(0);
     }
+    (config, source)
 }
 
 fn main() {
-    let config = setup_opts();
+    let (config, source) = setup_opts();
     let rt = Runtime::new().unwrap();
-    rt.block_on(tokio_main(config));
+    rt.block_on(tokio_main(config, source));
 }
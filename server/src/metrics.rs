@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Content type of the Prometheus text exposition format.
+pub(crate) const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Every metric name starts with it.
+const PREFIX: &str = "picontrolx_";
+/// Upper bounds of the duration buckets, in seconds. Disk rescans take
+/// seconds, RPCs milliseconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Counts {
+    /// Observations per bucket of `DURATION_BUCKETS`, the ones above the
+    /// last bound are only in `count`.
+    buckets: Vec<u64>,
+    /// In seconds.
+    sum: f64,
+    count: u64,
+}
+
+/// Distribution of durations, shared by whoever observes them and the
+/// metrics endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct Histogram {
+    inner: Arc<Mutex<Counts>>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Counts {
+                buckets: vec![0; DURATION_BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            })),
+        }
+    }
+}
+
+impl Histogram {
+    pub(crate) fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let mut counts = self.inner.lock().unwrap();
+        if let Some(i) = DURATION_BUCKETS.iter().position(|bound| secs <= *bound) {
+            counts.buckets[i] += 1;
+        }
+        counts.sum += secs;
+        counts.count += 1;
+    }
+
+    fn counts(&self) -> Counts {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+/// Metrics in the text exposition format. The samples of a family follow
+/// its `family` line, families must not be started twice.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    /// Start the family `name`, prefixed with picontrolx_.
+    pub(crate) fn family(&mut self, name: &str, kind: Kind, help: &str) {
+        let _ = writeln!(self.out, "# HELP {}{} {}", PREFIX, name, help);
+        let _ = writeln!(self.out, "# TYPE {}{} {}", PREFIX, name, kind.name());
+    }
+
+    /// A sample of the current family, `labels` are (name, value).
+    pub(crate) fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = writeln!(
+            self.out,
+            "{}{}{} {}",
+            PREFIX,
+            name,
+            format_labels(labels),
+            value
+        );
+    }
+
+    /// The buckets, sum and count of `histogram`, in a histogram family.
+    pub(crate) fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let counts = histogram.counts();
+        let bucket = format!("{}_bucket", name);
+        let bounds = DURATION_BUCKETS.iter().map(|bound| bound.to_string());
+        let cumulative = counts.buckets.iter().scan(0, |total, n| {
+            *total += n;
+            Some(*total)
+        });
+        let bounds = bounds.chain(std::iter::once(String::from("+Inf")));
+        let cumulative = cumulative.chain(std::iter::once(counts.count));
+        for (bound, n) in bounds.zip(cumulative) {
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &bound));
+            self.sample(&bucket, &bucket_labels, n as f64);
+        }
+        self.sample(&format!("{}_sum", name), labels, counts.sum);
+        self.sample(&format!("{}_count", name), labels, counts.count as f64);
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// `value` as a label value: backslashes, quotes and newlines escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+#[path = "./metrics_test.rs"]
+mod metrics_test;
@@ -0,0 +1,59 @@
+use super::*;
+
+#[test]
+fn test_samples() {
+    let mut out = MetricsWriter::default();
+    out.family("disk_size_bytes", Kind::Gauge, "Size of a disk.");
+    out.sample("disk_size_bytes", &[("disk", "sda")], 500e9);
+    out.sample("disk_size_bytes", &[("disk", "a\"b\\c\nd")], 1.5);
+    out.family("up", Kind::Gauge, "Always 1.");
+    out.sample("up", &[], 1.0);
+    assert_eq!(
+        out.finish(),
+        "# HELP picontrolx_disk_size_bytes Size of a disk.\n\
+         # TYPE picontrolx_disk_size_bytes gauge\n\
+         picontrolx_disk_size_bytes{disk=\"sda\"} 500000000000\n\
+         picontrolx_disk_size_bytes{disk=\"a\\\"b\\\\c\\nd\"} 1.5\n\
+         # HELP picontrolx_up Always 1.\n\
+         # TYPE picontrolx_up gauge\n\
+         picontrolx_up 1\n"
+    );
+}
+
+#[test]
+fn test_histogram() {
+    let histogram = Histogram::default();
+    histogram.observe(Duration::from_millis(3));
+    histogram.observe(Duration::from_millis(200));
+    histogram.observe(Duration::from_millis(250));
+    histogram.observe(Duration::from_secs(120));
+
+    let mut out = MetricsWriter::default();
+    out.histogram(
+        "rpc_duration_seconds",
+        &[("method", "ListDisks")],
+        &histogram,
+    );
+    let out = out.finish();
+    let sample = |name: &str| {
+        let line = out.lines().find(|l| l.starts_with(name)).unwrap();
+        line.rsplit(' ').next().unwrap().to_owned()
+    };
+    let bucket = |le: &str| {
+        sample(&format!(
+            "picontrolx_rpc_duration_seconds_bucket{{method=\"ListDisks\",le=\"{}\"}}",
+            le
+        ))
+    };
+    assert_eq!(bucket("0.001"), "0");
+    assert_eq!(bucket("0.005"), "1");
+    assert_eq!(bucket("0.1"), "1");
+    assert_eq!(bucket("0.25"), "3");
+    assert_eq!(bucket("60"), "3");
+    assert_eq!(bucket("+Inf"), "4");
+    assert_eq!(sample("picontrolx_rpc_duration_seconds_count"), "4");
+    let sum = sample("picontrolx_rpc_duration_seconds_sum")
+        .parse::<f64>()
+        .unwrap();
+    assert!((sum - 120.453).abs() < 1e-9, "{}", sum);
+}
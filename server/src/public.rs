@@ -1,33 +1,424 @@
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::BackupConfig;
+use job::{JobId, JobState};
+
 pub(crate) mod event_queue;
+pub(crate) mod job;
 pub(crate) mod shutdown;
+pub(crate) mod time;
+
+/// Name of a service, its events and watchers are keyed by it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ServiceType(&'static str);
+
+impl ServiceType {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        ServiceType(name)
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HealthState {
+    Running,
+    Restarting, // failed, waiting before the next start
+    Stopped,
+}
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum ServiceType {
-    _PRESERVED = 0,
-    DISK,
-    LEN,
+/// Health of the task of a service, tracked by its supervisor.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ServiceHealth {
+    pub(crate) service: ServiceType,
+    pub(crate) state: HealthState,
+    pub(crate) restarts: u32,
+    pub(crate) last_error: Option<String>,
+    pub(crate) last_error_time: u64, // unix timestamp, 0 if it never failed
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub(crate) struct PreservedServiceData {
     pub(crate) data: u32,
 }
 
-// #[derive(Clone, Debug)]
-// pub(crate) struct DiskInfo {
-    // pub(crate) uuid: String,
-// }
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Partition {
+    pub(crate) kernel: String,
+    pub(crate) size: u64,
+    pub(crate) uuid: Uuid,
+    pub(crate) label: String,
+    pub(crate) mount_path: Option<Vec<String>>,
+}
 
-#[derive(Debug, Clone)]
-pub(crate) struct DiskServiceData {
-    pub(crate) data: u32,
-    // pub(crate) disks: Vec<DiskInfo>,
+impl Partition {
+    /// First mount point of the partition, if mounted.
+    pub(crate) fn mount_point(&self) -> Option<&str> {
+        self.mount_path
+            .as_ref()
+            .and_then(|v| v.first())
+            .map(|v| v.as_str())
+    }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub(crate) enum ServiceData {
-    None,
-    Preserved(PreservedServiceData),
-    Disk(DiskServiceData),
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum SpinState {
+    #[default]
+    Unknown,
+    Active,
+    Standby,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DiskInfo {
+    pub(crate) kernel: String,
+    pub(crate) size: u64, // in bytes
+    pub(crate) serial: String,
+    pub(crate) discard: bool,
+    pub(crate) rotational: bool,
+    pub(crate) spin_state: SpinState,
+    pub(crate) partitions: Vec<Partition>,
+}
+
+/// A software RAID array, members are kernel names of the underlying devices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RaidArray {
+    pub(crate) kernel: String,
+    pub(crate) level: String,
+    pub(crate) state: String,
+    pub(crate) size: u64, // in bytes
+    pub(crate) degraded: bool,
+    pub(crate) degraded_since: u64, // unix timestamp, 0 if not degraded
+    pub(crate) members: Vec<String>,
+    pub(crate) failed: Vec<String>,
+    pub(crate) spares: Vec<String>,
+    pub(crate) sync_action: String,
+    pub(crate) sync_progress: Option<f32>, // in percent
 }
+
+/// An LVM logical volume, members are kernel names of the physical volumes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct LogicalVolume {
+    pub(crate) kernel: String,
+    pub(crate) name: String,
+    pub(crate) uuid: String,
+    pub(crate) size: u64, // in bytes
+    pub(crate) members: Vec<String>,
+}
+
+/// A disk the server has seen at least once, persisted across restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct KnownDisk {
+    pub(crate) uuid: String,
+    pub(crate) serial: String,
+    pub(crate) kernel: String,
+    pub(crate) label: String,
+    pub(crate) size: u64,
+    pub(crate) first_seen: u64, // unix timestamp, in seconds
+    pub(crate) last_seen: u64,  // unix timestamp, in seconds
+    pub(crate) last_mount_point: String,
+    pub(crate) nickname: String,
+    pub(crate) notes: String,
+    pub(crate) last_trim: u64, // unix timestamp, in seconds
+    pub(crate) last_trimmed_bytes: u64,
+    pub(crate) read_only: bool, // file API may not modify the partition
+}
+
+/// Outcome of trimming one partition.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TrimResult {
+    pub(crate) uuid: String,
+    pub(crate) kernel: String,
+    pub(crate) mount_point: String,
+    pub(crate) trimmed: u64, // in bytes
+    pub(crate) error: Option<String>,
+}
+
+/// Progress of a read-only surface scan of a whole disk.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScanProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) kernel: String,
+    pub(crate) scanned: u64,                // in bytes
+    pub(crate) total: u64,                  // in bytes
+    pub(crate) throughput: u64,             // in bytes per second
+    pub(crate) bad_ranges: Vec<(u64, u64)>, // (first LBA, number of sectors)
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum BenchPhase {
+    #[default]
+    Prepare,
+    SeqWrite,
+    SeqRead,
+    RandWrite,
+    RandRead,
+    Done,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BenchResult {
+    pub(crate) phase: BenchPhase,
+    pub(crate) mb_per_sec: f64,
+    pub(crate) iops: f64,
+}
+
+/// Progress of a benchmark on a mounted partition, `done` and `total` are
+/// bytes of the current phase.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BenchProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) uuid: String,
+    pub(crate) mount_point: String,
+    pub(crate) phase: BenchPhase,
+    pub(crate) done: u64,
+    pub(crate) total: u64,
+    pub(crate) results: Vec<BenchResult>,
+    pub(crate) error: Option<String>,
+}
+
+/// Progress of imaging the SD card into a file on partition `uuid`, or of
+/// verifying such an image. `path` is relative to the partition mount
+/// point, `skipped` counts unused bytes written as zeros without reading.
+/// `live_mounts` are the card filesystems mounted read-write while unused
+/// bytes were skipped, making the image best effort.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ImageProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) verify: bool,
+    pub(crate) device: String,
+    pub(crate) uuid: String,
+    pub(crate) path: String,
+    pub(crate) done: u64,  // in bytes
+    pub(crate) total: u64, // in bytes
+    pub(crate) skipped: u64,
+    pub(crate) live_mounts: Vec<String>,
+    pub(crate) checksum: String, // hex encoded SHA-256 of the image file
+    pub(crate) error: Option<String>,
+}
+
+/// A directory in the result of a space analysis, with the space used by
+/// its files and subdirectories. Only the largest subdirectories are kept.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UsageNode {
+    pub(crate) path: String, // relative to the mount point
+    pub(crate) bytes: u64,   // allocated on disk
+    pub(crate) files: u64,
+    pub(crate) children: Vec<UsageNode>,
+}
+
+/// Progress of a space analysis of the mounted partition `uuid`, with the
+/// result so far.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UsageProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) uuid: String,
+    pub(crate) mount_point: String,
+    pub(crate) dirs: u64,
+    pub(crate) files: u64,
+    pub(crate) bytes: u64,
+    pub(crate) errors: u64, // unreadable entries
+    pub(crate) root: UsageNode,
+    pub(crate) largest_files: Vec<(String, u64)>, // (path, bytes)
+    pub(crate) finished: u64,                     // unix timestamp, in seconds
+    pub(crate) error: Option<String>,
+}
+
+/// A file on partition `uuid`, or a directory to search for duplicates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DuplicateFile {
+    pub(crate) uuid: String,
+    pub(crate) path: String, // relative to the mount point
+}
+
+/// Files of the same size and content.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DuplicateGroup {
+    pub(crate) hash: String, // hex encoded SHA-256
+    pub(crate) size: u64,    // of each file, in bytes
+    pub(crate) files: Vec<DuplicateFile>,
+}
+
+impl DuplicateGroup {
+    /// Bytes freed by keeping a single file of the group.
+    pub(crate) fn reclaimable(&self) -> u64 {
+        self.size * (self.files.len() as u64).saturating_sub(1)
+    }
+}
+
+/// Progress of a duplicate search, with the groups found so far.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DuplicateProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) files_scanned: u64,
+    pub(crate) candidates: u64, // files sharing their size with another
+    pub(crate) hashed: u64,
+    pub(crate) bytes_hashed: u64,
+    pub(crate) bytes_total: u64, // to hash
+    pub(crate) errors: u64,
+    pub(crate) groups: Vec<DuplicateGroup>,
+    pub(crate) reclaimable: u64, // in bytes
+    pub(crate) error: Option<String>,
+}
+
+/// A file or directory below a managed mount point.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FileEntry {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) is_dir: bool,
+    pub(crate) is_symlink: bool,
+    pub(crate) mode: u32,
+    pub(crate) modified: u64, // unix timestamp, in seconds
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum FileError {
+    NotFound(String),
+    Denied(String),
+    Invalid(String),
+    Exists(String),
+    Io(String),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum FileOp {
+    #[default]
+    Copy,
+    Move,
+    Delete,
+}
+
+/// What to do when the destination of a copy or move already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ConflictPolicy {
+    #[default]
+    Skip,
+    Overwrite,
+    Rename, // keep both, the new one gets a " (n)" suffix
+}
+
+/// Copy or move `paths` from partition `source` into directory `target_dir`
+/// of partition `target`, or delete them. Partitions are given by uuid.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FileOpRequest {
+    pub(crate) op: FileOp,
+    pub(crate) source: String,
+    pub(crate) paths: Vec<String>,
+    pub(crate) target: String,
+    pub(crate) target_dir: String,
+    pub(crate) conflict: ConflictPolicy,
+    pub(crate) verify: bool, // compare checksums of copied files
+}
+
+/// Progress of a copy, move or delete job, `current` is the source path
+/// being processed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FileOpProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) op: FileOp,
+    pub(crate) files_done: u64,
+    pub(crate) files_total: u64,
+    pub(crate) bytes_done: u64,
+    pub(crate) bytes_total: u64,
+    pub(crate) current: String,
+    pub(crate) skipped: u64,
+    pub(crate) failed: Vec<(String, String)>, // (path, error)
+    pub(crate) error: Option<String>,
+}
+
+/// Outcome of the runs of a backup, kept across restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct BackupRecord {
+    pub(crate) last_run: u64,     // unix timestamp, in seconds
+    pub(crate) last_success: u64, // unix timestamp, in seconds
+    pub(crate) last_error: Option<String>,
+    pub(crate) last_snapshot: String,
+    pub(crate) files_copied: u64,
+    pub(crate) files_linked: u64,
+    pub(crate) files_failed: u64,
+    pub(crate) bytes_copied: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BackupStatus {
+    pub(crate) backup: BackupConfig,
+    pub(crate) from_config: bool,
+    pub(crate) running: bool,
+    pub(crate) record: BackupRecord,
+}
+
+/// Progress of importing photos and videos from the camera card `uuid`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ImportProgress {
+    pub(crate) job_id: JobId,
+    pub(crate) state: JobState,
+    pub(crate) uuid: String,
+    pub(crate) files_done: u64,
+    pub(crate) files_total: u64,
+    pub(crate) bytes_done: u64,
+    pub(crate) bytes_total: u64,
+    pub(crate) imported: u64,
+    pub(crate) duplicates: u64,
+    pub(crate) failed: u64,
+    pub(crate) unmounted: bool,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ShareProtocol {
+    #[default]
+    Smb,
+    Nfs,
+}
+
+/// A partition shared over the network while it is mounted.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Share {
+    pub(crate) uuid: String,
+    pub(crate) protocol: ShareProtocol,
+    pub(crate) name: String, // SMB share name
+    pub(crate) read_only: bool,
+    pub(crate) clients: String, // NFS client spec, empty for everyone
+}
+
+/// Space of a mounted partition and when it is expected to be full at the
+/// trend of its usage history.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct FillForecast {
+    pub(crate) uuid: String,
+    pub(crate) used: u64,      // in bytes
+    pub(crate) available: u64, // in bytes
+    pub(crate) days_until_full: Option<f64>,
+    pub(crate) warning: bool, // full sooner than the configured threshold
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DiskServiceData {
+    pub(crate) disks: Vec<DiskInfo>,
+    pub(crate) raids: Vec<RaidArray>,
+    pub(crate) volumes: Vec<LogicalVolume>,
+    pub(crate) known: Vec<KnownDisk>,
+    pub(crate) imports: Vec<ImportProgress>,
+    pub(crate) shares: Vec<Share>, // active ones
+    pub(crate) forecasts: Vec<FillForecast>,
+}
+
+/// State of a service, each service publishes its own type.
+pub(crate) type ServiceData = Arc<dyn Any + Send + Sync>;
@@ -1,65 +1,167 @@
 use log;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use tokio::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
 
-use super::ServiceType;
+use super::{ServiceData, ServiceType};
 
-#[derive(Debug)]
+/// State of a service as published by its cache.
+#[derive(Clone, Debug)]
 pub(crate) struct Event {
     pub(crate) service_type: ServiceType,
+    pub(crate) data: ServiceData,
+    /// Unix time the state was taken, in milliseconds.
+    pub(crate) timestamp: u64,
+    /// Increases with every event of the service, consumers order updates
+    /// with it.
+    pub(crate) sequence: u64,
 }
 
-#[derive(Clone)]
-pub(crate) struct EventNotifier {
-    sender: mpsc::Sender<()>,
-    q: Arc<Mutex<VecDeque<Event>>>,
+/// Counters of the queue, for diagnostics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct EventQStats {
+    pub(crate) pushed: u64,
+    /// Events merged into a pending event of the same service.
+    pub(crate) coalesced: u64,
+    /// Events lost because the queue was full.
+    pub(crate) dropped: u64,
+    pub(crate) max_depth: usize,
 }
 
-impl EventNotifier {
-    fn new(sender: mpsc::Sender<()>, q: Arc<Mutex<VecDeque<Event>>>) -> Self {
-        Self { sender, q }
+#[derive(Debug)]
+struct Inner {
+    pending: VecDeque<Event>,
+    capacity: usize,
+    stats: EventQStats,
+    /// Last sequence number of every service.
+    sequences: HashMap<ServiceType, u64>,
+}
+
+impl Inner {
+    /// Queue `event`, returns false if it was coalesced or dropped.
+    fn push(&mut self, event: Event) -> bool {
+        self.stats.pushed += 1;
+
+        // Events carry the whole state of a service, the newest pending
+        // event per service is enough.
+        if let Some(pending) = self
+            .pending
+            .iter_mut()
+            .find(|e| e.service_type == event.service_type)
+        {
+            *pending = event;
+            self.stats.coalesced += 1;
+            return false;
+        }
+
+        // A full queue makes room for the new event, the oldest one is the
+        // most likely to be outdated.
+        if self.pending.len() >= self.capacity {
+            self.stats.dropped += 1;
+            if let Some(oldest) = self.pending.pop_front() {
+                log::warn!("Event queue is full, dropping {:?}", oldest.service_type);
+            }
+        }
+
+        self.pending.push_back(event);
+        self.stats.max_depth = self.stats.max_depth.max(self.pending.len());
+        true
     }
+}
 
-    pub(crate) fn push(&self, data: Event) {
-        self.q.lock().unwrap().push_back(data);
-        if let Err(e) = self.sender.try_send(()) {
-            log::error!("Event Notifier send failed: {:?}", e);
+#[derive(Clone, Debug)]
+pub(crate) struct EventNotifier {
+    inner: Arc<Mutex<Inner>>,
+    notify: Arc<Notify>,
+}
+
+impl EventNotifier {
+    /// Publish the state returned by `snapshot`, returns its sequence
+    /// number. It runs under the queue lock, so sequence numbers follow the
+    /// order the states were taken in, and must not lock anything held
+    /// while pushing.
+    pub(crate) fn push<F>(&self, service_type: ServiceType, snapshot: F) -> u64
+    where
+        F: FnOnce() -> ServiceData,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let sequence = inner.sequences.entry(service_type).or_default();
+        *sequence += 1;
+        let sequence = *sequence;
+        let event = Event {
+            service_type,
+            sequence,
+            data: snapshot(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+        };
+        let queued = inner.push(event);
+        drop(inner);
+        if queued {
+            // Stores a permit if the consumer is busy, so bursts wake it once.
+            self.notify.notify_one();
         }
+        sequence
     }
 }
 
-#[derive(Debug)]
+/// Events of the caches waiting for the fetcher, bounded and coalesced per
+/// service. Clones share the queue.
+#[derive(Clone, Debug)]
 pub(crate) struct EventQ {
-    q: Arc<Mutex<VecDeque<Event>>>,
-    receiver: RwLock<Box<mpsc::Receiver<()>>>,
-    notifier: mpsc::Sender<()>,
+    inner: Arc<Mutex<Inner>>,
+    notify: Arc<Notify>,
 }
 
 impl EventQ {
     const QLEN: usize = 10;
 
     pub(crate) fn new() -> Self {
-        let (tx, rx) = mpsc::channel(1);
+        Self::with_capacity(Self::QLEN)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
         EventQ {
-            q: Arc::new(Mutex::new(VecDeque::with_capacity(Self::QLEN))),
-            receiver: RwLock::new(Box::new(rx)),
-            notifier: tx,
+            inner: Arc::new(Mutex::new(Inner {
+                pending: VecDeque::with_capacity(capacity),
+                capacity,
+                stats: EventQStats::default(),
+                sequences: HashMap::new(),
+            })),
+            notify: Arc::new(Notify::new()),
         }
     }
 
     pub(crate) fn get_notifier(&self) -> EventNotifier {
-        EventNotifier::new(self.notifier.clone(), self.q.clone())
+        EventNotifier {
+            inner: self.inner.clone(),
+            notify: self.notify.clone(),
+        }
     }
 
     pub(crate) fn drain(&self) -> Vec<Event> {
-        let mut guard = self.q.lock().unwrap();
-        guard.drain(..).collect()
+        let mut guard = self.inner.lock().unwrap();
+        guard.pending.drain(..).collect()
     }
 
     pub(crate) async fn notified(&self) {
-        let mut guard = self.receiver.write().await;
-        guard.recv().await;
+        self.notify.notified().await;
+    }
+
+    pub(crate) fn stats(&self) -> EventQStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    /// Events pending now.
+    pub(crate) fn depth(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
     }
 }
+
+#[cfg(test)]
+#[path = "./event_queue_test.rs"]
+mod event_queue_test;
@@ -0,0 +1,73 @@
+use super::*;
+use std::sync::Arc;
+
+const DISK: ServiceType = ServiceType::new("disk");
+const HELLO: ServiceType = ServiceType::new("hello");
+
+fn push(notifier: &EventNotifier, service_type: ServiceType, data: u32) {
+    notifier.push(service_type, || Arc::new(data));
+}
+
+fn payload(event: &Event) -> u32 {
+    *event.data.downcast_ref::<u32>().unwrap()
+}
+
+#[test]
+fn test_coalesce_per_service() {
+    let q = EventQ::new();
+    let notifier = q.get_notifier();
+    push(&notifier, DISK, 1);
+    push(&notifier, HELLO, 2);
+    push(&notifier, DISK, 3);
+
+    let events = q.drain();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].service_type, DISK);
+    // The newest state replaces the pending one, in place.
+    assert_eq!(payload(&events[0]), 3);
+    assert_eq!(events[0].sequence, 2);
+    assert_eq!(events[1].sequence, 1);
+    assert_eq!(
+        q.stats(),
+        EventQStats {
+            pushed: 3,
+            coalesced: 1,
+            dropped: 0,
+            max_depth: 2,
+        }
+    );
+
+    // Drained events do not coalesce with new ones.
+    push(&notifier, DISK, 4);
+    let events = q.drain();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].sequence, 3);
+}
+
+#[test]
+fn test_overflow_drops_oldest() {
+    let q = EventQ::with_capacity(1);
+    let notifier = q.get_notifier();
+    push(&notifier, DISK, 1);
+    push(&notifier, HELLO, 2);
+    let events = q.drain();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].service_type, HELLO);
+    assert_eq!(q.stats().dropped, 1);
+    assert_eq!(q.stats().max_depth, 1);
+}
+
+#[tokio::test]
+async fn test_burst_wakes_once() {
+    let q = EventQ::new();
+    let notifier = q.get_notifier();
+    for i in 0..100 {
+        push(&notifier, DISK, i);
+    }
+    q.notified().await;
+    let events = q.drain();
+    assert_eq!(events.len(), 1);
+    assert_eq!(payload(&events[0]), 99);
+    assert_eq!(events[0].sequence, 100);
+    assert_eq!(q.stats().coalesced, 99);
+}
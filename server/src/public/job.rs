@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::time::{sleep, Duration};
+
+pub(crate) type JobId = u64;
+
+/// How often `Jobs::wait_all` checks whether jobs are gone.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum JobState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+    Finished,
+    Failed,
+}
+
+impl JobState {
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(
+            self,
+            JobState::Cancelled | JobState::Finished | JobState::Failed
+        )
+    }
+}
+
+/// Returned by `JobToken::checkpoint` once the job is cancelled.
+#[derive(Debug)]
+pub(crate) struct JobCancelled;
+
+#[derive(Debug)]
+struct Control {
+    kind: &'static str,
+    state: Mutex<JobState>,
+    cond: Condvar,
+}
+
+/// Handed to a running job, which calls `checkpoint` between units of work.
+#[derive(Clone, Debug)]
+pub(crate) struct JobToken {
+    id: JobId,
+    control: Arc<Control>,
+}
+
+impl JobToken {
+    pub(crate) fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Blocks while the job is paused. Jobs run on blocking threads, so
+    /// waiting on a condition variable is fine here.
+    pub(crate) fn checkpoint(&self) -> Result<(), JobCancelled> {
+        self.checkpoint_with(|_| {})
+    }
+
+    /// Like `checkpoint`, and calls `paused(true)` before blocking on a
+    /// pause and `paused(false)` once resumed, so that the job can publish
+    /// its state. The state is not locked during the calls.
+    pub(crate) fn checkpoint_with(&self, mut paused: impl FnMut(bool)) -> Result<(), JobCancelled> {
+        let mut guard = self.control.state.lock().unwrap();
+        let pausing = *guard == JobState::Paused;
+        if pausing {
+            drop(guard);
+            paused(true);
+            guard = self
+                .control
+                .cond
+                .wait_while(self.control.state.lock().unwrap(), |state| {
+                    *state == JobState::Paused
+                })
+                .unwrap();
+        }
+        let state = *guard;
+        drop(guard);
+        match state {
+            JobState::Cancelled => Err(JobCancelled),
+            _ => {
+                if pausing {
+                    paused(false);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Background jobs of a service, addressed by id for pause, resume and cancel.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Jobs {
+    inner: Arc<Mutex<HashMap<JobId, Arc<Control>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Jobs {
+    pub(crate) fn register(&self, kind: &'static str) -> JobToken {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let control = Arc::new(Control {
+            kind,
+            state: Mutex::new(JobState::Running),
+            cond: Condvar::new(),
+        });
+        self.inner.lock().unwrap().insert(id, control.clone());
+        log::info!("Job {} ({}) is registered", id, kind);
+
+        JobToken { id, control }
+    }
+
+    /// Forget a job once it is done, it can not be controlled anymore.
+    pub(crate) fn unregister(&self, token: &JobToken) {
+        self.inner.lock().unwrap().remove(&token.id);
+    }
+
+    fn transit(&self, id: JobId, from: &[JobState], to: JobState) -> Result<(), String> {
+        let control = self
+            .inner
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("No such job {}", id))?;
+
+        let mut state = control.state.lock().unwrap();
+        if !from.contains(&*state) {
+            return Err(format!("Job {} is {:?}", id, *state));
+        }
+        log::info!("Job {} ({}) {:?} -> {:?}", id, control.kind, *state, to);
+        *state = to;
+        control.cond.notify_all();
+        Ok(())
+    }
+
+    pub(crate) fn pause(&self, id: JobId) -> Result<(), String> {
+        self.transit(id, &[JobState::Running], JobState::Paused)
+    }
+
+    pub(crate) fn resume(&self, id: JobId) -> Result<(), String> {
+        self.transit(id, &[JobState::Paused], JobState::Running)
+    }
+
+    pub(crate) fn cancel(&self, id: JobId) -> Result<(), String> {
+        self.transit(
+            id,
+            &[JobState::Running, JobState::Paused],
+            JobState::Cancelled,
+        )
+    }
+
+    /// Cancel every job, e.g. on shutdown.
+    pub(crate) fn cancel_all(&self) {
+        for control in self.inner.lock().unwrap().values() {
+            *control.state.lock().unwrap() = JobState::Cancelled;
+            control.cond.notify_all();
+        }
+    }
+
+    /// Wait until every job is unregistered. Jobs run on blocking threads
+    /// and are not awaitable, so this polls.
+    pub(crate) async fn wait_all(&self) {
+        while !self.inner.lock().unwrap().is_empty() {
+            sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./job_test.rs"]
+mod job_test;
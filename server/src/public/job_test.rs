@@ -0,0 +1,35 @@
+use super::*;
+use std::sync::mpsc;
+use std::thread;
+
+#[test]
+fn test_checkpoint_reports_pause() {
+    let jobs = Jobs::default();
+    let token = jobs.register("test");
+    let id = token.id();
+    let (tx, rx) = mpsc::channel();
+
+    let mut calls = Vec::new();
+    token.checkpoint_with(|paused| calls.push(paused)).unwrap();
+    assert!(calls.is_empty());
+
+    jobs.pause(id).unwrap();
+    let worker = thread::spawn(move || {
+        let result = token.checkpoint_with(|paused| tx.send(paused).unwrap());
+        (result.is_ok(), token)
+    });
+    assert!(rx.recv().unwrap());
+    jobs.resume(id).unwrap();
+    assert!(!rx.recv().unwrap());
+    let (resumed, token) = worker.join().unwrap();
+    assert!(resumed);
+
+    // Cancelled while paused: no resume is reported.
+    let (tx, rx) = mpsc::channel();
+    jobs.pause(id).unwrap();
+    let worker = thread::spawn(move || token.checkpoint_with(|paused| tx.send(paused).unwrap()));
+    assert!(rx.recv().unwrap());
+    jobs.cancel(id).unwrap();
+    assert!(worker.join().unwrap().is_err());
+    assert!(rx.recv().is_err());
+}
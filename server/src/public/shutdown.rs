@@ -19,15 +19,32 @@ pub(crate) fn new() -> (Sender, Receiver) {
 }
 
 impl Sender {
+    /// Signal the receivers, without waiting for them.
+    pub(crate) fn signal(&self) {
+        if self.inner.send(SENDVAL).is_err() {
+            log::debug!("Send shutdown signal, no receiver left");
+        }
+    }
+
+    /// Signal the receivers and wait until all of them are dropped.
     pub(crate) async fn shutdown(&self) {
-        self.inner.send(SENDVAL).unwrap();
+        self.signal();
         log::debug!("Send shutdown signal, waiting close...");
         self.inner.closed().await;
     }
 }
 
 impl Receiver {
+    /// Wait for the shutdown signal, returns at once if the sender is gone.
     pub(crate) async fn wait_on(&mut self) {
-        self.inner.changed().await.unwrap();
+        let _ = self.inner.changed().await;
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        *self.inner.borrow() == SENDVAL
     }
 }
+
+#[cfg(test)]
+#[path = "./shutdown_test.rs"]
+mod shutdown_test;
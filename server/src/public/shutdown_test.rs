@@ -0,0 +1,28 @@
+use super::*;
+use tokio::time::{timeout, Duration};
+
+#[tokio::test]
+async fn test_shutdown_without_receivers() {
+    let (sender, receiver) = new();
+    drop(receiver);
+    // Neither panics nor waits.
+    timeout(Duration::from_secs(1), sender.shutdown())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_on() {
+    let (sender, mut receiver) = new();
+    let mut late = receiver.clone();
+    assert!(!receiver.is_shutdown());
+    sender.signal();
+    receiver.wait_on().await;
+    assert!(receiver.is_shutdown());
+
+    // A dropped sender counts as shutdown.
+    drop(sender);
+    timeout(Duration::from_secs(1), late.wait_on())
+        .await
+        .unwrap();
+}
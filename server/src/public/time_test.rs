@@ -0,0 +1,8 @@
+use super::*;
+
+#[test]
+fn test_utc_datetime() {
+    assert_eq!(utc_datetime(0), (1970, 1, 1, 0, 0, 0));
+    assert_eq!(utc_datetime(951_782_400), (2000, 2, 29, 0, 0, 0));
+    assert_eq!(utc_datetime(1_700_000_000), (2023, 11, 14, 22, 13, 20));
+}
@@ -0,0 +1,139 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+
+use crate::caches::Cache;
+use crate::metrics::MetricsWriter;
+use crate::public::event_queue::Event;
+use crate::public::ServiceType;
+
+/// Message streamed to the watchers of a service.
+pub(crate) type WatchMessage = Arc<dyn Any + Send + Sync>;
+/// Turns the state of a service into its watch message.
+pub(crate) type Converter = Box<dyn Fn(&Event) -> Option<WatchMessage> + Send + Sync>;
+/// Writes the state of a service as metrics.
+pub(crate) type Exporter = Box<dyn Fn(&Event, &mut MetricsWriter) + Send + Sync>;
+
+/// Messages streamed to watchers carry the order and time of the state they
+/// show, and tell watchers when the server goes away.
+pub(crate) trait Watchable {
+    fn stamp(&mut self, sequence: u64, timestamp: u64);
+    /// Last message of a stream ended by shutdown.
+    fn going_away() -> Self;
+}
+
+/// Handlers of the registered services, looked up by type.
+#[derive(Clone, Default)]
+pub(crate) struct Handlers {
+    inner: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Handlers {
+    pub(crate) fn get<H: Send + 'static>(&self) -> Option<Arc<TokioMutex<H>>> {
+        let handler = self.inner.get(&TypeId::of::<H>())?.clone();
+        handler.downcast().ok()
+    }
+}
+
+/// Services of the server. A service registers its cache, the handler the
+/// gRPC service reaches it through and, if clients can watch it or scrape
+/// its metrics, how its state is converted, so the core dispatches without
+/// knowing services.
+#[derive(Default)]
+pub(crate) struct Registry {
+    caches: Vec<Box<dyn Cache + Send + Sync>>,
+    handlers: Handlers,
+    converters: HashMap<ServiceType, Converter>,
+    exporters: HashMap<ServiceType, Exporter>,
+}
+
+/// What the registry hands to the core once every service is registered.
+pub(crate) struct Parts {
+    pub(crate) caches: Vec<Box<dyn Cache + Send + Sync>>,
+    pub(crate) handlers: Handlers,
+    pub(crate) converters: HashMap<ServiceType, Converter>,
+    pub(crate) exporters: HashMap<ServiceType, Exporter>,
+}
+
+pub(crate) struct Registration<'a> {
+    registry: &'a mut Registry,
+    service_type: ServiceType,
+}
+
+impl Registry {
+    pub(crate) fn register<C, H>(&mut self, cache: C, handler: H) -> Registration<'_>
+    where
+        C: Cache + Send + Sync + 'static,
+        H: Send + 'static,
+    {
+        let service_type = cache.get_type();
+        let handler = Arc::new(TokioMutex::new(handler));
+        if self
+            .handlers
+            .inner
+            .insert(TypeId::of::<H>(), handler)
+            .is_some()
+        {
+            panic!("Handler of {:?} is registered twice", service_type);
+        }
+        self.caches.push(Box::new(cache));
+
+        Registration {
+            registry: self,
+            service_type,
+        }
+    }
+
+    pub(crate) fn into_parts(self) -> Parts {
+        Parts {
+            caches: self.caches,
+            handlers: self.handlers,
+            converters: self.converters,
+            exporters: self.exporters,
+        }
+    }
+}
+
+impl Registration<'_> {
+    /// Let clients watch the service, `convert` turns its state into the
+    /// message streamed to them.
+    pub(crate) fn watch<D, M>(self, convert: fn(&D) -> Option<M>) -> Self
+    where
+        D: Any,
+        M: Watchable + Send + Sync + 'static,
+    {
+        let service_type = self.service_type;
+        let converter = move |event: &Event| {
+            let data = event.data.downcast_ref::<D>();
+            if data.is_none() {
+                log::error!("{:?} published data of an unexpected type", service_type);
+            }
+            let mut message = convert(data?)?;
+            message.stamp(event.sequence, event.timestamp);
+            Some(Arc::new(message) as WatchMessage)
+        };
+        self.registry
+            .converters
+            .insert(service_type, Box::new(converter));
+        self
+    }
+
+    /// Serve the state of the service as metrics, written by `export`.
+    pub(crate) fn export<D: Any>(self, export: fn(&D, &mut MetricsWriter)) -> Self {
+        let service_type = self.service_type;
+        let exporter =
+            move |event: &Event, out: &mut MetricsWriter| match event.data.downcast_ref::<D>() {
+                Some(data) => export(data, out),
+                None => log::error!("{:?} published data of an unexpected type", service_type),
+            };
+        self.registry
+            .exporters
+            .insert(service_type, Box::new(exporter));
+        self
+    }
+}
+
+#[cfg(test)]
+#[path = "./registry_test.rs"]
+mod registry_test;
@@ -0,0 +1,101 @@
+use super::*;
+use crate::caches::Schedule;
+use crate::metrics::Kind;
+use crate::public::event_queue::EventQ;
+use crate::public::shutdown;
+use futures::future::BoxFuture;
+use tokio::time::Duration;
+
+const THIS_TYPE: ServiceType = ServiceType::new("test");
+
+struct TestCache;
+
+impl Cache for TestCache {
+    fn run(&self, _shutdown: shutdown::Receiver, _: Schedule) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn get_type(&self) -> ServiceType {
+        THIS_TYPE
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+struct TestHandler(u32);
+
+#[derive(Debug, PartialEq)]
+struct Message {
+    value: u32,
+    sequence: u64,
+}
+
+impl Watchable for Message {
+    fn stamp(&mut self, sequence: u64, _timestamp: u64) {
+        self.sequence = sequence;
+    }
+
+    fn going_away() -> Self {
+        Message {
+            value: 0,
+            sequence: 0,
+        }
+    }
+}
+
+fn to_message(data: &u32) -> Option<Message> {
+    Some(Message {
+        value: *data,
+        sequence: 0,
+    })
+}
+
+fn export(data: &u32, out: &mut MetricsWriter) {
+    out.family("test_value", Kind::Gauge, "Value of the test service.");
+    out.sample("test_value", &[], f64::from(*data));
+}
+
+#[tokio::test]
+async fn test_register_and_dispatch() {
+    let mut registry = Registry::default();
+    registry
+        .register(TestCache, TestHandler(7))
+        .watch(to_message)
+        .export(export);
+    let Parts {
+        caches,
+        handlers,
+        converters,
+        exporters,
+    } = registry.into_parts();
+    assert_eq!(caches.len(), 1);
+
+    let handler = handlers.get::<TestHandler>().unwrap();
+    assert_eq!(handler.lock().await.0, 7);
+    assert!(handlers.get::<u32>().is_none());
+
+    let q = EventQ::new();
+    q.get_notifier().push(THIS_TYPE, || Arc::new(5u32));
+    q.get_notifier()
+        .push(ServiceType::new("other"), || Arc::new(6u32));
+    let events = q.drain();
+    let message = converters[&THIS_TYPE](&events[0]).unwrap();
+    assert_eq!(
+        message.downcast_ref::<Message>(),
+        Some(&Message {
+            value: 5,
+            sequence: 1
+        })
+    );
+    assert!(!converters.contains_key(&events[1].service_type));
+
+    let mut out = MetricsWriter::default();
+    exporters[&THIS_TYPE](&events[0], &mut out);
+    assert!(out.finish().ends_with("picontrolx_test_value 5\n"));
+
+    // Data of another type is not converted.
+    q.get_notifier().push(THIS_TYPE, || Arc::new("5"));
+    assert!(converters[&THIS_TYPE](&q.drain()[0]).is_none());
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+
+use crate::caches::CacheManagerHandler;
+use crate::config::{Config, ConfigSource, LIVE_SETTINGS};
+use crate::logging::LogHandle;
+
+/// Settings changed by a reload, named after their command line flag.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Reload {
+    pub(crate) applied: Vec<&'static str>,
+    /// Changed settings which take effect on restart only.
+    pub(crate) restart_required: Vec<&'static str>,
+}
+
+/// Loads the configuration again, on SIGHUP or through the API, and applies
+/// what can change while running.
+#[derive(Clone)]
+pub(crate) struct Reloader {
+    source: Arc<ConfigSource>,
+    /// The configuration in effect. Settings needing a restart keep their
+    /// running value, so every later reload reports them again.
+    running: Arc<TokioMutex<Config>>,
+    caches: CacheManagerHandler,
+    log: LogHandle,
+}
+
+impl Reloader {
+    pub(crate) fn new(
+        config: Config,
+        source: ConfigSource,
+        caches: CacheManagerHandler,
+        log: LogHandle,
+    ) -> Self {
+        Self {
+            source: Arc::new(source),
+            running: Arc::new(TokioMutex::new(config)),
+            caches,
+            log,
+        }
+    }
+
+    pub(crate) async fn reload(&self) -> Result<Reload, String> {
+        let new = self.source.load().map_err(|e| {
+            log::error!("Configuration is not reloaded: {}", e);
+            e
+        })?;
+        let mut running = self.running.lock().await;
+        let (applied, restart_required) = running
+            .changes(&new)
+            .into_iter()
+            .partition::<Vec<_>, _>(|setting| LIVE_SETTINGS.contains(setting));
+
+        for setting in &applied {
+            match *setting {
+                "log-level" => {
+                    self.log.set_levels(new.log.level.clone());
+                    running.log.level = new.log.level.clone();
+                }
+                "disable-service" => {
+                    self.caches.set_disabled(&new.disabled_services).await;
+                    running.disabled_services = new.disabled_services.clone();
+                }
+                "refresh-interval" => {
+                    self.caches
+                        .set_refresh_intervals(&new.refresh_intervals)
+                        .await;
+                    running.refresh_intervals = new.refresh_intervals.clone();
+                }
+                _ => unreachable!("{} is not a live setting", setting),
+            }
+        }
+
+        log::warn!("Configuration is reloaded, applied {:?}", applied);
+        if !restart_required.is_empty() {
+            log::warn!("Restart to apply {:?}", restart_required);
+        }
+        Ok(Reload {
+            applied,
+            restart_required,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "./reload_test.rs"]
+mod reload_test;
@@ -0,0 +1,38 @@
+use super::*;
+use crate::caches::CacheManager;
+use crate::test_util::TempDir;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+#[tokio::test]
+async fn test_reload() {
+    let dir = TempDir::new("reload");
+    let path = dir.join("reload.toml");
+    fs::write(&path, "port = 1000\n").unwrap();
+    let source = ConfigSource {
+        path: Some(path.to_str().unwrap().to_owned()),
+        env: vec![],
+        flags: vec![(String::from("state-dir"), String::from("/srv"))],
+    };
+    let config = source.load().unwrap();
+    let caches = CacheManager::new(vec![], &HashMap::new()).run(&HashSet::new());
+    let log = LogHandle::new(config.log.level.clone());
+    let reloader = Reloader::new(config, source, caches, log);
+
+    assert_eq!(reloader.reload().await, Ok(Reload::default()));
+
+    // Flags still override the file.
+    let text = "port = 2000\nstate_dir = \"/var\"\n[services.disk]\nrefresh_interval = 5\n";
+    fs::write(&path, text).unwrap();
+    let reload = reloader.reload().await.unwrap();
+    assert_eq!(reload.applied, vec!["refresh-interval"]);
+    assert_eq!(reload.restart_required, vec!["port"]);
+
+    // Until restarted.
+    let reload = reloader.reload().await.unwrap();
+    assert!(reload.applied.is_empty());
+    assert_eq!(reload.restart_required, vec!["port"]);
+
+    fs::write(&path, "port = \"x\"\n").unwrap();
+    assert!(reloader.reload().await.is_err());
+}
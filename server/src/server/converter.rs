@@ -1,12 +1,44 @@
+use tonic::Status;
+
+use super::api_rpc;
 use super::api_rpc::{Disk, DiskListAndWatchResponse};
-use crate::public::DiskServiceData;
+use crate::config::BackupConfig;
+use crate::public::job::JobState;
 use crate::public::PreservedServiceData;
+use crate::public::{
+    BackupStatus, BenchPhase, BenchProgress, ConflictPolicy, DiskServiceData, DuplicateFile,
+    DuplicateGroup, DuplicateProgress, FileEntry, FileError, FileOp, FileOpProgress, HealthState,
+    ImageProgress, ImportProgress, KnownDisk, ScanProgress, ServiceHealth, Share, ShareProtocol,
+    SpinState, TrimResult, UsageNode, UsageProgress,
+};
+use crate::registry::Watchable;
+use crate::reload::Reload;
+
+impl Watchable for DiskListAndWatchResponse {
+    fn stamp(&mut self, sequence: u64, timestamp: u64) {
+        self.sequence = sequence;
+        self.timestamp_ms = timestamp;
+    }
+
+    fn going_away() -> Self {
+        DiskListAndWatchResponse {
+            going_away: true,
+            ..Default::default()
+        }
+    }
+}
 
-pub(super) fn preserved_to_disk_list_and_watch_response(
+pub(crate) fn preserved_to_disk_list_and_watch_response(
     _data: &PreservedServiceData,
 ) -> Option<DiskListAndWatchResponse> {
     dbg!(_data);
     let disks = DiskListAndWatchResponse {
+        raid_arrays: Vec::new(),
+        logical_volumes: Vec::new(),
+        imports: Vec::new(),
+        sequence: 0,
+        timestamp_ms: 0,
+        going_away: false,
         disks: vec![
             Disk {
                 name: String::from("helllllo"),
@@ -15,6 +47,7 @@ pub(super) fn preserved_to_disk_list_and_watch_response(
                 mounted: true,
                 mount_point: String::from("/mnt"),
                 label: String::from("label1"),
+                ..Default::default()
             },
             Disk {
                 name: String::from("world"),
@@ -23,6 +56,7 @@ pub(super) fn preserved_to_disk_list_and_watch_response(
                 mounted: true,
                 mount_point: String::from("/media"),
                 label: String::from("label2"),
+                ..Default::default()
             },
         ],
     };
@@ -30,30 +64,458 @@ pub(super) fn preserved_to_disk_list_and_watch_response(
     Some(disks)
 }
 
-pub(super) fn data_to_disk_list_and_watch_response(
+pub(crate) fn data_to_disk_list_and_watch_response(
     data: &DiskServiceData,
 ) -> Option<DiskListAndWatchResponse> {
-    dbg!(data);
-    let disks = DiskListAndWatchResponse {
-        disks: vec![
-            Disk {
-                name: String::from("helllllo"),
-                size: 1024,
-                uuid: String::from("123-123-4123-123"),
-                mounted: true,
-                mount_point: String::from("/mnt"),
-                label: String::from("label1"),
-            },
-            Disk {
-                name: String::from("world"),
-                size: 1024,
-                uuid: String::from("123-123-4123-123"),
-                mounted: true,
-                mount_point: String::from("/media"),
-                label: String::from("label2"),
-            },
-        ],
+    let mut disks = Vec::new();
+    for disk in data.disks.iter() {
+        for part in disk.partitions.iter() {
+            let uuid = part.uuid.to_string();
+            let mount_point = part
+                .mount_path
+                .as_ref()
+                .and_then(|v| v.first().cloned())
+                .unwrap_or_default();
+            let known = data.known.iter().find(|k| k.uuid == uuid);
+            let known = known.cloned().unwrap_or_default();
+            let forecast = data.forecasts.iter().find(|f| f.uuid == uuid);
+            let forecast = forecast.cloned().unwrap_or_default();
+
+            disks.push(Disk {
+                name: part.kernel.clone(),
+                size: part.size,
+                uuid,
+                mounted: !mount_point.is_empty(),
+                mount_point,
+                label: part.label.clone(),
+                nickname: known.nickname,
+                notes: known.notes,
+                serial: disk.serial.clone(),
+                discard: disk.discard,
+                last_trim: known.last_trim,
+                last_trimmed_bytes: known.last_trimmed_bytes,
+                spin_state: spin_state_to_rpc(disk.spin_state) as i32,
+                read_only: known.read_only,
+                shares: data
+                    .shares
+                    .iter()
+                    .filter(|s| s.uuid == part.uuid.to_string())
+                    .map(|s| share_to_rpc(s, true))
+                    .collect(),
+                used_bytes: forecast.used,
+                available_bytes: forecast.available,
+                days_until_full: forecast.days_until_full.unwrap_or(-1.0),
+                fill_warning: forecast.warning,
+            });
+        }
+    }
+
+    let raid_arrays = data
+        .raids
+        .iter()
+        .map(|r| api_rpc::RaidArray {
+            name: r.kernel.clone(),
+            level: r.level.clone(),
+            state: r.state.clone(),
+            size: r.size,
+            degraded: r.degraded,
+            degraded_since: r.degraded_since,
+            members: r.members.clone(),
+            failed: r.failed.clone(),
+            spares: r.spares.clone(),
+            sync_action: r.sync_action.clone(),
+            sync_progress: r.sync_progress.unwrap_or(-1.0),
+        })
+        .collect();
+
+    let logical_volumes = data
+        .volumes
+        .iter()
+        .map(|v| api_rpc::LogicalVolume {
+            name: v.kernel.clone(),
+            lv_name: v.name.clone(),
+            uuid: v.uuid.clone(),
+            size: v.size,
+            members: v.members.clone(),
+        })
+        .collect();
+
+    Some(DiskListAndWatchResponse {
+        disks,
+        raid_arrays,
+        logical_volumes,
+        imports: data.imports.iter().map(import_progress_to_rpc).collect(),
+        ..Default::default()
+    })
+}
+
+fn import_progress_to_rpc(progress: &ImportProgress) -> api_rpc::CardImport {
+    api_rpc::CardImport {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        uuid: progress.uuid.clone(),
+        files_done: progress.files_done,
+        files_total: progress.files_total,
+        bytes_done: progress.bytes_done,
+        bytes_total: progress.bytes_total,
+        imported: progress.imported,
+        duplicates: progress.duplicates,
+        failed: progress.failed,
+        unmounted: progress.unmounted,
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+fn spin_state_to_rpc(state: SpinState) -> api_rpc::SpinState {
+    match state {
+        SpinState::Unknown => api_rpc::SpinState::Unknown,
+        SpinState::Active => api_rpc::SpinState::Active,
+        SpinState::Standby => api_rpc::SpinState::Standby,
+    }
+}
+
+pub(super) fn known_disk_to_rpc(disk: &KnownDisk, present: bool) -> api_rpc::KnownDisk {
+    api_rpc::KnownDisk {
+        uuid: disk.uuid.clone(),
+        serial: disk.serial.clone(),
+        name: disk.kernel.clone(),
+        label: disk.label.clone(),
+        size: disk.size,
+        first_seen: disk.first_seen,
+        last_seen: disk.last_seen,
+        last_mount_point: disk.last_mount_point.clone(),
+        nickname: disk.nickname.clone(),
+        notes: disk.notes.clone(),
+        present,
+        last_trim: disk.last_trim,
+        last_trimmed_bytes: disk.last_trimmed_bytes,
+    }
+}
+
+pub(super) fn trim_result_to_rpc(result: &TrimResult) -> api_rpc::DiskTrimResult {
+    api_rpc::DiskTrimResult {
+        uuid: result.uuid.clone(),
+        name: result.kernel.clone(),
+        mount_point: result.mount_point.clone(),
+        ok: result.error.is_none(),
+        trimmed_bytes: result.trimmed,
+        reason: result.error.clone().unwrap_or_default(),
+    }
+}
+
+fn job_state_to_rpc(state: JobState) -> api_rpc::JobState {
+    match state {
+        JobState::Running => api_rpc::JobState::Running,
+        JobState::Paused => api_rpc::JobState::Paused,
+        JobState::Cancelled => api_rpc::JobState::Cancelled,
+        JobState::Finished => api_rpc::JobState::Finished,
+        JobState::Failed => api_rpc::JobState::Failed,
+    }
+}
+
+pub(super) fn scan_progress_to_rpc(progress: &ScanProgress) -> api_rpc::DiskSurfaceScanProgress {
+    api_rpc::DiskSurfaceScanProgress {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        name: progress.kernel.clone(),
+        scanned_bytes: progress.scanned,
+        total_bytes: progress.total,
+        throughput: progress.throughput,
+        bad_ranges: progress
+            .bad_ranges
+            .iter()
+            .map(|(start, count)| api_rpc::LbaRange {
+                start: *start,
+                count: *count,
+            })
+            .collect(),
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+pub(super) fn image_progress_to_rpc(progress: &ImageProgress) -> api_rpc::DiskImageProgress {
+    api_rpc::DiskImageProgress {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        verify: progress.verify,
+        name: progress.device.clone(),
+        uuid: progress.uuid.clone(),
+        path: progress.path.clone(),
+        done_bytes: progress.done,
+        total_bytes: progress.total,
+        skipped_bytes: progress.skipped,
+        checksum: progress.checksum.clone(),
+        reason: progress.error.clone().unwrap_or_default(),
+        live_mounts: progress.live_mounts.clone(),
+    }
+}
+
+fn usage_node_to_rpc(node: &UsageNode) -> api_rpc::DiskUsageNode {
+    api_rpc::DiskUsageNode {
+        path: node.path.clone(),
+        bytes: node.bytes,
+        files: node.files,
+        children: node.children.iter().map(usage_node_to_rpc).collect(),
+    }
+}
+
+pub(super) fn usage_progress_to_rpc(progress: &UsageProgress) -> api_rpc::DiskUsageReport {
+    api_rpc::DiskUsageReport {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        uuid: progress.uuid.clone(),
+        mount_point: progress.mount_point.clone(),
+        dirs: progress.dirs,
+        files: progress.files,
+        bytes: progress.bytes,
+        errors: progress.errors,
+        root: Some(usage_node_to_rpc(&progress.root)),
+        largest_files: progress
+            .largest_files
+            .iter()
+            .map(|(path, bytes)| api_rpc::DiskUsageFile {
+                path: path.clone(),
+                bytes: *bytes,
+            })
+            .collect(),
+        finished: progress.finished,
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+pub(super) fn duplicate_file_from_rpc(file: api_rpc::DuplicateFile) -> DuplicateFile {
+    DuplicateFile {
+        uuid: file.uuid,
+        path: file.path,
+    }
+}
+
+pub(super) fn duplicate_file_to_rpc(file: &DuplicateFile) -> api_rpc::DuplicateFile {
+    api_rpc::DuplicateFile {
+        uuid: file.uuid.clone(),
+        path: file.path.clone(),
+    }
+}
+
+fn duplicate_group_to_rpc(group: &DuplicateGroup) -> api_rpc::DuplicateGroup {
+    api_rpc::DuplicateGroup {
+        hash: group.hash.clone(),
+        size: group.size,
+        files: group.files.iter().map(duplicate_file_to_rpc).collect(),
+        reclaimable_bytes: group.reclaimable(),
+    }
+}
+
+pub(super) fn duplicate_progress_to_rpc(
+    progress: &DuplicateProgress,
+) -> api_rpc::DuplicateFindProgress {
+    api_rpc::DuplicateFindProgress {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        files_scanned: progress.files_scanned,
+        candidates: progress.candidates,
+        hashed: progress.hashed,
+        hashed_bytes: progress.bytes_hashed,
+        total_bytes: progress.bytes_total,
+        errors: progress.errors,
+        groups: progress.groups.iter().map(duplicate_group_to_rpc).collect(),
+        reclaimable_bytes: progress.reclaimable,
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+fn bench_phase_to_rpc(phase: BenchPhase) -> api_rpc::BenchPhase {
+    match phase {
+        BenchPhase::Prepare => api_rpc::BenchPhase::Prepare,
+        BenchPhase::SeqWrite => api_rpc::BenchPhase::SeqWrite,
+        BenchPhase::SeqRead => api_rpc::BenchPhase::SeqRead,
+        BenchPhase::RandWrite => api_rpc::BenchPhase::RandWrite,
+        BenchPhase::RandRead => api_rpc::BenchPhase::RandRead,
+        BenchPhase::Done => api_rpc::BenchPhase::Done,
+    }
+}
+
+pub(super) fn bench_progress_to_rpc(progress: &BenchProgress) -> api_rpc::DiskBenchmarkProgress {
+    api_rpc::DiskBenchmarkProgress {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        uuid: progress.uuid.clone(),
+        mount_point: progress.mount_point.clone(),
+        phase: bench_phase_to_rpc(progress.phase) as i32,
+        done_bytes: progress.done,
+        total_bytes: progress.total,
+        results: progress
+            .results
+            .iter()
+            .map(|r| api_rpc::DiskBenchmarkResult {
+                phase: bench_phase_to_rpc(r.phase) as i32,
+                mb_per_sec: r.mb_per_sec,
+                iops: r.iops,
+            })
+            .collect(),
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+pub(super) fn file_entry_to_rpc(entry: &FileEntry) -> api_rpc::FileInfo {
+    api_rpc::FileInfo {
+        name: entry.name.clone(),
+        size: entry.size,
+        is_dir: entry.is_dir,
+        is_symlink: entry.is_symlink,
+        mode: entry.mode,
+        modified: entry.modified,
+    }
+}
+
+pub(super) fn file_op_from_rpc(op: api_rpc::FileOp) -> FileOp {
+    match op {
+        api_rpc::FileOp::Copy => FileOp::Copy,
+        api_rpc::FileOp::Move => FileOp::Move,
+        api_rpc::FileOp::Delete => FileOp::Delete,
+    }
+}
+
+fn file_op_to_rpc(op: FileOp) -> api_rpc::FileOp {
+    match op {
+        FileOp::Copy => api_rpc::FileOp::Copy,
+        FileOp::Move => api_rpc::FileOp::Move,
+        FileOp::Delete => api_rpc::FileOp::Delete,
+    }
+}
+
+pub(super) fn conflict_policy_from_rpc(policy: api_rpc::ConflictPolicy) -> ConflictPolicy {
+    match policy {
+        api_rpc::ConflictPolicy::Skip => ConflictPolicy::Skip,
+        api_rpc::ConflictPolicy::Overwrite => ConflictPolicy::Overwrite,
+        api_rpc::ConflictPolicy::Rename => ConflictPolicy::Rename,
+    }
+}
+
+pub(super) fn file_op_progress_to_rpc(progress: &FileOpProgress) -> api_rpc::FileOperationProgress {
+    api_rpc::FileOperationProgress {
+        job_id: progress.job_id,
+        state: job_state_to_rpc(progress.state) as i32,
+        op: file_op_to_rpc(progress.op) as i32,
+        files_done: progress.files_done,
+        files_total: progress.files_total,
+        bytes_done: progress.bytes_done,
+        bytes_total: progress.bytes_total,
+        current: progress.current.clone(),
+        skipped: progress.skipped,
+        failed: progress
+            .failed
+            .iter()
+            .map(|(path, reason)| api_rpc::FileOperationFailure {
+                path: path.clone(),
+                reason: reason.clone(),
+            })
+            .collect(),
+        reason: progress.error.clone().unwrap_or_default(),
+    }
+}
+
+pub(super) fn backup_from_rpc(backup: api_rpc::Backup) -> BackupConfig {
+    BackupConfig {
+        name: backup.name,
+        source: backup.source,
+        target: backup.target_uuid,
+        target_dir: backup.target_dir,
+        interval: backup.interval,
+        keep: backup.keep,
+    }
+}
+
+pub(super) fn backup_status_to_rpc(status: &BackupStatus) -> api_rpc::BackupStatus {
+    let backup = &status.backup;
+    let record = &status.record;
+    api_rpc::BackupStatus {
+        backup: Some(api_rpc::Backup {
+            name: backup.name.clone(),
+            source: backup.source.clone(),
+            target_uuid: backup.target.clone(),
+            target_dir: backup.target_dir.clone(),
+            interval: backup.interval,
+            keep: backup.keep,
+        }),
+        from_config: status.from_config,
+        running: status.running,
+        last_run: record.last_run,
+        last_success: record.last_success,
+        last_error: record.last_error.clone().unwrap_or_default(),
+        last_snapshot: record.last_snapshot.clone(),
+        files_copied: record.files_copied,
+        files_linked: record.files_linked,
+        files_failed: record.files_failed,
+        bytes_copied: record.bytes_copied,
+    }
+}
+
+pub(super) fn share_protocol_from_rpc(protocol: api_rpc::ShareProtocol) -> ShareProtocol {
+    match protocol {
+        api_rpc::ShareProtocol::Smb => ShareProtocol::Smb,
+        api_rpc::ShareProtocol::Nfs => ShareProtocol::Nfs,
+    }
+}
+
+pub(super) fn share_from_rpc(share: api_rpc::DiskShare) -> Option<Share> {
+    let protocol = api_rpc::ShareProtocol::from_i32(share.protocol)?;
+    Some(Share {
+        uuid: share.uuid,
+        protocol: share_protocol_from_rpc(protocol),
+        name: share.name,
+        read_only: share.read_only,
+        clients: share.clients,
+    })
+}
+
+pub(super) fn share_to_rpc(share: &Share, active: bool) -> api_rpc::DiskShare {
+    let protocol = match share.protocol {
+        ShareProtocol::Smb => api_rpc::ShareProtocol::Smb,
+        ShareProtocol::Nfs => api_rpc::ShareProtocol::Nfs,
     };
+    api_rpc::DiskShare {
+        uuid: share.uuid.clone(),
+        protocol: protocol as i32,
+        name: share.name.clone(),
+        read_only: share.read_only,
+        clients: share.clients.clone(),
+        active,
+    }
+}
 
-    Some(disks)
+pub(super) fn file_error_to_status(e: FileError) -> Status {
+    match e {
+        FileError::NotFound(v) => Status::not_found(v),
+        FileError::Denied(v) => Status::permission_denied(v),
+        FileError::Invalid(v) => Status::invalid_argument(v),
+        FileError::Exists(v) => Status::already_exists(v),
+        FileError::Io(v) => Status::internal(v),
+    }
+}
+
+pub(super) fn service_health_to_rpc(health: &ServiceHealth) -> api_rpc::ServiceStatus {
+    use api_rpc::service_status::State;
+    let state = match health.state {
+        HealthState::Running => State::Running,
+        HealthState::Restarting => State::Restarting,
+        HealthState::Stopped => State::Stopped,
+    };
+    api_rpc::ServiceStatus {
+        service: health.service.name().to_owned(),
+        state: state as i32,
+        restarts: health.restarts,
+        last_error: health.last_error.clone().unwrap_or_default(),
+        last_error_time: health.last_error_time,
+    }
+}
+
+pub(super) fn reload_to_rpc(reload: &Reload) -> api_rpc::ReloadConfigResponse {
+    let names = |settings: &[&str]| settings.iter().map(|s| s.to_string()).collect();
+    api_rpc::ReloadConfigResponse {
+        ok: true,
+        reason: String::new(),
+        applied: names(&reload.applied),
+        restart_required: names(&reload.restart_required),
+    }
 }
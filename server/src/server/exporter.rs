@@ -0,0 +1,116 @@
+use crate::metrics::{Kind, MetricsWriter};
+use crate::public::{DiskServiceData, SpinState};
+
+fn flag(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+pub(crate) fn disk_metrics(data: &DiskServiceData, out: &mut MetricsWriter) {
+    out.family("disk_size_bytes", Kind::Gauge, "Size of a disk.");
+    for disk in &data.disks {
+        let labels = [("disk", disk.kernel.as_str()), ("serial", &disk.serial)];
+        out.sample("disk_size_bytes", &labels, disk.size as f64);
+    }
+
+    out.family(
+        "disk_spinning",
+        Kind::Gauge,
+        "1 if a rotational disk spins, 0 if it is in standby.",
+    );
+    for disk in &data.disks {
+        let spinning = match disk.spin_state {
+            SpinState::Active => true,
+            SpinState::Standby => false,
+            SpinState::Unknown => continue,
+        };
+        out.sample("disk_spinning", &[("disk", &disk.kernel)], flag(spinning));
+    }
+
+    let partitions = data
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter().map(move |p| (d, p)));
+    out.family("partition_size_bytes", Kind::Gauge, "Size of a partition.");
+    for (disk, partition) in partitions.clone() {
+        let uuid = partition.uuid.to_string();
+        let labels = [
+            ("partition", partition.kernel.as_str()),
+            ("disk", &disk.kernel),
+            ("uuid", &uuid),
+            ("label", &partition.label),
+        ];
+        out.sample("partition_size_bytes", &labels, partition.size as f64);
+    }
+
+    out.family(
+        "partition_mounted",
+        Kind::Gauge,
+        "1 if a partition is mounted, 0 if not.",
+    );
+    for (_, partition) in partitions {
+        let uuid = partition.uuid.to_string();
+        let labels = [("partition", partition.kernel.as_str()), ("uuid", &uuid)];
+        let mounted = partition.mount_point().is_some();
+        out.sample("partition_mounted", &labels, flag(mounted));
+    }
+
+    out.family(
+        "filesystem_used_bytes",
+        Kind::Gauge,
+        "Bytes used on a mounted filesystem.",
+    );
+    for forecast in &data.forecasts {
+        let labels = [("uuid", forecast.uuid.as_str())];
+        out.sample("filesystem_used_bytes", &labels, forecast.used as f64);
+    }
+
+    out.family(
+        "filesystem_available_bytes",
+        Kind::Gauge,
+        "Bytes available on a mounted filesystem.",
+    );
+    for forecast in &data.forecasts {
+        let labels = [("uuid", forecast.uuid.as_str())];
+        out.sample(
+            "filesystem_available_bytes",
+            &labels,
+            forecast.available as f64,
+        );
+    }
+
+    out.family(
+        "filesystem_days_until_full",
+        Kind::Gauge,
+        "Days until a filesystem is full at its current growth, if it grows.",
+    );
+    for forecast in &data.forecasts {
+        if let Some(days) = forecast.days_until_full {
+            let labels = [("uuid", forecast.uuid.as_str())];
+            out.sample("filesystem_days_until_full", &labels, days);
+        }
+    }
+
+    out.family("raid_size_bytes", Kind::Gauge, "Size of a RAID array.");
+    for raid in &data.raids {
+        let labels = [("array", raid.kernel.as_str()), ("level", &raid.level)];
+        out.sample("raid_size_bytes", &labels, raid.size as f64);
+    }
+
+    out.family(
+        "raid_degraded",
+        Kind::Gauge,
+        "1 if a RAID array is degraded, 0 if not.",
+    );
+    for raid in &data.raids {
+        let labels = [("array", raid.kernel.as_str())];
+        out.sample("raid_degraded", &labels, flag(raid.degraded));
+    }
+}
+
+#[cfg(test)]
+#[path = "./exporter_test.rs"]
+mod exporter_test;
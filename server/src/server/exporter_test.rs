@@ -0,0 +1,84 @@
+use super::*;
+use crate::public::{DiskInfo, FillForecast, Partition, RaidArray};
+use uuid::Uuid;
+
+#[test]
+fn test_disk_metrics() {
+    let uuid = Uuid::from_u128(1);
+    let data = DiskServiceData {
+        disks: vec![DiskInfo {
+            kernel: String::from("sda"),
+            size: 1000,
+            serial: String::from("S1"),
+            spin_state: SpinState::Standby,
+            partitions: vec![
+                Partition {
+                    kernel: String::from("sda1"),
+                    size: 600,
+                    uuid,
+                    label: String::from("photos"),
+                    mount_path: Some(vec![String::from("/mnt/photos")]),
+                },
+                Partition {
+                    kernel: String::from("sda2"),
+                    size: 400,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        raids: vec![RaidArray {
+            kernel: String::from("md0"),
+            level: String::from("raid1"),
+            degraded: true,
+            ..Default::default()
+        }],
+        forecasts: vec![FillForecast {
+            uuid: uuid.to_string(),
+            used: 100,
+            available: 500,
+            days_until_full: None,
+            warning: false,
+        }],
+        ..Default::default()
+    };
+    let mut out = MetricsWriter::default();
+    disk_metrics(&data, &mut out);
+    let out = out.finish();
+    let samples = out
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .collect::<Vec<_>>();
+    let uuid = uuid.to_string();
+    let nil = Uuid::nil().to_string();
+    let expected = vec![
+        String::from("picontrolx_disk_size_bytes{disk=\"sda\",serial=\"S1\"} 1000"),
+        String::from("picontrolx_disk_spinning{disk=\"sda\"} 0"),
+        format!(
+            "picontrolx_partition_size_bytes{{partition=\"sda1\",disk=\"sda\",uuid=\"{}\",label=\"photos\"}} 600",
+            uuid
+        ),
+        format!(
+            "picontrolx_partition_size_bytes{{partition=\"sda2\",disk=\"sda\",uuid=\"{}\",label=\"\"}} 400",
+            nil
+        ),
+        format!(
+            "picontrolx_partition_mounted{{partition=\"sda1\",uuid=\"{}\"}} 1",
+            uuid
+        ),
+        format!(
+            "picontrolx_partition_mounted{{partition=\"sda2\",uuid=\"{}\"}} 0",
+            nil
+        ),
+        format!("picontrolx_filesystem_used_bytes{{uuid=\"{}\"}} 100", uuid),
+        format!(
+            "picontrolx_filesystem_available_bytes{{uuid=\"{}\"}} 500",
+            uuid
+        ),
+        String::from("picontrolx_raid_size_bytes{array=\"md0\",level=\"raid1\"} 0"),
+        String::from("picontrolx_raid_degraded{array=\"md0\"} 1"),
+    ];
+    assert_eq!(samples, expected);
+    // Every family is announced, even without samples.
+    assert!(out.contains("# TYPE picontrolx_filesystem_days_until_full gauge\n"));
+}
@@ -1,54 +1,28 @@
 use log;
-use std::sync::Arc;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::error::SendError;
-use tokio::sync::Mutex;
 
-use crate::caches::{CacheHandler, Handler};
-use crate::public::event_queue::EventQ;
+use crate::public::event_queue::{Event, EventQ};
 use crate::public::shutdown;
-use crate::public::{ServiceData, ServiceType};
-
-#[derive(Clone, Debug)]
-pub(super) struct CacheHandlers {
-    inner: Vec<Option<Arc<Mutex<Handler>>>>,
-}
-
-impl CacheHandlers {
-    fn new() -> Self {
-        let mut caches = Vec::with_capacity(ServiceType::LEN as usize);
-        caches.resize_with(ServiceType::LEN as usize, || None);
-        Self { inner: caches }
-    }
-}
 
 pub(super) struct Fetcher {
     shutdown: shutdown::Receiver,
-    caches_handlers: Arc<Mutex<CacheHandlers>>,
     event_queue: Option<EventQ>,
 }
 
-#[derive(Clone, Debug)]
-pub(super) struct Fetcherhandler {
-    caches_handlers: Arc<Mutex<CacheHandlers>>,
-}
-
 impl Fetcher {
-    pub(super) fn new(shutdown: shutdown::Receiver) -> (Self, Fetcherhandler) {
-        let caches_handlers = Arc::new(Mutex::new(CacheHandlers::new()));
-        let fetcher = Fetcher {
+    pub(super) fn new(shutdown: shutdown::Receiver) -> Self {
+        Fetcher {
             shutdown,
-            caches_handlers: caches_handlers.clone(),
             event_queue: None,
-        };
-        (fetcher, Fetcherhandler { caches_handlers })
+        }
     }
 
     pub(super) fn add_event_queue(&mut self, q: EventQ) {
         self.event_queue = Some(q);
     }
 
-    pub(super) async fn wait_event(&mut self, data_chan: broadcast::Sender<ServiceData>) {
+    pub(super) async fn wait_event(&mut self, data_chan: broadcast::Sender<Event>) {
         if self.event_queue.is_none() {
             log::error!("Fetcher cannot wait event, event_queue is not initialized");
             return;
@@ -77,47 +51,26 @@ impl Fetcher {
         }
     }
 
-    pub(super) async fn add_cache(&mut self, service_type: ServiceType, cache: Handler) {
-        let idx = service_type as usize;
-        self.caches_handlers.lock().await.inner[idx] = Some(Arc::new(Mutex::new(cache)));
-    }
-
-    pub(super) async fn add_caches(&mut self, caches: Vec<(ServiceType, Handler)>) {
-        for v in caches.into_iter() {
-            self.add_cache(v.0, v.1).await;
-        }
-    }
-
     async fn handle_single_event(
         &self,
-        service_type: ServiceType,
-        data_chan: &broadcast::Sender<ServiceData>,
-    ) -> Result<(), SendError<ServiceData>> {
-        let cache = self.caches_handlers.lock().await;
-        let cache = cache.inner[service_type as usize].as_ref();
-        if cache.is_none() {
-            return Ok(());
-        }
-        let cache = cache.unwrap().as_ref().lock().await;
-
-        let (service_type, data) = match &*cache {
-            Handler::Hello(cache) => (cache.get_type(), cache.fetch()),
-            Handler::Disk(cache) => (cache.get_type(), cache.fetch()),
-        };
+        event: Event,
+        data_chan: &broadcast::Sender<Event>,
+    ) -> Result<(), SendError<Event>> {
         log::debug!(
-            "Fetcher handles data in event queue from {:?} = {:?}",
-            service_type,
-            &data
+            "Fetcher handles data in event queue from {:?} #{} = {:?}",
+            event.service_type,
+            event.sequence,
+            &event.data
         );
-        data_chan.send(data.clone())?;
+        data_chan.send(event)?;
 
         Ok(())
     }
 
     async fn handle_events(
         &self,
-        data_chan: broadcast::Sender<ServiceData>,
-    ) -> Result<(), SendError<ServiceData>> {
+        data_chan: broadcast::Sender<Event>,
+    ) -> Result<(), SendError<Event>> {
         log::debug!("Fetcher starts handle events");
 
         if self.event_queue.is_none() {
@@ -126,20 +79,16 @@ impl Fetcher {
 
         let q = self.event_queue.as_ref().unwrap();
         let events = q.drain();
+        log::debug!(
+            "Fetcher handles {} events, queue stats: {:?}",
+            events.len(),
+            q.stats()
+        );
 
-        for e in events.iter() {
-            self.handle_single_event(e.service_type, &data_chan).await?;
+        for e in events.into_iter() {
+            self.handle_single_event(e, &data_chan).await?;
         }
 
         Ok(())
     }
 }
-
-impl Fetcherhandler {
-    pub async fn get_cache_handler(
-        &self,
-        service_type: ServiceType,
-    ) -> Option<Arc<Mutex<Handler>>> {
-        self.caches_handlers.lock().await.inner[service_type as usize].clone()
-    }
-}
@@ -0,0 +1,319 @@
+use futures::future::BoxFuture;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Service};
+use tonic::transport::NamedService;
+
+use crate::caches::Health;
+use crate::metrics::{Histogram, Kind, MetricsWriter, CONTENT_TYPE};
+use crate::public::event_queue::{Event, EventQ};
+use crate::public::shutdown;
+use crate::public::{HealthState, ServiceType};
+use crate::registry::Exporter;
+
+/// gRPC status of calls to unknown methods, they are not timed.
+const UNIMPLEMENTED: &str = "12";
+
+#[derive(Default)]
+struct Inner {
+    /// Open watch streams per service.
+    streams: Mutex<BTreeMap<&'static str, u64>>,
+    /// Events dispatched per service.
+    events: Mutex<BTreeMap<&'static str, u64>>,
+    /// Last event of every service, its data is exported.
+    last: Mutex<BTreeMap<&'static str, Event>>,
+    /// Durations of the RPCs, by method.
+    rpcs: Mutex<BTreeMap<String, Histogram>>,
+}
+
+/// What the server counts for the metrics endpoint.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    inner: Arc<Inner>,
+}
+
+impl Metrics {
+    /// Count a watch stream of `service` as open until the guard is dropped.
+    pub(crate) fn stream_opened(&self, service: ServiceType) -> StreamGuard {
+        *self
+            .inner
+            .streams
+            .lock()
+            .unwrap()
+            .entry(service.name())
+            .or_default() += 1;
+        StreamGuard {
+            metrics: self.clone(),
+            service,
+        }
+    }
+
+    pub(crate) fn event_dispatched(&self, event: &Event) {
+        let service = event.service_type.name();
+        *self
+            .inner
+            .events
+            .lock()
+            .unwrap()
+            .entry(service)
+            .or_default() += 1;
+        self.inner
+            .last
+            .lock()
+            .unwrap()
+            .insert(service, event.clone());
+    }
+
+    fn rpc_done(&self, method: &str, started: Instant) {
+        let mut rpcs = self.inner.rpcs.lock().unwrap();
+        let histogram = rpcs.entry(method.to_owned()).or_default();
+        histogram.observe(started.elapsed());
+    }
+}
+
+/// An open watch stream, counted until dropped.
+pub(crate) struct StreamGuard {
+    metrics: Metrics,
+    service: ServiceType,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut streams = self.metrics.inner.streams.lock().unwrap();
+        if let Some(open) = streams.get_mut(self.service.name()) {
+            *open -= 1;
+        }
+    }
+}
+
+/// A gRPC service whose calls are timed.
+#[derive(Clone)]
+pub(crate) struct Timed<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S> Timed<S> {
+    pub(crate) fn new(inner: S, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<S> Service<http::Request<Body>> for Timed<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        // The path is /package.Service/Method.
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+        let method = method.to_owned();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            if response.headers().get("grpc-status").map(|s| s.as_bytes())
+                != Some(UNIMPLEMENTED.as_bytes())
+            {
+                // Streams are timed until their headers are sent.
+                metrics.rpc_done(&method, started);
+            }
+            Ok(response)
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for Timed<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Everything the metrics endpoint reports, rendered on every scrape.
+pub(crate) struct Report {
+    pub(crate) metrics: Metrics,
+    pub(crate) event_q: EventQ,
+    pub(crate) health: Health,
+    pub(crate) refresh_durations: Vec<(ServiceType, Histogram)>,
+    pub(crate) exporters: HashMap<ServiceType, Exporter>,
+}
+
+impl Report {
+    pub(crate) fn render(&self) -> String {
+        let mut out = MetricsWriter::default();
+        let inner = &self.metrics.inner;
+
+        out.family("watch_streams", Kind::Gauge, "Open watch streams.");
+        for (service, open) in inner.streams.lock().unwrap().iter() {
+            out.sample("watch_streams", &[("service", service)], *open as f64);
+        }
+
+        out.family(
+            "events_total",
+            Kind::Counter,
+            "Events of a service dispatched to watchers.",
+        );
+        for (service, events) in inner.events.lock().unwrap().iter() {
+            out.sample("events_total", &[("service", service)], *events as f64);
+        }
+
+        let stats = self.event_q.stats();
+        let queue = [
+            (
+                "event_queue_depth",
+                Kind::Gauge,
+                "Events waiting to be dispatched.",
+                self.event_q.depth() as u64,
+            ),
+            (
+                "event_queue_max_depth",
+                Kind::Gauge,
+                "Most events ever waiting at once.",
+                stats.max_depth as u64,
+            ),
+            (
+                "event_queue_pushed_total",
+                Kind::Counter,
+                "Events published by the caches.",
+                stats.pushed,
+            ),
+            (
+                "event_queue_coalesced_total",
+                Kind::Counter,
+                "Events replaced by a newer one of the same service before dispatch.",
+                stats.coalesced,
+            ),
+            (
+                "event_queue_dropped_total",
+                Kind::Counter,
+                "Events lost because the queue was full.",
+                stats.dropped,
+            ),
+        ];
+        for (name, kind, help, value) in queue.iter() {
+            out.family(name, *kind, help);
+            out.sample(name, &[], *value as f64);
+        }
+
+        out.family(
+            "rpc_duration_seconds",
+            Kind::Histogram,
+            "Time to answer an RPC, until the first message of a stream.",
+        );
+        for (method, histogram) in inner.rpcs.lock().unwrap().iter() {
+            out.histogram("rpc_duration_seconds", &[("method", method)], histogram);
+        }
+
+        out.family(
+            "cache_refresh_duration_seconds",
+            Kind::Histogram,
+            "Time a cache takes to refresh its data.",
+        );
+        for (service, histogram) in &self.refresh_durations {
+            let labels = [("service", service.name())];
+            out.histogram("cache_refresh_duration_seconds", &labels, histogram);
+        }
+
+        let health = self.health.list();
+        out.family(
+            "cache_running",
+            Kind::Gauge,
+            "1 if the task of a cache runs, 0 if it is stopped or restarting.",
+        );
+        for h in &health {
+            let running = f64::from(u8::from(h.state == HealthState::Running));
+            out.sample("cache_running", &[("service", h.service.name())], running);
+        }
+        out.family(
+            "cache_failures_total",
+            Kind::Counter,
+            "Failures of the task of a cache, each followed by a restart.",
+        );
+        for h in &health {
+            let labels = [("service", h.service.name())];
+            out.sample("cache_failures_total", &labels, f64::from(h.restarts));
+        }
+        out.family(
+            "cache_last_failure_timestamp_seconds",
+            Kind::Gauge,
+            "Unix time of the last failure of the task of a cache.",
+        );
+        for h in health.iter().filter(|h| h.last_error_time > 0) {
+            let labels = [("service", h.service.name())];
+            let time = h.last_error_time as f64;
+            out.sample("cache_last_failure_timestamp_seconds", &labels, time);
+        }
+
+        for event in inner.last.lock().unwrap().values() {
+            if let Some(export) = self.exporters.get(&event.service_type) {
+                export(event, &mut out);
+            }
+        }
+        out.finish()
+    }
+}
+
+fn respond(report: &Report, request: &Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            *response.body_mut() = Body::from(report.render());
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static(CONTENT_TYPE),
+            );
+        }
+        (_, "/metrics") => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+        _ => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+    response
+}
+
+/// Serve `report` over HTTP at `addr`/metrics until shutdown.
+pub(crate) async fn serve(addr: SocketAddr, report: Report, mut shutdown: shutdown::Receiver) {
+    let report = Arc::new(report);
+    let make_service = make_service_fn(move |_| {
+        let report = report.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&report, &request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            log::error!("Cannot serve metrics on {}: {}", addr, e);
+            return;
+        }
+    };
+    log::warn!("Metrics are served on http://{}/metrics", addr);
+    let result = server
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.wait_on().await })
+        .await;
+    if let Err(e) = result {
+        log::error!("Metrics server failed: {}", e);
+    }
+    log::warn!("Metrics server is shut down");
+}
+
+#[cfg(test)]
+#[path = "./metrics_test.rs"]
+mod metrics_test;
@@ -0,0 +1,93 @@
+use super::*;
+use crate::metrics::MetricsWriter;
+use std::sync::Arc;
+use std::time::Duration;
+
+const THIS_TYPE: ServiceType = ServiceType::new("test");
+
+fn report(metrics: &Metrics, event_q: &EventQ) -> Report {
+    let export: Exporter = Box::new(|event: &Event, out: &mut MetricsWriter| {
+        let value = event.data.downcast_ref::<u32>().unwrap();
+        out.family("test_value", Kind::Gauge, "Value of the test service.");
+        out.sample("test_value", &[], f64::from(*value));
+    });
+    let mut exporters = HashMap::new();
+    exporters.insert(THIS_TYPE, export);
+    let refreshes = Histogram::default();
+    refreshes.observe(Duration::from_millis(20));
+    Report {
+        metrics: metrics.clone(),
+        event_q: event_q.clone(),
+        health: Health::default(),
+        refresh_durations: vec![(THIS_TYPE, refreshes)],
+        exporters,
+    }
+}
+
+fn sample(out: &str, name: &str) -> Option<String> {
+    let line = out.lines().find(|l| l.starts_with(name))?;
+    Some(line[name.len()..].trim().to_owned())
+}
+
+#[test]
+fn test_render() {
+    let metrics = Metrics::default();
+    let event_q = EventQ::new();
+    let report = report(&metrics, &event_q);
+
+    let first = metrics.stream_opened(THIS_TYPE);
+    let second = metrics.stream_opened(THIS_TYPE);
+    drop(first);
+    event_q.get_notifier().push(THIS_TYPE, || Arc::new(5u32));
+    event_q.get_notifier().push(THIS_TYPE, || Arc::new(6u32));
+    for event in event_q.drain() {
+        metrics.event_dispatched(&event);
+    }
+    event_q
+        .get_notifier()
+        .push(ServiceType::new("other"), || Arc::new(7u32));
+
+    let out = report.render();
+    let streams = "picontrolx_watch_streams{service=\"test\"}";
+    assert_eq!(sample(&out, streams).as_deref(), Some("1"));
+    let events = "picontrolx_events_total{service=\"test\"}";
+    assert_eq!(sample(&out, events).as_deref(), Some("1"));
+    let depth = "picontrolx_event_queue_depth ";
+    assert_eq!(sample(&out, depth).as_deref(), Some("1"));
+    let coalesced = "picontrolx_event_queue_coalesced_total";
+    assert_eq!(sample(&out, coalesced).as_deref(), Some("1"));
+    let refreshes = "picontrolx_cache_refresh_duration_seconds_count{service=\"test\"}";
+    assert_eq!(sample(&out, refreshes).as_deref(), Some("1"));
+    // Data of the last event only, the other service has no exporter.
+    assert_eq!(sample(&out, "picontrolx_test_value").as_deref(), Some("6"));
+
+    drop(second);
+    let out = report.render();
+    assert_eq!(sample(&out, streams).as_deref(), Some("0"));
+    assert_eq!(out.matches("# TYPE picontrolx_test_value").count(), 1);
+}
+
+#[test]
+fn test_respond() {
+    let report = report(&Metrics::default(), &EventQ::new());
+    let request = |method: Method, path: &str| {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        respond(&report, &request)
+    };
+
+    let response = request(Method::GET, "/metrics");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[hyper::header::CONTENT_TYPE],
+        CONTENT_TYPE
+    );
+    assert_eq!(
+        request(Method::POST, "/metrics").status(),
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(request(Method::GET, "/").status(), StatusCode::NOT_FOUND);
+}
@@ -1,7 +1,9 @@
 pub(crate) mod fetcher;
 pub(crate) mod server;
 
-pub(self) mod converter;
+pub(crate) mod converter;
+pub(crate) mod exporter;
+pub(crate) mod metrics;
 pub(self) mod api_rpc {
     tonic::include_proto!("api");
 }
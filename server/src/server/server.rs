@@ -1,206 +1,422 @@
-use futures::FutureExt;
+use futures::future::OptionFuture;
 use futures::Stream;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::broadcast::error::RecvError;
-use tokio::sync::{broadcast, oneshot};
+use tokio::sync::Mutex as TokioMutex;
+use tokio::sync::{broadcast, watch};
 use tonic::transport::Server as TonicServer;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
 use super::api_rpc;
 use super::api_rpc::api_server;
 use super::converter;
-use super::fetcher::{Fetcher, Fetcherhandler};
-use crate::caches::Handler;
-use crate::public::event_queue::EventQ;
+use super::fetcher::Fetcher;
+use super::metrics::{self, Metrics, Report, Timed};
+use crate::caches::{
+    DiskCacheHandler, FileSandbox, Health, HelloCacheHandler, Refreshers, DISK_SERVICE,
+};
+use crate::logging::{LogHandle, TEMPORARY_LEVELS_DURATION};
+use crate::metrics::Histogram;
+use crate::public::event_queue::{Event, EventQ};
 use crate::public::shutdown;
-use crate::public::{ServiceData, ServiceType};
+use crate::public::{FileOpRequest, HealthState, ServiceType};
+use crate::registry::{Converter, Exporter, Handlers, WatchMessage, Watchable};
+use crate::reload::Reloader;
 
 pub(crate) struct ServerHandler {
-    shutdown: shutdown::Sender,
+    accept: shutdown::Sender,
+    streams: shutdown::Sender,
+    fetcher: shutdown::Sender,
 }
 
 impl ServerHandler {
+    /// Stop accepting RPCs and scrapes, end open streams, telling watchers
+    /// the server goes away, then stop forwarding events.
     pub async fn shutdown(&self) {
-        self.shutdown.shutdown().await;
+        log::warn!("GRPC server stops accepting RPCs...");
+        self.accept.signal();
+        log::warn!("GRPC server ends open streams...");
+        self.streams.shutdown().await;
+        log::warn!("Server stops forwarding events...");
+        self.fetcher.shutdown().await;
     }
 }
 
-#[derive(Clone, Debug)]
-enum GrpcData {
-    None,
-    Disk(api_rpc::DiskListAndWatchResponse),
+/// Watchers of a service: how its state is converted, the last message and
+/// the channel of the next ones.
+struct Watch {
+    convert: Converter,
+    last: RwLock<Option<WatchMessage>>,
+    chan: broadcast::Sender<WatchMessage>,
 }
 
-#[derive(Clone)]
-struct GrpcDataCache {
-    inner: Arc<RwLock<Vec<GrpcData>>>,
+#[derive(Clone, Default)]
+struct Watches {
+    inner: Arc<HashMap<ServiceType, Watch>>,
+    metrics: Metrics,
 }
 
-impl GrpcDataCache {
-    fn new() -> Self {
-        let mut v = Vec::new();
-        v.resize(ServiceType::LEN as usize, GrpcData::None);
-
+impl Watches {
+    fn new(converters: HashMap<ServiceType, Converter>, metrics: Metrics) -> Self {
+        let inner = converters
+            .into_iter()
+            .map(|(service_type, convert)| {
+                let (chan, _) = broadcast::channel(2);
+                let watch = Watch {
+                    convert,
+                    last: RwLock::new(None),
+                    chan,
+                };
+                (service_type, watch)
+            })
+            .collect();
         Self {
-            inner: Arc::new(RwLock::new(v)),
+            inner: Arc::new(inner),
+            metrics,
         }
     }
 
-    fn update(&self, service_type: ServiceType, data: GrpcData) {
-        let mut guard = self.inner.write().unwrap();
-        guard[service_type as usize] = data;
+    /// Convert the state of a service and send it to its watchers.
+    fn publish(&self, event: &Event) {
+        let watch = match self.inner.get(&event.service_type) {
+            Some(watch) => watch,
+            None => return,
+        };
+        let message = match (watch.convert)(event) {
+            Some(message) => message,
+            None => {
+                log::warn!("Cannot convert data of {:?}", event.service_type);
+                return;
+            }
+        };
+
+        *watch.last.write().unwrap() = Some(message.clone());
+        if watch.chan.send(message).is_err() {
+            log::debug!("No watcher of {:?}", event.service_type);
+        }
     }
 
-    fn get(&self, service_type: ServiceType) -> GrpcData {
-        let guard = self.inner.read().unwrap();
-        guard[service_type as usize].clone()
+    /// Stream the last message of a service, then every new one. None if
+    /// the service cannot be watched.
+    fn stream<M>(
+        &self,
+        service_type: ServiceType,
+        mut shutdown: shutdown::Receiver,
+    ) -> Option<impl Stream<Item = Result<M, Status>>>
+    where
+        M: Watchable + Clone + Send + Sync + 'static,
+    {
+        let watch = self.inner.get(&service_type)?;
+        let mut chan = watch.chan.subscribe();
+        let last = watch.last.read().unwrap().clone();
+        let open = self.metrics.stream_opened(service_type);
+
+        Some(async_stream::try_stream! {
+            let _open = open;
+            if let Some(message) = last.as_ref().and_then(|m| m.downcast_ref::<M>()) {
+                yield message.clone();
+            }
+            loop {
+                tokio::select! {
+                    v = chan.recv() => {
+                        match v {
+                            Ok(message) => {
+                                if let Some(message) = message.downcast_ref::<M>() {
+                                    yield message.clone();
+                                }
+                            }
+                            // Messages carry the whole state, the next one
+                            // catches up.
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => break,
+                        }
+                    }
+                    _ = shutdown.wait_on() => {
+                        yield M::going_away();
+                        break;
+                    }
+                }
+            }
+        })
     }
 }
 
 pub(crate) struct Server {
-    shutdown: shutdown::Receiver,
+    accept: shutdown::Receiver,
+    streams: shutdown::Receiver,
     addr: SocketAddr,
     fetcher: Fetcher,
-    fetcher_handler: Fetcherhandler,
-    grpc_data_cache: GrpcDataCache,
+    handlers: Handlers,
+    watches: Watches,
+    health: Health,
+    refreshers: Refreshers,
+    reloader: Option<Reloader>,
+    log: Option<LogHandle>,
+    metrics: Metrics,
+    metrics_addr: Option<SocketAddr>,
+    exporters: HashMap<ServiceType, Exporter>,
+    refresh_durations: Vec<(ServiceType, Histogram)>,
 }
 
 impl Server {
     pub fn new(addr: SocketAddr) -> (Self, ServerHandler) {
-        let (s, r) = shutdown::new();
-        let (fetcher, fetcher_handler) = Fetcher::new(r.clone());
+        let (accept_tx, accept) = shutdown::new();
+        let (streams_tx, streams) = shutdown::new();
+        let (fetcher_tx, fetcher_rx) = shutdown::new();
         (
             Server {
-                shutdown: r,
+                accept,
+                streams,
                 addr,
-                fetcher,
-                fetcher_handler,
-                grpc_data_cache: GrpcDataCache::new(),
+                fetcher: Fetcher::new(fetcher_rx),
+                handlers: Handlers::default(),
+                watches: Watches::default(),
+                health: Health::default(),
+                refreshers: Refreshers::default(),
+                reloader: None,
+                log: None,
+                metrics: Metrics::default(),
+                metrics_addr: None,
+                exporters: HashMap::new(),
+                refresh_durations: Vec::new(),
+            },
+            ServerHandler {
+                accept: accept_tx,
+                streams: streams_tx,
+                fetcher: fetcher_tx,
             },
-            ServerHandler { shutdown: s },
         )
     }
 
-    pub async fn serve(&mut self, event_q: EventQ) {
+    /// Serve until the fetcher is shut down. The server is consumed, so no
+    /// shutdown receiver outlives the phase it belongs to.
+    pub async fn serve(self, event_q: EventQ) {
+        let Server {
+            mut accept,
+            streams,
+            addr,
+            mut fetcher,
+            handlers,
+            watches,
+            health,
+            refreshers,
+            reloader,
+            log,
+            metrics,
+            metrics_addr,
+            exporters,
+            refresh_durations,
+        } = self;
         let (chan_tx, mut chan_rx) = broadcast::channel(2);
-        let mut data_chans = Vec::with_capacity(ServiceType::LEN as usize);
-        data_chans.resize_with(ServiceType::LEN as usize, || {
-            let (s, _) = broadcast::channel(2);
-            s
+
+        let metrics_handler = metrics_addr.map(|addr| {
+            let report = Report {
+                metrics: metrics.clone(),
+                event_q: event_q.clone(),
+                health: health.clone(),
+                refresh_durations,
+                exporters,
+            };
+            tokio::spawn(metrics::serve(addr, report, accept.clone()))
         });
 
         let service = GrpcService::new(
-            self.shutdown.clone(),
-            data_chans.clone(),
-            self.grpc_data_cache.clone(),
-            self.fetcher_handler.clone(),
+            streams,
+            handlers,
+            watches.clone(),
+            health,
+            refreshers,
+            reloader,
+            log,
         );
-        let grpc_server = TonicServer::builder().add_service(api_server::ApiServer::new(service));
-        let (tx, rx) = oneshot::channel::<()>();
+        let service = Timed::new(api_server::ApiServer::new(service), metrics.clone());
+        let grpc_server = TonicServer::builder().add_service(service);
 
-        self.fetcher.add_event_queue(event_q);
+        fetcher.add_event_queue(event_q);
 
-        let addr = self.addr.clone();
         let handler = tokio::spawn(async move {
-            grpc_server
-                .serve_with_shutdown(addr, rx.map(|_| ()))
-                .await
-                .unwrap();
+            let result = grpc_server
+                .serve_with_shutdown(addr, async move { accept.wait_on().await })
+                .await;
+            if let Err(e) = result {
+                log::error!("GRPC server failed: {}", e);
+            }
+            log::warn!("GRPC server is shut down");
         });
 
-        let mut shutdown = self.shutdown.clone();
-        let grpc_data_cache = self.grpc_data_cache.clone();
+        // Ends once the fetcher drops its sender.
         tokio::spawn(async move {
             loop {
-                tokio::select! {
-                    _ = shutdown.wait_on() => {
+                match chan_rx.recv().await {
+                    Ok(event) => {
+                        metrics.event_dispatched(&event);
+                        watches.publish(&event);
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        log::error!("Server dispatcher lagged, {} events are lost", n)
+                    }
+                    Err(RecvError::Closed) => {
                         log::warn!("Server is shutting down...");
                         break;
                     }
-                    v = chan_rx.recv() => {
-                        Self::handle_new_data(&v, &data_chans, &grpc_data_cache);
-                    }
                 }
             }
         });
 
-        self.fetcher.wait_event(chan_tx).await;
-        log::warn!("GRPC server is shutting down...");
-        tx.send(()).unwrap(); // shutting down server
-        handler.await.unwrap();
+        fetcher.wait_event(chan_tx).await;
+        drop(fetcher);
+        if let Err(e) = handler.await {
+            log::error!("GRPC server task failed: {}", e);
+        }
+        if let Some(Err(e)) = OptionFuture::from(metrics_handler).await {
+            log::error!("Metrics server task failed: {}", e);
+        }
     }
 
-    fn handle_new_data(
-        data: &Result<ServiceData, RecvError>,
-        data_chans: &Vec<broadcast::Sender<GrpcData>>,
-        data_cache: &GrpcDataCache,
+    /// Serve the handlers of the registered services, let clients watch
+    /// them and export their state as metrics.
+    pub fn add_services(
+        &mut self,
+        handlers: Handlers,
+        converters: HashMap<ServiceType, Converter>,
+        exporters: HashMap<ServiceType, Exporter>,
     ) {
-        if let Err(e) = data {
-            log::error!("Server dispatcher read channel failed: {:?}", e);
-            return;
-        }
+        self.handlers = handlers;
+        self.watches = Watches::new(converters, self.metrics.clone());
+        self.exporters = exporters;
+    }
 
-        let v = data.as_ref().unwrap();
-        let (service_type, grpc_data) = match v {
-            ServiceData::Preserved(data) => {
-                if let Some(response) = converter::preserved_to_disk_list_and_watch_response(data) {
-                    (ServiceType::DISK, GrpcData::Disk(response.clone()))
-                } else {
-                    return;
-                }
-            }
-            ServiceData::Disk(disks) => {
-                if let Some(response) = converter::data_to_disk_list_and_watch_response(disks) {
-                    (ServiceType::DISK, GrpcData::Disk(response.clone()))
-                } else {
-                    log::warn!("ServiceData is DISK, but cannot convert to response");
-                    return;
-                }
-            }
-            _ => return,
-        };
+    /// Report the health of the supervised cache tasks.
+    pub fn add_health(&mut self, health: Health) {
+        self.health = health;
+    }
 
-        data_cache.update(service_type, grpc_data.clone());
-        let sender = &data_chans[ServiceType::DISK as usize];
-        if let Err(_) = sender.send(grpc_data) {
-            log::warn!("Server dispatcher cannot send GRPC data")
-        }
+    /// Let clients refresh the services on demand.
+    pub fn add_refreshers(&mut self, refreshers: Refreshers) {
+        self.refreshers = refreshers;
+    }
+
+    /// Let clients reload the configuration.
+    pub fn add_reloader(&mut self, reloader: Reloader) {
+        self.reloader = Some(reloader);
     }
 
-    pub async fn add_caches(&mut self, caches: Vec<(ServiceType, Handler)>) {
-        self.fetcher.add_caches(caches).await;
+    /// Let clients change the log levels for a while.
+    pub fn add_log_handle(&mut self, log: LogHandle) {
+        self.log = Some(log);
+    }
+
+    /// Serve metrics over HTTP at `addr`, with how long the refreshes of the
+    /// caches took.
+    pub fn add_metrics(
+        &mut self,
+        addr: SocketAddr,
+        refresh_durations: Vec<(ServiceType, Histogram)>,
+    ) {
+        self.metrics_addr = Some(addr);
+        self.refresh_durations = refresh_durations;
     }
 }
 
 struct GrpcService {
     shutdown: shutdown::Receiver,
-    data_chans: Vec<broadcast::Sender<GrpcData>>,
-    cache: GrpcDataCache,
-    fetcher_handler: Fetcherhandler,
+    handlers: Handlers,
+    watches: Watches,
+    health: Health,
+    refreshers: Refreshers,
+    reloader: Option<Reloader>,
+    log: Option<LogHandle>,
 }
 
 impl GrpcService {
     fn new(
         shutdown: shutdown::Receiver,
-        data_chans: Vec<broadcast::Sender<GrpcData>>,
-        grpc_data_cache: GrpcDataCache,
-        fetcher_handler: Fetcherhandler,
+        handlers: Handlers,
+        watches: Watches,
+        health: Health,
+        refreshers: Refreshers,
+        reloader: Option<Reloader>,
+        log: Option<LogHandle>,
     ) -> Self {
-        let mut last_data = Vec::with_capacity(ServiceType::LEN as usize);
-        last_data.resize_with(ServiceType::LEN as usize, || ServiceData::None);
-
         Self {
             shutdown,
-            data_chans,
-            cache: grpc_data_cache,
-            fetcher_handler,
+            handlers,
+            watches,
+            health,
+            refreshers,
+            reloader,
+            log,
         }
     }
 }
 
+/// Stream every update of a job's progress until the job is done.
+fn job_progress_stream<T, R>(
+    mut progress: watch::Receiver<T>,
+    mut shutdown: shutdown::Receiver,
+    convert: fn(&T) -> R,
+    is_done: fn(&T) -> bool,
+) -> impl Stream<Item = Result<R, Status>>
+where
+    T: Clone + Send + Sync + 'static,
+    R: Send + 'static,
+{
+    async_stream::try_stream! {
+        loop {
+            let current = progress.borrow_and_update().clone();
+            yield convert(&current);
+            if is_done(&current) {
+                break;
+            }
+            tokio::select! {
+                v = progress.changed() => {
+                    if v.is_err() {
+                        break;
+                    }
+                }
+                _ = shutdown.wait_on() => {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+const FILE_CHUNK_SIZE: usize = 64 << 10;
+const FILE_PAGE_SIZE: usize = 100;
+
+/// Run blocking file system work off the async runtime.
+async fn blocking<T, F>(f: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, crate::public::FileError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(converter::file_error_to_status)
+}
+
+impl GrpcService {
+    async fn file_sandbox(&self, uuid: &str) -> Result<FileSandbox, Status> {
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let sandbox = handler.lock().await.file_sandbox(uuid);
+        sandbox.map_err(converter::file_error_to_status)
+    }
+
+    async fn disk_handler(&self) -> Result<Arc<TokioMutex<DiskCacheHandler>>, String> {
+        self.handlers
+            .get::<DiskCacheHandler>()
+            .ok_or_else(|| String::from("No cache handler"))
+    }
+}
+
 #[tonic::async_trait]
 impl api_server::Api for GrpcService {
     type DiskListAndWatchStream = Pin<
@@ -212,6 +428,57 @@ impl api_server::Api for GrpcService {
         >,
     >;
 
+    type DiskSurfaceScanStream = Pin<
+        Box<
+            dyn Stream<Item = Result<api_rpc::DiskSurfaceScanProgress, Status>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >;
+
+    type DiskBenchmarkStream = Pin<
+        Box<
+            dyn Stream<Item = Result<api_rpc::DiskBenchmarkProgress, Status>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >;
+
+    type FileOperationStream = Pin<
+        Box<
+            dyn Stream<Item = Result<api_rpc::FileOperationProgress, Status>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >;
+
+    type DiskImageBackupStream = Pin<
+        Box<dyn Stream<Item = Result<api_rpc::DiskImageProgress, Status>> + Send + Sync + 'static>,
+    >;
+
+    type DiskImageVerifyStream = Pin<
+        Box<dyn Stream<Item = Result<api_rpc::DiskImageProgress, Status>> + Send + Sync + 'static>,
+    >;
+
+    type DiskUsageAnalyzeStream = Pin<
+        Box<dyn Stream<Item = Result<api_rpc::DiskUsageReport, Status>> + Send + Sync + 'static>,
+    >;
+
+    type DuplicateFindStream = Pin<
+        Box<
+            dyn Stream<Item = Result<api_rpc::DuplicateFindProgress, Status>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >;
+
+    type FileDownloadStream =
+        Pin<Box<dyn Stream<Item = Result<api_rpc::FileChunk, Status>> + Send + Sync + 'static>>;
+
     async fn disk_list_and_watch(
         &self,
         request: Request<api_rpc::DiskListAndWatchRequest>,
@@ -221,28 +488,10 @@ impl api_server::Api for GrpcService {
             request.remote_addr()
         );
 
-        const THIS_TYPE: ServiceType = ServiceType::DISK;
-        let mut this_chan = self.data_chans[THIS_TYPE as usize].subscribe();
-        let mut shutdown = self.shutdown.clone();
-        let d = self.cache.get(THIS_TYPE);
-
-        let output = async_stream::try_stream! {
-            if let GrpcData::Disk(disks) = d {
-                yield disks;
-            }
-            loop {
-                tokio::select! {
-                    Ok(v) = this_chan.recv() => {
-                        if let GrpcData::Disk(disks) = v {
-                                yield disks;
-                        }
-                    }
-                    _ = shutdown.wait_on() => {
-                        break;
-                    }
-                }
-            }
-        };
+        let output = self
+            .watches
+            .stream::<api_rpc::DiskListAndWatchResponse>(DISK_SERVICE, self.shutdown.clone())
+            .ok_or_else(|| Status::unavailable("Disks cannot be watched"))?;
 
         Ok(Response::new(
             Box::pin(output) as Self::DiskListAndWatchStream
@@ -253,9 +502,8 @@ impl api_server::Api for GrpcService {
         &self,
         request: Request<api_rpc::DiskMountRequest>,
     ) -> Result<Response<api_rpc::DiskMountResponse>, Status> {
-        const THIS_TYPE: ServiceType = ServiceType::DISK;
-
-        let handler = self.fetcher_handler.get_cache_handler(THIS_TYPE).await;
+        // TODO: Change hello to disk
+        let handler = self.handlers.get::<HelloCacheHandler>();
         // dbg!(&handler);
         if handler.is_none() {
             return Ok(Response::new(api_rpc::DiskMountResponse {
@@ -266,23 +514,788 @@ impl api_server::Api for GrpcService {
         }
 
         let handler = handler.unwrap();
-        let handler = handler.lock().await;
+        let disk_handler = handler.lock().await;
+        log::info!("-=-=--=-=-=-1=1=-1=1-=-1");
+        disk_handler.disk_mount();
+        Ok(Response::new(api_rpc::DiskMountResponse {
+            ok: true,
+            uuid: "123-456-7890".into(),
+            reason: "".into(),
+        }))
+    }
 
-        // TODO: Change hello to disk
-        if let Handler::Hello(disk_handler) = &*handler {
-            log::info!("-=-=--=-=-=-1=1=-1=1-=-1");
-            disk_handler.disk_mount();
-            return Ok(Response::new(api_rpc::DiskMountResponse {
+    async fn disk_set_nickname(
+        &self,
+        request: Request<api_rpc::DiskSetNicknameRequest>,
+    ) -> Result<Response<api_rpc::DiskSetNicknameResponse>, Status> {
+        let request = request.into_inner();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => {
+                handler
+                    .lock()
+                    .await
+                    .set_nickname(&request.uuid, &request.nickname, &request.notes)
+            }
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::DiskSetNicknameResponse {
+                ok: true,
+                uuid: request.uuid,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::DiskSetNicknameResponse {
+                ok: false,
+                uuid: request.uuid,
+                reason,
+            },
+        }))
+    }
+
+    async fn disk_list_known(
+        &self,
+        request: Request<api_rpc::DiskListKnownRequest>,
+    ) -> Result<Response<api_rpc::DiskListKnownResponse>, Status> {
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let disk_handler = handler.lock().await;
+
+        let absent = disk_handler.absent_disks();
+        let disks = if request.get_ref().absent_only {
+            absent
+                .iter()
+                .map(|d| converter::known_disk_to_rpc(d, false))
+                .collect()
+        } else {
+            disk_handler
+                .known_disks()
+                .iter()
+                .map(|d| {
+                    let present = !absent.iter().any(|a| a.uuid == d.uuid);
+                    converter::known_disk_to_rpc(d, present)
+                })
+                .collect()
+        };
+
+        Ok(Response::new(api_rpc::DiskListKnownResponse { disks }))
+    }
+
+    async fn disk_trim(
+        &self,
+        request: Request<api_rpc::DiskTrimRequest>,
+    ) -> Result<Response<api_rpc::DiskTrimResponse>, Status> {
+        let uuid = Some(request.into_inner().uuid).filter(|uuid| !uuid.is_empty());
+
+        // Trim can take a while, do not hold the handler lock meanwhile.
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let trim = handler.lock().await.trim(uuid);
+
+        let results = trim
+            .await
+            .iter()
+            .map(converter::trim_result_to_rpc)
+            .collect();
+        Ok(Response::new(api_rpc::DiskTrimResponse { results }))
+    }
+
+    async fn disk_standby(
+        &self,
+        request: Request<api_rpc::DiskStandbyRequest>,
+    ) -> Result<Response<api_rpc::DiskStandbyResponse>, Status> {
+        let uuid = request.into_inner().uuid;
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => {
+                let standby = handler.lock().await.standby(uuid.clone());
+                standby.await
+            }
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::DiskStandbyResponse {
+                ok: true,
+                uuid,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::DiskStandbyResponse {
+                ok: false,
+                uuid,
+                reason,
+            },
+        }))
+    }
+
+    async fn disk_surface_scan(
+        &self,
+        request: Request<api_rpc::DiskSurfaceScanRequest>,
+    ) -> Result<Response<Self::DiskSurfaceScanStream>, Status> {
+        let name = request.into_inner().name;
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler.lock().await.surface_scan(&name);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::scan_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(
+            Box::pin(output) as Self::DiskSurfaceScanStream
+        ))
+    }
+
+    async fn disk_benchmark(
+        &self,
+        request: Request<api_rpc::DiskBenchmarkRequest>,
+    ) -> Result<Response<Self::DiskBenchmarkStream>, Status> {
+        let request = request.into_inner();
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler.lock().await.benchmark(&request.uuid, request.size);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::bench_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(Box::pin(output) as Self::DiskBenchmarkStream))
+    }
+
+    async fn job_control(
+        &self,
+        request: Request<api_rpc::JobControlRequest>,
+    ) -> Result<Response<api_rpc::JobControlResponse>, Status> {
+        use api_rpc::job_control_request::Action;
+
+        let request = request.into_inner();
+        let job_id = request.job_id;
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => {
+                let disk_handler = handler.lock().await;
+                match Action::from_i32(request.action) {
+                    Some(Action::Pause) => disk_handler.pause_job(job_id),
+                    Some(Action::Resume) => disk_handler.resume_job(job_id),
+                    Some(Action::Cancel) => disk_handler.cancel_job(job_id),
+                    None => Err(format!("Unknown action {}", request.action)),
+                }
+            }
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::JobControlResponse {
+                ok: true,
+                job_id,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::JobControlResponse {
+                ok: false,
+                job_id,
+                reason,
+            },
+        }))
+    }
+
+    async fn file_list(
+        &self,
+        request: Request<api_rpc::FileListRequest>,
+    ) -> Result<Response<api_rpc::FileListResponse>, Status> {
+        let request = request.into_inner();
+        let sandbox = self.file_sandbox(&request.uuid).await?;
+        let read_only = sandbox.read_only();
+        let offset = request.page_token as usize;
+        let limit = match request.page_size {
+            0 => FILE_PAGE_SIZE,
+            v => v as usize,
+        };
+
+        let (entries, next) = blocking(move || sandbox.list(&request.path, offset, limit)).await?;
+        Ok(Response::new(api_rpc::FileListResponse {
+            entries: entries.iter().map(converter::file_entry_to_rpc).collect(),
+            next_page_token: next.unwrap_or(0) as u64,
+            read_only,
+        }))
+    }
+
+    async fn file_stat(
+        &self,
+        request: Request<api_rpc::FileStatRequest>,
+    ) -> Result<Response<api_rpc::FileInfo>, Status> {
+        let request = request.into_inner();
+        let sandbox = self.file_sandbox(&request.uuid).await?;
+
+        let entry = blocking(move || sandbox.stat(&request.path)).await?;
+        Ok(Response::new(converter::file_entry_to_rpc(&entry)))
+    }
+
+    async fn file_download(
+        &self,
+        request: Request<api_rpc::FileDownloadRequest>,
+    ) -> Result<Response<Self::FileDownloadStream>, Status> {
+        let request = request.into_inner();
+        let sandbox = self.file_sandbox(&request.uuid).await?;
+        let path = request.path.clone();
+
+        let file = blocking(move || sandbox.open_read(&path)).await?;
+        let mut file = tokio::fs::File::from_std(file);
+        let mut offset = request.offset;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        log::info!("GRPC service starts download of {}", request.path);
+        let output = async_stream::try_stream! {
+            loop {
+                let mut data = vec![0u8; FILE_CHUNK_SIZE];
+                let n = file
+                    .read(&mut data)
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                if n == 0 {
+                    break;
+                }
+                data.truncate(n);
+                yield api_rpc::FileChunk { offset, data };
+                offset += n as u64;
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::FileDownloadStream))
+    }
+
+    async fn file_upload(
+        &self,
+        request: Request<Streaming<api_rpc::FileUploadRequest>>,
+    ) -> Result<Response<api_rpc::FileUploadResponse>, Status> {
+        let mut stream = request.into_inner();
+        let first = match stream.message().await? {
+            None => return Err(Status::invalid_argument("Empty upload")),
+            Some(first) => first,
+        };
+
+        let target = first.path.clone();
+        let result = async {
+            let sandbox = self.file_sandbox(&first.uuid).await?;
+            let path = first.path.clone();
+            let overwrite = first.overwrite;
+            let mut upload = blocking(move || sandbox.create(&path, overwrite)).await?;
+
+            let mut data = first.data;
+            loop {
+                upload = blocking(move || {
+                    use std::io::Write;
+                    upload.file().write_all(&data)?;
+                    Ok(upload)
+                })
+                .await?;
+
+                data = match stream.message().await? {
+                    None => break,
+                    Some(message) => message.data,
+                };
+            }
+
+            blocking(move || upload.commit()).await
+        }
+        .await;
+
+        Ok(Response::new(match result {
+            Ok(size) => {
+                log::info!("GRPC service uploaded {} ({} bytes)", target, size);
+                api_rpc::FileUploadResponse {
+                    ok: true,
+                    size,
+                    reason: "".into(),
+                }
+            }
+            Err(status) => api_rpc::FileUploadResponse {
+                ok: false,
+                size: 0,
+                reason: status.message().into(),
+            },
+        }))
+    }
+
+    async fn file_set_read_only(
+        &self,
+        request: Request<api_rpc::FileSetReadOnlyRequest>,
+    ) -> Result<Response<api_rpc::FileSetReadOnlyResponse>, Status> {
+        let request = request.into_inner();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => handler
+                .lock()
+                .await
+                .set_read_only(&request.uuid, request.read_only),
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::FileSetReadOnlyResponse {
+                ok: true,
+                uuid: request.uuid,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::FileSetReadOnlyResponse {
+                ok: false,
+                uuid: request.uuid,
+                reason,
+            },
+        }))
+    }
+
+    async fn file_operation(
+        &self,
+        request: Request<api_rpc::FileOperationRequest>,
+    ) -> Result<Response<Self::FileOperationStream>, Status> {
+        let request = request.into_inner();
+        let op = api_rpc::FileOp::from_i32(request.op)
+            .ok_or_else(|| Status::invalid_argument("Unknown operation"))?;
+        let conflict = api_rpc::ConflictPolicy::from_i32(request.conflict)
+            .ok_or_else(|| Status::invalid_argument("Unknown conflict policy"))?;
+        let request = FileOpRequest {
+            op: converter::file_op_from_rpc(op),
+            source: request.source_uuid,
+            paths: request.paths,
+            target: request.target_uuid,
+            target_dir: request.target_dir,
+            conflict: converter::conflict_policy_from_rpc(conflict),
+            verify: request.verify,
+        };
+
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler.lock().await.file_op(request);
+        let progress = progress.map_err(converter::file_error_to_status)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::file_op_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(Box::pin(output) as Self::FileOperationStream))
+    }
+
+    async fn backup_list(
+        &self,
+        _request: Request<api_rpc::BackupListRequest>,
+    ) -> Result<Response<api_rpc::BackupListResponse>, Status> {
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let backups = handler.lock().await.backups();
+
+        Ok(Response::new(api_rpc::BackupListResponse {
+            backups: backups
+                .iter()
+                .map(converter::backup_status_to_rpc)
+                .collect(),
+        }))
+    }
+
+    async fn backup_set(
+        &self,
+        request: Request<api_rpc::BackupSetRequest>,
+    ) -> Result<Response<api_rpc::BackupSetResponse>, Status> {
+        let backup = request
+            .into_inner()
+            .backup
+            .map(converter::backup_from_rpc)
+            .ok_or_else(|| Status::invalid_argument("No backup given"))?;
+        let name = backup.name.clone();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => handler.lock().await.set_backup(backup),
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::BackupSetResponse {
+                ok: true,
+                name,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::BackupSetResponse {
+                ok: false,
+                name,
+                reason,
+            },
+        }))
+    }
+
+    async fn backup_remove(
+        &self,
+        request: Request<api_rpc::BackupRemoveRequest>,
+    ) -> Result<Response<api_rpc::BackupRemoveResponse>, Status> {
+        let request = request.into_inner();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => handler.lock().await.remove_backup(&request.name),
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::BackupRemoveResponse {
+                ok: true,
+                name: request.name,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::BackupRemoveResponse {
+                ok: false,
+                name: request.name,
+                reason,
+            },
+        }))
+    }
+
+    async fn backup_run(
+        &self,
+        request: Request<api_rpc::BackupRunRequest>,
+    ) -> Result<Response<api_rpc::BackupRunResponse>, Status> {
+        let request = request.into_inner();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => handler.lock().await.run_backup(&request.name),
+        };
+
+        Ok(Response::new(match result {
+            Ok(job_id) => api_rpc::BackupRunResponse {
+                ok: true,
+                name: request.name,
+                job_id,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::BackupRunResponse {
+                ok: false,
+                name: request.name,
+                job_id: 0,
+                reason,
+            },
+        }))
+    }
+
+    async fn share_list(
+        &self,
+        _request: Request<api_rpc::ShareListRequest>,
+    ) -> Result<Response<api_rpc::ShareListResponse>, Status> {
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let shares = handler.lock().await.shares();
+
+        Ok(Response::new(api_rpc::ShareListResponse {
+            shares: shares
+                .iter()
+                .map(|(share, active)| converter::share_to_rpc(share, *active))
+                .collect(),
+        }))
+    }
+
+    async fn share_set(
+        &self,
+        request: Request<api_rpc::ShareSetRequest>,
+    ) -> Result<Response<api_rpc::ShareSetResponse>, Status> {
+        let share = request
+            .into_inner()
+            .share
+            .and_then(converter::share_from_rpc)
+            .ok_or_else(|| Status::invalid_argument("No valid share given"))?;
+        let uuid = share.uuid.clone();
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => handler.lock().await.set_share(share).await,
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::ShareSetResponse {
+                ok: true,
+                uuid,
+                reason: "".into(),
+            },
+            Err(reason) => api_rpc::ShareSetResponse {
+                ok: false,
+                uuid,
+                reason,
+            },
+        }))
+    }
+
+    async fn share_remove(
+        &self,
+        request: Request<api_rpc::ShareRemoveRequest>,
+    ) -> Result<Response<api_rpc::ShareRemoveResponse>, Status> {
+        let request = request.into_inner();
+        let protocol = api_rpc::ShareProtocol::from_i32(request.protocol)
+            .map(converter::share_protocol_from_rpc)
+            .ok_or_else(|| Status::invalid_argument("Unknown protocol"))?;
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => {
+                handler
+                    .lock()
+                    .await
+                    .remove_share(&request.uuid, protocol)
+                    .await
+            }
+        };
+
+        Ok(Response::new(match result {
+            Ok(()) => api_rpc::ShareRemoveResponse {
                 ok: true,
-                uuid: "123-456-7890".into(),
+                uuid: request.uuid,
                 reason: "".into(),
+            },
+            Err(reason) => api_rpc::ShareRemoveResponse {
+                ok: false,
+                uuid: request.uuid,
+                reason,
+            },
+        }))
+    }
+
+    async fn disk_image_backup(
+        &self,
+        request: Request<api_rpc::DiskImageBackupRequest>,
+    ) -> Result<Response<Self::DiskImageBackupStream>, Status> {
+        let request = request.into_inner();
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler
+            .lock()
+            .await
+            .image_backup(&request.uuid, request.skip_unused);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::image_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(
+            Box::pin(output) as Self::DiskImageBackupStream
+        ))
+    }
+
+    async fn disk_image_verify(
+        &self,
+        request: Request<api_rpc::DiskImageVerifyRequest>,
+    ) -> Result<Response<Self::DiskImageVerifyStream>, Status> {
+        let request = request.into_inner();
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler
+            .lock()
+            .await
+            .image_verify(&request.uuid, &request.path);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::image_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(
+            Box::pin(output) as Self::DiskImageVerifyStream
+        ))
+    }
+
+    async fn disk_usage_analyze(
+        &self,
+        request: Request<api_rpc::DiskUsageRequest>,
+    ) -> Result<Response<Self::DiskUsageAnalyzeStream>, Status> {
+        let uuid = request.into_inner().uuid;
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler.lock().await.analyze_usage(&uuid);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::usage_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(
+            Box::pin(output) as Self::DiskUsageAnalyzeStream
+        ))
+    }
+
+    async fn disk_usage_cached(
+        &self,
+        request: Request<api_rpc::DiskUsageRequest>,
+    ) -> Result<Response<api_rpc::DiskUsageReport>, Status> {
+        let uuid = request.into_inner().uuid;
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let usage = handler.lock().await.cached_usage(&uuid);
+        match usage {
+            Some(usage) => Ok(Response::new(converter::usage_progress_to_rpc(&usage))),
+            None => Err(Status::not_found(format!("{} was not analyzed", uuid))),
+        }
+    }
+
+    async fn duplicate_find(
+        &self,
+        request: Request<api_rpc::DuplicateFindRequest>,
+    ) -> Result<Response<Self::DuplicateFindStream>, Status> {
+        let request = request.into_inner();
+        let roots = request
+            .roots
+            .into_iter()
+            .map(converter::duplicate_file_from_rpc)
+            .collect();
+        let handler = self.disk_handler().await.map_err(Status::unavailable)?;
+        let progress = handler
+            .lock()
+            .await
+            .find_duplicates(roots, request.min_size);
+        let progress = progress.map_err(Status::failed_precondition)?;
+
+        let output = job_progress_stream(
+            progress,
+            self.shutdown.clone(),
+            converter::duplicate_progress_to_rpc,
+            |p| p.state.is_done(),
+        );
+        Ok(Response::new(Box::pin(output) as Self::DuplicateFindStream))
+    }
+
+    async fn duplicate_delete(
+        &self,
+        request: Request<api_rpc::DuplicateDeleteRequest>,
+    ) -> Result<Response<api_rpc::DuplicateDeleteResponse>, Status> {
+        let request = request.into_inner();
+        let files = request
+            .files
+            .into_iter()
+            .map(converter::duplicate_file_from_rpc)
+            .collect();
+
+        // Files are hashed again, do not hold the handler lock meanwhile.
+        let result = match self.disk_handler().await {
+            Err(e) => Err(e),
+            Ok(handler) => {
+                let delete = handler
+                    .lock()
+                    .await
+                    .delete_duplicates(request.job_id, files);
+                delete.await
+            }
+        };
+
+        Ok(Response::new(match result {
+            Ok(results) => api_rpc::DuplicateDeleteResponse {
+                ok: results.iter().all(|(_, r)| r.is_ok()),
+                freed_bytes: results.iter().filter_map(|(_, r)| r.as_ref().ok()).sum(),
+                results: results
+                    .iter()
+                    .map(|(file, result)| api_rpc::DuplicateDeleteResult {
+                        file: Some(converter::duplicate_file_to_rpc(file)),
+                        ok: result.is_ok(),
+                        reason: result.clone().err().unwrap_or_default(),
+                    })
+                    .collect(),
+                reason: String::new(),
+            },
+            Err(reason) => api_rpc::DuplicateDeleteResponse {
+                ok: false,
+                results: Vec::new(),
+                freed_bytes: 0,
+                reason,
+            },
+        }))
+    }
+
+    async fn service_health(
+        &self,
+        _request: Request<api_rpc::ServiceHealthRequest>,
+    ) -> Result<Response<api_rpc::ServiceHealthResponse>, Status> {
+        let services = self.health.list();
+        Ok(Response::new(api_rpc::ServiceHealthResponse {
+            services: services
+                .iter()
+                .map(converter::service_health_to_rpc)
+                .collect(),
+        }))
+    }
+
+    async fn refresh(
+        &self,
+        request: Request<api_rpc::RefreshRequest>,
+    ) -> Result<Response<api_rpc::RefreshResponse>, Status> {
+        let service = request.into_inner().service;
+        // Requests to a stopped service would wait until it is enabled.
+        let stopped = self
+            .health
+            .list()
+            .iter()
+            .any(|h| h.service.name() == service && h.state == HealthState::Stopped);
+        if stopped {
+            return Ok(Response::new(api_rpc::RefreshResponse {
+                ok: false,
+                sequence: 0,
+                reason: format!("Service {} is stopped", service),
             }));
         }
+        Ok(Response::new(
+            match self.refreshers.refresh(&service).await {
+                Ok(sequence) => api_rpc::RefreshResponse {
+                    ok: true,
+                    sequence,
+                    reason: String::new(),
+                },
+                Err(reason) => api_rpc::RefreshResponse {
+                    ok: false,
+                    sequence: 0,
+                    reason,
+                },
+            },
+        ))
+    }
 
-        Ok(Response::new(api_rpc::DiskMountResponse {
-            ok: false,
-            uuid: "".into(),
-            reason: "Internal error".into(),
+    async fn reload_config(
+        &self,
+        _request: Request<api_rpc::ReloadConfigRequest>,
+    ) -> Result<Response<api_rpc::ReloadConfigResponse>, Status> {
+        let reloader = self
+            .reloader
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Configuration cannot be reloaded"))?;
+        Ok(Response::new(match reloader.reload().await {
+            Ok(reload) => converter::reload_to_rpc(&reload),
+            Err(reason) => api_rpc::ReloadConfigResponse {
+                ok: false,
+                reason,
+                applied: Vec::new(),
+                restart_required: Vec::new(),
+            },
+        }))
+    }
+
+    async fn set_log_level(
+        &self,
+        request: Request<api_rpc::SetLogLevelRequest>,
+    ) -> Result<Response<api_rpc::SetLogLevelResponse>, Status> {
+        let log = self
+            .log
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Log levels cannot be changed"))?;
+        let request = request.into_inner();
+        let levels = match request.levels.as_str() {
+            "" => None,
+            levels => match levels.parse() {
+                Ok(levels) => Some(levels),
+                Err(reason) => {
+                    return Ok(Response::new(api_rpc::SetLogLevelResponse {
+                        ok: false,
+                        reason,
+                        levels: log.levels().to_string(),
+                    }))
+                }
+            },
+        };
+        let duration = match request.duration_secs {
+            0 => TEMPORARY_LEVELS_DURATION,
+            secs => Duration::from_secs(secs),
+        };
+        log.set_temporary(levels, duration);
+        Ok(Response::new(api_rpc::SetLogLevelResponse {
+            ok: true,
+            reason: String::new(),
+            levels: log.levels().to_string(),
         }))
     }
 }